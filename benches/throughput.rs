@@ -0,0 +1,77 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use nes_emu::Emulator;
+
+/*
+Throughput Benchmarks:
+
+Runs the core headlessly on a small built-in NROM cartridge that
+keeps the CPU busy in a RAM-touching loop with rendering enabled.
+    instructions - CPU::step calls (the bus/PPU still tick along)
+    frames       - whole frames, CPU + PPU + frame rendering
+
+Criterion keeps the last run in target/criterion and reports the
+change against it. To compare against a fixed point instead -
+    cargo bench --bench throughput -- --save-baseline main
+    cargo bench --bench throughput -- --baseline main
+*/
+
+const INSTRUCTIONS: u64 = 10_000;
+
+fn bench_rom() -> Vec<u8> {
+    let mut prg = vec![0xEA; 0x4000];
+    let code = [
+        0x78, 0xD8, 0xA2, 0xFF, 0x9A,       //$8000 SEI, CLD, LDX #$FF, TXS
+        0xA9, 0x80, 0x8D, 0x00, 0x20,       //$8005 LDA #$80, STA $2000 ; NMI on
+        0xA9, 0x1E, 0x8D, 0x01, 0x20,       //$800A LDA #$1E, STA $2001 ; rendering on
+        0xA2, 0x00,                         //$800F LDX #$00
+        0xBD, 0x00, 0x02,                   //$8011 loop: LDA $0200,X
+        0x69, 0x01,                         //$8014 ADC #$01
+        0x9D, 0x00, 0x02,                   //$8016 STA $0200,X
+        0xE8,                               //$8019 INX
+        0x4C, 0x11, 0x80,                   //$801A JMP loop
+    ];
+    prg[..code.len()].copy_from_slice(&code);
+    //NMI handler at $8100 is a bare RTI
+    prg[0x0100] = 0x40;
+    //vectors: NMI $8100, RESET $8000, IRQ $8000
+    prg[0x3FFA..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x00, 0x80]);
+
+    let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    raw.extend(prg);
+    raw.extend(vec![0x55; 0x2000]);
+    raw
+}
+
+fn throughput(c: &mut Criterion) {
+    let raw = bench_rom();
+    let mut emulator = Emulator::new(&raw).expect("benchmark ROM should load");
+    //the cart enables rendering and NMI straight away
+    emulator.set_ppu_warm_up(false);
+
+    let mut group = c.benchmark_group("throughput");
+
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+    group.bench_function("instructions", |b| {
+        b.iter_batched_ref(
+            || emulator.cpu.clone(),
+            |cpu| {
+                for _ in 0..INSTRUCTIONS {
+                    cpu.step();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("frames", |b| {
+        let mut emulator = Emulator::new(&raw).expect("benchmark ROM should load");
+        emulator.set_ppu_warm_up(false);
+        b.iter(|| emulator.step_frame())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,203 @@
+use nes_emu::golden::{Golden, GoldenCheck};
+use nes_emu::Emulator;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+/*
+Headless Runner:
+
+Loads a ROM, runs it for a fixed number of frames without
+opening a window, then prints a CRC32 of the final frame buffer
+along with the CPU state. Used to pin known-good output for
+regression testing. With --replay the input recording is played
+from power-on, and the frame count defaults to its length so the
+run stops where the recording does.
+
+With --test-rom the ROM is run as a test ROM reporting through
+PRG-RAM (the blargg protocol) -
+    $6000       status, $80 while running, $81 asks for a reset
+                button press, anything lower is the final result
+                (0 passed, else the failing test's code)
+    $6001-6003  $DE $B0 $61 once the status is valid
+    $6004       zero terminated ASCII message
+It runs until a result is reported, or frames (default a minute
+of emulated time) pass, then prints the message and exits with
+the result code, TIMED_OUT if the ROM never finished.
+
+With --golden the final frame is compared against the ROM's golden
+file (see golden.rs) and the run fails on a mismatch. The frame
+count defaults to the one the golden was recorded with.
+
+With --max-instr N the ROM runs for at most N CPU instructions
+(stopping early on BRK) instead of whole frames, which bounds
+malformed ROMs stuck in a loop. Nothing is drawn in this mode.
+
+Usage -
+    headless <rom.nes> [frames] [--replay FILE] [--test-rom] [--golden]
+                       [--max-instr N] [--fast-boot]
+
+--fast-boot skips the PPU's power-on warm-up (see ppu.rs).
+*/
+
+const DEFAULT_FRAMES: u32 = 60;
+const TEST_ROM_FRAMES: u32 = 60 * 60;
+//a reset request is answered after this many frames (the protocol asks for at least 100ms)
+const RESET_DELAY_FRAMES: u32 = 10;
+const TIMED_OUT: i32 = 124;
+const TEST_ROM_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+//the status byte, None until the ROM has written the signature
+fn test_rom_status(emulator: &Emulator) -> Option<u8> {
+    let signature = [emulator.peek(0x6001), emulator.peek(0x6002), emulator.peek(0x6003)];
+    (signature == TEST_ROM_SIGNATURE).then(|| emulator.peek(0x6000))
+}
+
+fn test_rom_message(emulator: &Emulator) -> String {
+    let bytes: Vec<u8> = (0x6004..0x8000)
+        .map(|addr| emulator.peek(addr))
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+//runs a test ROM to completion, returns the result code
+fn run_test_rom(emulator: &mut Emulator, frames: u32) -> Option<u8> {
+    let mut reset_countdown = None;
+    for _ in 0..frames {
+        emulator.step_frame();
+        match test_rom_status(emulator) {
+            Some(0x80) | None => (),
+            Some(0x81) => match reset_countdown {
+                None => reset_countdown = Some(RESET_DELAY_FRAMES),
+                Some(0) => {
+                    emulator.cpu.reset();
+                    reset_countdown = None;
+                },
+                Some(frames) => reset_countdown = Some(frames - 1),
+            },
+            Some(result) => return Some(result),
+        }
+    }
+    None
+}
+
+fn main() {
+    env_logger::init();
+    let mut args: Vec<String> = env::args().collect();
+    let mut flag = |name: &str| match args.iter().position(|arg| arg == name) {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    };
+    let test_rom = flag("--test-rom");
+    let golden = flag("--golden");
+    let fast_boot = flag("--fast-boot");
+    let replay = match args.iter().position(|arg| arg == "--replay") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            Some(path)
+        },
+        Some(_) => {
+            eprintln!("missing value for --replay");
+            process::exit(2);
+        },
+        None => None,
+    };
+    let max_instructions = match args.iter().position(|arg| arg == "--max-instr") {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Some(value.parse::<u64>().unwrap_or_else(|_| {
+                eprintln!("invalid instruction count '{}'", value);
+                process::exit(2);
+            }))
+        },
+        Some(_) => {
+            eprintln!("missing value for --max-instr");
+            process::exit(2);
+        },
+        None => None,
+    };
+    if args.len() < 2 {
+        eprintln!("usage: {} <rom.nes> [frames] [--replay FILE] [--test-rom] [--golden] [--max-instr N] [--fast-boot]", args[0]);
+        process::exit(2);
+    }
+    let mut emulator = Emulator::load_rom(Path::new(&args[1])).unwrap_or_else(|e| {
+        eprintln!("unable to load ROM: {}", e);
+        process::exit(1);
+    });
+    if fast_boot {
+        emulator.set_ppu_warm_up(false);
+    }
+    if let Some(path) = &replay {
+        if let Err(e) = emulator.play_file(Path::new(path)) {
+            eprintln!("unable to load replay: {}", e);
+            process::exit(1);
+        }
+    }
+    let golden_path = Golden::path(Path::new(&args[1]));
+    let golden_frames = if golden {
+        fs::read_to_string(&golden_path).ok().and_then(|text| Golden::parse(&text).ok()).map(|golden| golden.frames)
+    } else {
+        None
+    };
+    let frames = match args.get(2) {
+        Some(n) => n.parse::<u32>().unwrap_or_else(|_| {
+            eprintln!("invalid frame count '{}'", n);
+            process::exit(2);
+        }),
+        None if replay.is_some() => emulator.playback_remaining() as u32,
+        None if test_rom => TEST_ROM_FRAMES,
+        None => golden_frames.unwrap_or(DEFAULT_FRAMES),
+    };
+    if test_rom {
+        let result = run_test_rom(&mut emulator, frames);
+        println!("{}", test_rom_message(&emulator));
+        match result {
+            Some(code) => {
+                println!("result: {}", if code == 0 { "passed".to_string() } else { format!("failed (${:02X})", code) });
+                process::exit(code as i32);
+            },
+            None => {
+                println!("result: timed out after {} frames", frames);
+                process::exit(TIMED_OUT);
+            },
+        }
+    }
+    if let Some(max_instructions) = max_instructions {
+        emulator.cpu.set_brk_stop(true);
+        println!("instructions: {}", emulator.run_for(max_instructions));
+    } else {
+        for _ in 0..frames {
+            emulator.step_frame();
+        }
+        println!("frames: {}", frames);
+    }
+
+    let cpu_6502 = &emulator.cpu;
+    println!("frame_crc32: {:08x}", crc32fast::hash(emulator.frame_buffer()));
+    println!(
+        "cpu: A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X} CYC:{}",
+        cpu_6502.reg_a, cpu_6502.reg_x, cpu_6502.reg_y, cpu_6502.reg_stat,
+        cpu_6502.reg_sp, cpu_6502.reg_pc, cpu_6502.tot_cycles
+    );
+    if golden {
+        match Golden::new(frames, emulator.frame_buffer()).check(&golden_path) {
+            Ok(GoldenCheck::Matched) => println!("golden: matched"),
+            Ok(GoldenCheck::Blessed) => println!("golden: wrote {}", golden_path.display()),
+            Ok(GoldenCheck::Mismatched { expected }) => {
+                println!("golden: MISMATCH, expected frame_crc32 {:08x} after {} frames", expected.frame_crc32, expected.frames);
+                process::exit(1);
+            },
+            Err(e) => {
+                eprintln!("golden: {}", e);
+                process::exit(1);
+            },
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use nes_emu::rom::{Header, Region, Rom};
+use std::env;
+use std::fs;
+use std::process;
+
+/*
+ROM Info:
+
+Prints what a ROM's header says (format, mapper, sizes, mirroring,
+battery, trainer, region) without running it, to find out why a
+game doesn't load. For ROMs that load it also prints the CRC32 and
+MD5 identifying the game (see Rom::hash).
+
+Usage -
+    rominfo <rom.nes> [--json]
+*/
+
+fn region_name(region: Option<Region>) -> Option<&'static str> {
+    region.map(|region| match region {
+        Region::Ntsc => "NTSC",
+        Region::Pal => "PAL",
+        Region::Multi => "multi-region",
+        Region::Dendy => "Dendy",
+    })
+}
+
+//CRC32 and MD5 as lowercase hex, None for ROMs that don't load
+fn ids(raw: &[u8]) -> Option<(String, String)> {
+    Rom::new(raw).ok().map(|rom| {
+        let md5: String = rom.md5().iter().map(|byte| format!("{:02x}", byte)).collect();
+        (format!("{:08x}", rom.hash()), md5)
+    })
+}
+
+//the header and ids as one JSON object, absent values as null
+fn json(header: &Header, ids: &Option<(String, String)>) -> String {
+    let mirroring = format!("{:?}", header.mirroring).to_lowercase();
+    let submapper = header.submapper.map_or("null".to_string(), |n| n.to_string());
+    let region = region_name(header.region).map_or("null".to_string(), |name| format!("\"{}\"", name));
+    let (crc32, md5) = match ids {
+        Some((crc32, md5)) => (format!("\"{}\"", crc32), format!("\"{}\"", md5)),
+        None => ("null".to_string(), "null".to_string()),
+    };
+    format!(
+        "{{\"format\":\"{}\",\"mapper\":{},\"submapper\":{},\"prg_rom_size\":{},\"chr_rom_size\":{},\"mirroring\":\"{}\",\"battery\":{},\"trainer\":{},\"region\":{},\"crc32\":{},\"md5\":{}}}",
+        header.format, header.mapper, submapper, header.prg_rom_size, header.chr_rom_size,
+        mirroring, header.battery, header.trainer, region, crc32, md5
+    )
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: {} <rom.nes> [--json]", args[0]);
+        process::exit(2);
+    }
+    let as_json = args[2..].iter().any(|arg| arg == "--json");
+
+    let raw = fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("unable to read ROM: {}", e);
+        process::exit(1);
+    });
+    let header = Header::parse(&raw).unwrap_or_else(|e| {
+        eprintln!("unable to parse header: {}", e);
+        process::exit(1);
+    });
+    let ids = ids(&raw);
+
+    if as_json {
+        println!("{}", json(&header, &ids));
+    } else {
+        let mirroring = format!("{:?}", header.mirroring).to_lowercase();
+        println!("format: {}", header.format);
+        println!("mapper: {}", header.mapper);
+        if let Some(submapper) = header.submapper {
+            println!("submapper: {}", submapper);
+        }
+        println!("prg_rom_size: {} bytes", header.prg_rom_size);
+        println!("chr_rom_size: {} bytes", header.chr_rom_size);
+        println!("mirroring: {}", mirroring);
+        println!("battery: {}", header.battery);
+        println!("trainer: {}", header.trainer);
+        println!("region: {}", region_name(header.region).unwrap_or("unknown"));
+        if let Some((crc32, md5)) = &ids {
+            println!("crc32: {}", crc32);
+            println!("md5: {}", md5);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //NROM, one 16KB PRG and one 8KB CHR bank of zeroes, vertical mirroring with a battery
+    fn known_rom() -> Vec<u8> {
+        let mut raw = vec![0; 16 + 0x4000 + 0x2000];
+        raw[..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 1, 1, 0x03, 0x00]);
+        raw
+    }
+
+    #[test]
+    fn json_lists_every_header_field() {
+        let raw = known_rom();
+        let header = Header::parse(&raw).unwrap();
+        assert_eq!(
+            json(&header, &ids(&raw)),
+            "{\"format\":\"iNES\",\"mapper\":0,\"submapper\":null,\"prg_rom_size\":16384,\"chr_rom_size\":8192,\
+             \"mirroring\":\"vertical\",\"battery\":true,\"trainer\":false,\"region\":null,\
+             \"crc32\":\"6ebed2ee\",\"md5\":\"91ff0dac5df86e798bfef5e573536b08\"}"
+        );
+        //a truncated ROM still reports its header, without the ids
+        let header = Header::parse(&raw[..100]).unwrap();
+        assert!(json(&header, &ids(&raw[..100])).ends_with("\"crc32\":null,\"md5\":null}"));
+    }
+}
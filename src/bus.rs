@@ -1,47 +1,216 @@
 use crate::cpu::Mem;
-use crate::rom::Rom;
+use crate::rom::{Mirroring, Rom, RomError};
+use crate::mapper::{self, Mapper};
 use crate::ppu::PPU;
-use crate::input::Controller;
+use crate::input::{Controller, Expansion};
+use crate::genie::{GenieCode, GenieError};
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+#[cfg(feature = "serde")]
+use serde_big_array::BigArray;
+use std::sync::Arc;
+
+//value CPU RAM is filled with at power-on (real hardware is semi-random)
+pub const POWER_ON_RAM: u8 = 0x00;
+//high byte of $4016/$4017, left on the data bus by the read's address
+pub const CONTROLLER_OPEN_BUS: u8 = 0x40;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bus {
+    #[cfg_attr(feature = "serde", serde(with = "BigArray"))]
     cpu_vram: [u8; 0x800],
-    prg_rom: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "mapper::snapshot"))]
+    mapper: Box<dyn Mapper>,
+    //nametable mirroring the board powers up with, the header's unless overridden
+    mirroring: Mirroring,
+    /*
+    PRG-RAM:
+
+    8KB of cartridge RAM at $6000-$7FFF, battery backed on some
+    boards. Every cartridge gets it whether the board has it or
+    not, which is what test ROMs reporting results there expect.
+    Like battery RAM it keeps its contents over a power cycle.
+    */
+    #[cfg_attr(feature = "serde", serde(with = "BigArray"))]
+    prg_ram: [u8; 0x2000],
     pub ppu: PPU,
     pub port1: Controller,
     pub port2: Controller,
+    /*
+    PPU Open Bus:
+
+    The CPU-PPU data bus holds on to the last value driven onto
+    it. Reads from write-only PPU registers (and the unused low
+    bits of PPUSTATUS) return this stale value instead of 0.
+    */
+    ppu_open_bus: u8,
+    /*
+    OAM DMA:
+
+    A $4014 write suspends the CPU for 513 cycles (514 when it
+    starts on an odd cycle) while the 256 bytes are copied. The
+    stall can be switched off for legacy/debug timing.
+    */
+    pub oam_dma_stall: bool,
+    oam_dma_pending: bool,
+    /*
+    Controller Open Bus:
+
+    Reads of $4016/$4017 only drive bits 0-4 (the pad in bit 0 and
+    expansion port lines), bits 5-7 keep whatever was last on the
+    data bus, usually the $40 of the address. Some games compare
+    the whole byte, so the upper bits returned are configurable.
+    */
+    pub controller_open_bus: u8,
+    //Vs. System/multicart input lines, a frontend setting like the key maps (see input.rs)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub expansion: Expansion,
+    //active Game Genie codes, patching reads from $8000-$FFFF
+    genie_codes: Vec<GenieCode>,
+    /*
+    Power-On RAM Seed:
+
+    None clears CPU RAM to POWER_ON_RAM. A seed fills it with noise
+    instead, closer to hardware for games that (wrongly) read RAM
+    before writing it, but still the same noise on every power-on
+    with the same seed so recordings replay identically.
+    */
+    pub ram_seed: Option<u64>,
 }
 
 impl Bus {
-    pub fn new(rom: Rom) -> Self {
-        Bus {
-            cpu_vram: [0; 2048],
-            ppu: PPU::new(rom.chr_rom, rom.screen_mirroring),
-            prg_rom: rom.prg_rom,
+    //a mirroring override replaces the header's, for bad dumps with the wrong bit (mappers can still switch it)
+    pub fn new(rom: Rom, mirroring: Option<Mirroring>) -> Result<Self, RomError> {
+        let mirroring = mirroring.unwrap_or(rom.screen_mirroring);
+        let mapper = mapper::create_mapper(rom.mapper, rom.prg_rom, &rom.chr_rom, mirroring.clone())?;
+        let mut ppu = PPU::new(rom.chr_rom, rom.chr_ram, mirroring.clone());
+        ppu.set_chr_banks(mapper.chr_banks());
+        Ok(Bus {
+            cpu_vram: [POWER_ON_RAM; 2048],
+            ppu,
+            mapper,
+            mirroring,
+            prg_ram: [0; 0x2000],
             port1: Controller::new(),
             port2: Controller::new(),
+            ppu_open_bus: 0,
+            oam_dma_stall: true,
+            oam_dma_pending: false,
+            controller_open_bus: CONTROLLER_OPEN_BUS,
+            expansion: Expansion::default(),
+            genie_codes: Vec::new(),
+            ram_seed: None,
+        })
+    }
+    //clears RAM and puts every peripheral back into its power-on state
+    pub fn power_on(&mut self) {
+        match self.ram_seed {
+            Some(seed) => StdRng::seed_from_u64(seed).fill_bytes(&mut self.cpu_vram),
+            None => self.cpu_vram = [POWER_ON_RAM; 0x800],
+        }
+        self.ppu.power_on();
+        self.ppu.set_mirroring(self.mirroring.clone());
+        self.mapper.power_on();
+        self.sync_mapper();
+        self.port1.power_on();
+        self.port2.power_on();
+        self.ppu_open_bus = 0;
+        self.oam_dma_pending = false;
+    }
+    //decodes and enables a 6 or 8 letter Game Genie code
+    pub fn add_genie_code(&mut self, code: &str) -> Result<GenieCode, GenieError> {
+        let code = GenieCode::decode(code)?;
+        self.genie_codes.push(code);
+        Ok(code)
+    }
+    pub fn clear_genie_codes(&mut self) {
+        self.genie_codes.clear();
+    }
+    /*
+    Peek/Poke:
+
+    Memory access for tools (debuggers, trainers, test harnesses).
+    peek returns what the CPU would read but without side effects:
+    no PPUSTATUS clear, no VRAM address increment, no controller
+    shift. poke only reaches CPU and PRG RAM, registers and ROM have nothing
+    to store into without side effects (use the PPU's peek/poke for
+    VRAM, OAM and palette).
+    */
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM ..= RAM_MIRRORS_END => self.cpu_vram[(addr & 0x07FF) as usize],
+            PPU_REGS ..= PPU_REGS_MIRRORS_END => match addr & 0x2007 {
+                0x2002 => (self.ppu.stat & 0xE0) | (self.ppu_open_bus & 0x1F),
+                0x2004 => self.ppu.oam_read(),
+                0x2007 => self.ppu.peek_data(),
+                _ => self.ppu_open_bus,
+            },
+            0x4014 => self.ppu_open_bus,
+            0x4016 => self.controller_bits(0x4016) | self.port1.peek(),
+            0x4017 => self.controller_bits(0x4017) | self.port2.peek(),
+            EXPANSION ..= EXPANSION_END => self.expansion.read(addr),
+            PRG_RAM ..= PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize],
+            ROM ..= ROM_END => {
+                let value = self.mapper.read_prg(addr);
+                self.genie_codes.iter().fold(value, |value, code| code.apply(addr, value))
+            },
+            _ => 0,
+        }
+    }
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            RAM ..= RAM_MIRRORS_END => self.cpu_vram[(addr & 0x07FF) as usize] = data,
+            PRG_RAM ..= PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize] = data,
+            _ => (),
         }
     }
-    fn read_prg_rom(&self, mut addr: u16) -> u8 {
-        addr -= 0x8000;
-       if self.prg_rom.len() == 0x4000 && addr >= 0x4000 {
-           //mirror if needed
-           addr %= 0x4000;
-       }
-       self.prg_rom[addr as usize]
+    //$4016/$4017 bits other than the pad's: expansion lines, open bus in bits 5-7 they don't drive
+    fn controller_bits(&self, addr: u16) -> u8 {
+        (self.controller_open_bus & 0xE0 & !self.expansion.driven(addr)) | self.expansion.read(addr)
     }
+    //pushes the mapper's CHR banks and mirroring through to the PPU
+    fn sync_mapper(&mut self) {
+        self.ppu.set_chr_banks(self.mapper.chr_banks());
+        if let Some(mirroring) = self.mapper.mirroring() {
+            self.set_mirroring(mirroring);
+        }
+    }
+    //hook for mappers that update mirroring control at runtime
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.ppu.set_mirroring(mirroring);
+    }
+    //whether both consoles hold the same copy of the cartridge's PRG and CHR, as clones do until CHR-RAM is written
+    pub fn shares_rom_with(&self, other: &Bus) -> bool {
+        Arc::ptr_eq(self.mapper.prg_rom(), other.mapper.prg_rom()) && Arc::ptr_eq(&self.ppu.chr_rom, &other.ppu.chr_rom)
+    }
+    //whether an NMI is pending, without taking it
     pub fn poll_nmi_status(&self) -> bool {
         self.ppu.nmi_interrupt
     }
-    pub fn tick(&mut self, cycles: u8) {
+    //the CPU's interrupt poll, a pending NMI is taken so it is serviced once (see the PPU's NMI latch)
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.ppu.nmi_interrupt)
+    }
+    pub fn tick(&mut self, cycles: u16) {
         self.ppu.tick(cycles);
     }
+    //true once per OAM DMA transfer that should stall the CPU
+    pub fn take_oam_dma(&mut self) -> bool {
+        let pending = self.oam_dma_pending;
+        self.oam_dma_pending = false;
+        pending && self.oam_dma_stall
+    }
  }
 
 const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGS: u16 = 0x2000;
 const PPU_REGS_MIRRORS_END: u16 = 0x3FFF;
+const EXPANSION: u16 = 0x4020;
+const EXPANSION_END: u16 = 0x5FFF;
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7FFF;
 const ROM: u16 = 0x8000;
 const ROM_END: u16 = 0xFFFF;
 
@@ -54,20 +223,35 @@ impl Mem for Bus {
                 self.cpu_vram[mirror_down_addr as usize]
             }
             0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 | 0x4014 => {
-                //panic!("Attempt to read from write-only PPU address {:x}", addr);
-                0
+                //write-only registers return the stale open bus value
+                self.ppu_open_bus
             }
-            0x2002 => self.ppu.stat_read(),
-            0x2004 => self.ppu.oam_read(),
-            0x2007 => self.ppu.read(),
+            0x2002 => {
+                //only the top 3 bits are driven, the rest is open bus
+                self.ppu_open_bus = (self.ppu.stat_read() & 0xE0) | (self.ppu_open_bus & 0x1F);
+                self.ppu_open_bus
+            },
+            0x2004 => {
+                self.ppu_open_bus = self.ppu.oam_read();
+                self.ppu_open_bus
+            },
+            0x2007 => {
+                self.ppu_open_bus = self.ppu.read();
+                self.ppu_open_bus
+            },
 
             PPU_REGS..=PPU_REGS_MIRRORS_END => {
                 let mirror_down_addr = addr & 0x2007;
                 self.mem_read(mirror_down_addr)
             },
-            0x4016 => self.port1.read(),
-            0x4017 => self.port2.read(),
-            ROM ..=ROM_END => self.read_prg_rom(addr),
+            0x4016 => self.controller_bits(0x4016) | self.port1.read(),
+            0x4017 => self.controller_bits(0x4017) | self.port2.read(),
+            EXPANSION ..= EXPANSION_END => self.expansion.read(addr),
+            PRG_RAM ..= PRG_RAM_END => self.prg_ram[(addr - PRG_RAM) as usize],
+            ROM ..=ROM_END => {
+                let value = self.mapper.read_prg(addr);
+                self.genie_codes.iter().fold(value, |value, code| code.apply(addr, value))
+            },
             _ => {
                 //println!("Ignoring mem access at ${:04x?}", addr);
                 0
@@ -76,6 +260,9 @@ impl Mem for Bus {
     }
     // write byte in memory
     fn mem_write(&mut self, addr: u16, data: u8) {
+        if (PPU_REGS..=0x2007).contains(&addr) {
+            self.ppu_open_bus = data;
+        }
         match addr {
             RAM ..= RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0x07FF;
@@ -88,7 +275,7 @@ impl Mem for Bus {
                 self.ppu.mask_write(data);
             },
             0x2002 => {
-                panic!("attempt to write to PPU status register");
+                //PPUSTATUS is read-only, the write only reaches the open bus
             },
             0x2003 => {
                 self.ppu.oam_addr_write(data);
@@ -110,29 +297,36 @@ impl Mem for Bus {
                 self.mem_write(mirror_down_addr, data);
             },
             0x4014 => {
+                //the source page can be anywhere in CPU address space
                 let mut oam_dma = [0; 256];
-                let base_addr = ((data as usize) << 8) & 0x07FF;
-                for i in 0..256 {
-                    oam_dma[i] = self.cpu_vram[base_addr + i];
+                let base_addr = (data as u16) << 8;
+                for (i, byte) in oam_dma.iter_mut().enumerate() {
+                    *byte = self.mem_read(base_addr + i as u16);
                 }
                 self.ppu.write_oam_dma(&oam_dma);
+                self.oam_dma_pending = true;
             },
+            //both ports share the strobe line driven by $4016 ($4017 writes go to the APU)
             0x4016 => {
-                if data & 0x01 != 0 {
-                    self.port1.set_strobe();
-                } else {
-                    self.port1.reset_strobe();
+                for port in [&mut self.port1, &mut self.port2] {
+                    if data & 0x01 != 0 {
+                        port.set_strobe();
+                    } else {
+                        port.reset_strobe();
+                    }
                 }
             },
-            0x4017 => {
-                if data & 0x01 != 0 {
-                    self.port2.set_strobe();
-                } else {
-                    self.port2.reset_strobe();
-                }
-            }
+            PRG_RAM ..= PRG_RAM_END => {
+                self.prg_ram[(addr - PRG_RAM) as usize] = data;
+            },
             ROM ..= ROM_END => {
-                panic!("Attempt to write to Cartridge ROM space")
+                let data = if self.mapper.has_bus_conflicts() {
+                    data & self.mapper.read_prg(addr)
+                } else {
+                    data
+                };
+                self.mapper.write_prg(addr, data);
+                self.sync_mapper();
             },
             _ => {
                 //println!("Ignoring mem write-access at ${:X?}", addr);
@@ -152,4 +346,209 @@ impl Mem for Bus {
         self.mem_write(addr, lo);
         self.mem_write(addr + 1, hi);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Button, ExpansionLine};
+    use crate::mapper::tests::ines;
+
+    fn bus() -> Bus {
+        let mut bus = Bus::new(Rom::new(&ines(0, 1, 1)).unwrap(), None).unwrap();
+        bus.ppu.set_warm_up(false);
+        bus
+    }
+
+    //a buffered $2007 read through the CPU registers
+    fn read_vram(bus: &mut Bus, addr: u16) -> u8 {
+        bus.mem_write(0x2006, (addr >> 8) as u8);
+        bus.mem_write(0x2006, addr as u8);
+        bus.mem_read(0x2007);
+        bus.mem_read(0x2007)
+    }
+
+    #[test]
+    fn write_only_registers_read_back_the_open_bus() {
+        let mut bus = bus();
+        bus.mem_write(0x2000, 0x5A);
+        for addr in [0x2000, 0x2001, 0x2003, 0x2005, 0x2006, 0x2008] {
+            assert_eq!(bus.mem_read(addr), 0x5A, "${:04X}", addr);
+        }
+        //PPUSTATUS only drives the top 3 bits
+        bus.mem_write(0x2001, 0x1F);
+        assert_eq!(bus.mem_read(0x2002) & 0x1F, 0x1F);
+    }
+
+    #[test]
+    fn oam_dma_copies_a_page_starting_at_oamaddr() {
+        let mut bus = bus();
+        for i in 0..=0xFF {
+            bus.mem_write(0x0300 + i, i as u8);
+        }
+        bus.mem_write(0x2003, 0x10);
+        //$0B00 is a mirror of $0300
+        bus.mem_write(0x4014, 0x0B);
+        for i in 0..=0xFFu8 {
+            assert_eq!(bus.ppu.peek_oam(i.wrapping_add(0x10)), i);
+        }
+        assert!(bus.take_oam_dma());
+    }
+
+    #[test]
+    fn mirroring_can_be_switched_at_runtime() {
+        let mut bus = bus();
+        assert_eq!(bus.ppu.mirroring, Mirroring::HORIZONTAL);
+        bus.mem_write(0x2006, 0x20);
+        bus.mem_write(0x2006, 0x00);
+        bus.mem_write(0x2007, 0x77);
+        assert_eq!(read_vram(&mut bus, 0x2400), 0x77);
+        assert_eq!(read_vram(&mut bus, 0x2800), 0x00);
+
+        bus.set_mirroring(Mirroring::VERTICAL);
+        assert_eq!(read_vram(&mut bus, 0x2400), 0x00);
+        assert_eq!(read_vram(&mut bus, 0x2800), 0x77);
+    }
+
+    #[test]
+    fn genie_codes_patch_prg_reads() {
+        //each 8KB of PRG holds its bank number, $E000-$FFFF reads 3
+        let mut bus = Bus::new(Rom::new(&ines(0, 2, 1)).unwrap(), None).unwrap();
+        bus.add_genie_code("GOSSIP").unwrap();
+        assert_eq!(bus.mem_read(0xD1DD), 0x14);
+        assert_eq!(bus.mem_read(0xD1DE), 2);
+        //compare codes only patch when the ROM holds the compare byte
+        bus.add_genie_code("OPZTLPLE").unwrap();
+        assert_eq!(bus.mem_read(0xE123), 0x99);
+        bus.add_genie_code("ZEXPYGLA").unwrap();
+        assert_eq!(bus.mem_read(0x94A7), 0);
+        assert_eq!(bus.peek(0xE123), 0x99);
+
+        bus.clear_genie_codes();
+        assert_eq!(bus.mem_read(0xD1DD), 2);
+    }
+
+    #[test]
+    fn bus_conflicts_and_the_written_bank_with_the_rom_byte() {
+        //UxROM with four 16KB banks, each PRG byte holds its 8KB bank number
+        let mut bus = Bus::new(Rom::new(&ines(2, 4, 0)).unwrap(), None).unwrap();
+        //$C000 holds 6, bank 3 & 6 selects bank 2
+        bus.mem_write(0xC000, 3);
+        assert_eq!(bus.mem_read(0x8000), 4);
+        //$E000 holds 7, which lets bank 3 through
+        bus.mem_write(0xE000, 3);
+        assert_eq!(bus.mem_read(0x8000), 6);
+
+        //CNROM, $A000 holds 1 so CHR bank 3 & 1 is bank 1 (its first 1KB is bank 8)
+        let mut bus = Bus::new(Rom::new(&ines(3, 1, 4)).unwrap(), None).unwrap();
+        bus.mem_write(0xA000, 3);
+        assert_eq!(bus.ppu.peek_vram(0x0000), 8);
+    }
+
+    #[test]
+    fn peek_has_none_of_the_read_side_effects() {
+        let mut bus = bus();
+        while bus.ppu.stat & 0x80 == 0 {
+            bus.ppu.step_dot();
+        }
+        assert_ne!(bus.peek(0x2002) & 0x80, 0);
+        assert_ne!(bus.peek(0x2002) & 0x80, 0);
+        assert_ne!(bus.mem_read(0x2002) & 0x80, 0);
+        assert_eq!(bus.mem_read(0x2002) & 0x80, 0);
+
+        //the VRAM address only moves on a real $2007 read
+        bus.ppu.poke_vram(0x2400, 0x11);
+        bus.ppu.poke_vram(0x2401, 0x22);
+        bus.mem_write(0x2006, 0x24);
+        bus.mem_write(0x2006, 0x00);
+        bus.peek(0x2007);
+        bus.mem_read(0x2007);
+        assert_eq!(bus.peek(0x2007), 0x11);
+        assert_eq!(bus.mem_read(0x2007), 0x11);
+        assert_eq!(bus.peek(0x2007), 0x22);
+
+        //pokes land on RAM through its mirrors, the pad's next bit stays put
+        bus.poke(0x0801, 0x5A);
+        assert_eq!(bus.peek(0x0001), 0x5A);
+        bus.port1.set_button(Button::A, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.peek(0x4016) & 1, 1);
+        assert_eq!(bus.mem_read(0x4016) & 1, 1);
+        assert_eq!(bus.peek(0x4016) & 1, 0);
+    }
+
+    #[test]
+    fn mirroring_override_replaces_the_header() {
+        let rom = Rom::new(&ines(0, 1, 1)).unwrap();
+        assert_eq!(rom.screen_mirroring, Mirroring::HORIZONTAL);
+        let mut bus = Bus::new(rom, Some(Mirroring::VERTICAL)).unwrap();
+        //$2400 is its own page and $2800 repeats $2000
+        assert_eq!(bus.ppu.mirror_vram_addr(0x2400), 0x0400);
+        assert_eq!(bus.ppu.mirror_vram_addr(0x2800), 0x0000);
+        bus.power_on();
+        assert_eq!(bus.ppu.mirror_vram_addr(0x2C00), 0x0400);
+    }
+
+    #[test]
+    fn a_4016_strobe_reloads_both_controllers() {
+        let mut bus = bus();
+        bus.port1.set_button(Button::A, true);
+        bus.port2.set_button(Button::B, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        //shift both registers part way
+        assert_eq!(bus.mem_read(0x4016) & 1, 1);
+        assert_eq!(bus.mem_read(0x4017) & 1, 0);
+        assert_eq!(bus.mem_read(0x4017) & 1, 1);
+        //strobing through $4016 alone starts both over at A
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.mem_read(0x4016) & 1, 1);
+        assert_eq!(bus.mem_read(0x4017) & 1, 0);
+        assert_eq!(bus.mem_read(0x4017) & 1, 1);
+        //$4017 writes leave both shift registers where they are
+        bus.mem_write(0x4017, 1);
+        bus.mem_write(0x4017, 0);
+        assert_eq!(bus.mem_read(0x4016) & 1, 0);
+        assert_eq!(bus.mem_read(0x4017) & 1, 0);
+    }
+
+    #[test]
+    fn pad_reads_carry_the_open_bus_upper_bits() {
+        let mut bus = bus();
+        bus.port1.set_button(Button::A, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        //A pressed, then B released, over the $40 left by the address's high byte
+        assert_eq!(bus.mem_read(0x4016), 0x41);
+        assert_eq!(bus.mem_read(0x4016), 0x40);
+        assert_eq!(bus.mem_read(0x4017), 0x40);
+        //only bits 5-7 are open bus, the rest stay clear
+        bus.controller_open_bus = 0xFF;
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+        assert_eq!(bus.mem_read(0x4016), 0xE1);
+    }
+
+    #[test]
+    fn expansion_dip_switches_read_back_at_their_address() {
+        let mut bus = bus();
+        let dip = bus.expansion.map(ExpansionLine { addr: 0x4017, mask: 0x08, scancode: None, active: false });
+        let menu = bus.expansion.map(ExpansionLine { addr: 0x5000, mask: 0x01, scancode: None, active: false });
+        assert_eq!(bus.mem_read(0x4017), 0x40);
+        assert_eq!(bus.mem_read(0x5000), 0x00);
+        bus.expansion.set(dip, true);
+        bus.expansion.set(menu, true);
+        assert_eq!(bus.mem_read(0x4017), 0x48);
+        assert_eq!(bus.mem_read(0x5000), 0x01);
+        //the other port and a neighbouring address are left alone
+        assert_eq!(bus.mem_read(0x4016), 0x40);
+        assert_eq!(bus.mem_read(0x5001), 0x00);
+        //a line mapped over an open bus bit drives it low while inactive
+        let coin = bus.expansion.map(ExpansionLine { addr: 0x4016, mask: 0x40, scancode: None, active: false });
+        assert_eq!(bus.mem_read(0x4016), 0x00);
+        bus.expansion.set(coin, true);
+        assert_eq!(bus.mem_read(0x4016), 0x40);
+        assert_eq!(bus.peek(0x4016), 0x40);
+    }
+}
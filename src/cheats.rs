@@ -0,0 +1,137 @@
+use crate::cpu::CPU;
+use std::fmt;
+use thiserror::Error;
+
+/*
+Cheats:
+
+Action Replay style RAM pokes. Every active cheat writes its value
+to CPU RAM once per frame, after the frame has run, so the game
+keeps seeing the forced value (infinite lives and the like).
+
+Codes are written as a hex address and value, "AAAA:VV" or
+"AAAAVV", and must target CPU RAM ($0000-$07FF).
+*/
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CheatError {
+    #[error("invalid cheat '{0}', expected AAAA:VV")]
+    Format(String),
+    #[error("cheat address ${0:04X} is outside CPU RAM")]
+    Address(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cheat {
+    pub addr: u16,
+    pub value: u8,
+}
+
+impl Cheat {
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        let format_err = || CheatError::Format(code.to_string());
+        let digits: String = code.trim().chars().filter(|&c| c != ':').collect();
+        if digits.len() != 6 || !digits.is_ascii() {
+            return Err(format_err());
+        }
+        let addr = u16::from_str_radix(&digits[..4], 16).map_err(|_| format_err())?;
+        let value = u8::from_str_radix(&digits[4..], 16).map_err(|_| format_err())?;
+        if addr > 0x07FF {
+            return Err(CheatError::Address(addr));
+        }
+        Ok(Cheat { addr, value })
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}:{:02X}", self.addr, self.value)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats { cheats: Vec::new() }
+    }
+
+    //parses and enables a code, replacing any cheat on the same address
+    pub fn add(&mut self, code: &str) -> Result<Cheat, CheatError> {
+        let cheat = Cheat::parse(code)?;
+        self.remove(cheat.addr);
+        self.cheats.push(cheat);
+        Ok(cheat)
+    }
+
+    //returns false if no cheat was set on the address
+    pub fn remove(&mut self, addr: u16) -> bool {
+        let len = self.cheats.len();
+        self.cheats.retain(|cheat| cheat.addr != addr);
+        self.cheats.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Cheat> {
+        self.cheats.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    //forces every cheat's value into RAM, called once per frame (not a CPU write, so no watchpoint fires)
+    pub fn apply(&self, cpu: &mut CPU) {
+        for cheat in &self.cheats {
+            cpu.mem_bus.poke(cheat.addr, cheat.value);
+        }
+    }
+}
+
+//one code per line, for logging the active list
+impl fmt::Display for Cheats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for cheat in &self.cheats {
+            writeln!(f, "{}", cheat)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::cpu;
+    use crate::cpu::{Access, StepResult, WatchKind};
+
+    #[test]
+    fn a_poke_holds_its_value_after_each_frame() {
+        //INC $10, INC $20, JMP $8000
+        let mut cpu = cpu(&[0xE6, 0x10, 0xE6, 0x20, 0x4C, 0x00, 0x80]);
+        let mut cheats = Cheats::new();
+        assert_eq!(cheats.add("0010:63"), Ok(Cheat { addr: 0x0010, value: 0x63 }));
+        assert_eq!(cheats.add("0800:01"), Err(CheatError::Address(0x0800)));
+        assert_eq!(cheats.add("10:63"), Err(CheatError::Format("10:63".into())));
+        //cheats apply directly, the watchpoint only sees the program's own writes
+        cpu.add_watchpoint(0x0010, WatchKind::Write);
+        cheats.apply(&mut cpu);
+        assert_eq!(cpu.mem_bus.peek(0x0010), 0x63);
+        assert_eq!(cpu.step(), StepResult::Watchpoint(0x0010, Access::Write));
+        assert_eq!(cpu.mem_bus.peek(0x0010), 0x64);
+        cpu.remove_watchpoint(0x0010);
+
+        cpu.run_for(1000);
+        cheats.apply(&mut cpu);
+        assert_eq!(cpu.mem_bus.peek(0x0010), 0x63);
+        assert_ne!(cpu.mem_bus.peek(0x0020), 0);
+        assert!(cheats.remove(0x0010));
+        assert!(cheats.is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::demo::DemoProgram;
+use crate::input::ExpansionLine;
+use crate::palette::PaletteParams;
+use crate::rom::Mirroring;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/*
+Config:
+
+Frontend settings taken from the command line.
+
+Options -
+    --scale N           window size as a multiple of 256x240 (1-6, default 3)
+    --palette FILE      custom .pal colours instead of the built-in palette
+    --overscan          hide the top and bottom 8 scanlines like a TV
+    --cheat AAAA:VV     poke a RAM value every frame (repeatable)
+    --frameskip N       only draw every Nth frame on slow machines (default 1)
+    --ram-seed N        power up with seeded noise in RAM instead of zeros
+    --replay FILE       play an input recording (or FCEUX .fm2 movie) from
+                        power-on, then hand the controller back
+    --vsync             pace frames by the display refresh instead of a timer
+    --profiles DIR      where per-game profiles are looked up (default
+                        profiles), see profile.rs
+    --mirroring MODE    ignore the header's nametable mirroring for bad
+                        dumps, MODE is vertical, horizontal or four
+    --demo NAME         run a built-in easy6502 program (snake or bmp)
+                        instead of a ROM, see demo.rs
+    --fast-boot         skip the PPU's power-on warm-up, register writes
+                        take effect from the first frame
+    --ntsc-palette H,S,B,C
+                        generate the palette from hue (degrees), saturation,
+                        brightness and contrast (0,1,0,1 is close to the
+                        built-in one), overrides --palette, see palette.rs
+    --expansion AAAA:B=KEY
+                        map bit B (0-7) of $AAAA ($4016, $4017 or $4020-$5FFF)
+                        to a key scancode, or to "on" for a set DIP switch
+                        (repeatable), see input.rs
+*/
+
+pub const DEFAULT_SCALE: u32 = 3;
+//6x (1536x1440) is about the largest that fits a common 1440p display
+pub const MAX_SCALE: u32 = 6;
+pub const DEFAULT_PROFILE_DIR: &str = "profiles";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ConfigError {
+    #[error("missing value for {0}")]
+    MissingValue(String),
+    #[error("invalid scale '{0}', expected 1-{MAX_SCALE}")]
+    InvalidScale(String),
+    #[error("invalid frameskip '{0}', expected a number of frames from 1")]
+    InvalidFrameskip(String),
+    #[error("invalid RAM seed '{0}', expected a number")]
+    InvalidSeed(String),
+    #[error("invalid mirroring '{0}', expected vertical, horizontal or four")]
+    InvalidMirroring(String),
+    #[error("unknown demo '{0}', expected snake or bmp")]
+    InvalidDemo(String),
+    #[error("invalid palette parameters '{0}', expected hue,saturation,brightness,contrast")]
+    InvalidPaletteParams(String),
+    #[error("invalid expansion line '{0}', expected AAAA:B=KEY with AAAA 4016, 4017 or 4020-5FFF")]
+    InvalidExpansion(String),
+    #[error("unknown option '{0}'")]
+    UnknownOption(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub scale: u32,
+    pub palette: Option<PathBuf>,
+    pub overscan: bool,
+    pub cheats: Vec<String>,
+    pub frameskip: u32,
+    pub ram_seed: Option<u64>,
+    pub replay: Option<PathBuf>,
+    pub vsync: bool,
+    pub profile_dir: PathBuf,
+    pub mirroring: Option<Mirroring>,
+    pub demo: Option<DemoProgram>,
+    pub fast_boot: bool,
+    pub ntsc_palette: Option<PaletteParams>,
+    pub expansion: Vec<ExpansionLine>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            scale: DEFAULT_SCALE,
+            palette: None,
+            overscan: false,
+            cheats: Vec::new(),
+            frameskip: 1,
+            ram_seed: None,
+            replay: None,
+            vsync: false,
+            profile_dir: PathBuf::from(DEFAULT_PROFILE_DIR),
+            mirroring: None,
+            demo: None,
+            fast_boot: false,
+            ntsc_palette: None,
+            expansion: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    //parses the arguments following the program name
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| ConfigError::MissingValue(arg.clone()));
+            match arg.as_str() {
+                "--scale" => {
+                    let value = value()?;
+                    config.scale = match value.parse::<u32>() {
+                        Ok(scale) if (1..=MAX_SCALE).contains(&scale) => scale,
+                        _ => return Err(ConfigError::InvalidScale(value)),
+                    };
+                },
+                "--palette" => config.palette = Some(PathBuf::from(value()?)),
+                "--overscan" => config.overscan = true,
+                "--cheat" => config.cheats.push(value()?),
+                "--frameskip" => {
+                    let value = value()?;
+                    config.frameskip = match value.parse::<u32>() {
+                        Ok(frames) if frames >= 1 => frames,
+                        _ => return Err(ConfigError::InvalidFrameskip(value)),
+                    };
+                },
+                "--ram-seed" => {
+                    let value = value()?;
+                    config.ram_seed = Some(value.parse::<u64>().map_err(|_| ConfigError::InvalidSeed(value))?);
+                },
+                "--replay" => config.replay = Some(PathBuf::from(value()?)),
+                "--vsync" => config.vsync = true,
+                "--fast-boot" => config.fast_boot = true,
+                "--profiles" => config.profile_dir = PathBuf::from(value()?),
+                "--mirroring" => {
+                    let value = value()?;
+                    config.mirroring = Some(parse_mirroring(&value).ok_or(ConfigError::InvalidMirroring(value))?);
+                },
+                "--demo" => {
+                    let value = value()?;
+                    config.demo = Some(match value.to_ascii_lowercase().as_str() {
+                        "snake" => DemoProgram::Snake,
+                        "bmp" => DemoProgram::Bmp,
+                        _ => return Err(ConfigError::InvalidDemo(value)),
+                    });
+                },
+                "--ntsc-palette" => {
+                    let value = value()?;
+                    let params: Vec<f32> = value.split(',').map(|param| param.trim().parse::<f32>()).collect::<Result<_, _>>()
+                        .map_err(|_| ConfigError::InvalidPaletteParams(value.clone()))?;
+                    config.ntsc_palette = match params[..] {
+                        [hue, saturation, brightness, contrast] => Some(PaletteParams { hue, saturation, brightness, contrast }),
+                        _ => return Err(ConfigError::InvalidPaletteParams(value)),
+                    };
+                },
+                "--expansion" => {
+                    let value = value()?;
+                    let line = parse_expansion(&value).ok_or(ConfigError::InvalidExpansion(value))?;
+                    config.expansion.push(line);
+                },
+                _ => return Err(ConfigError::UnknownOption(arg)),
+            }
+        }
+        Ok(config)
+    }
+
+    //inner size of the window in physical pixels
+    pub fn window_size(&self) -> (u32, u32) {
+        (256 * self.scale, 240 * self.scale)
+    }
+}
+
+//vertical, horizontal or four, see --mirroring (per-game profiles take the same names)
+pub fn parse_mirroring(value: &str) -> Option<Mirroring> {
+    match value.to_ascii_lowercase().as_str() {
+        "vertical" => Some(Mirroring::VERTICAL),
+        "horizontal" => Some(Mirroring::HORIZONTAL),
+        "four" => Some(Mirroring::FOUR_SCREEN),
+        _ => None,
+    }
+}
+
+//AAAA:B=KEY, see --expansion
+fn parse_expansion(value: &str) -> Option<ExpansionLine> {
+    let (addr, rest) = value.split_once(':')?;
+    let (bit, key) = rest.split_once('=')?;
+    let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16).ok()?;
+    if !matches!(addr, 0x4016 | 0x4017 | 0x4020..=0x5FFF) {
+        return None;
+    }
+    let bit = bit.parse::<u8>().ok().filter(|&bit| bit < 8)?;
+    let (scancode, active) = match key {
+        "on" => (None, true),
+        _ => (Some(key.parse::<u32>().ok()?), false),
+    };
+    Some(ExpansionLine { addr, mask: 1 << bit, scancode, active })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Config, ConfigError> {
+        Config::from_args(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn scale_sets_the_window_size() {
+        assert_eq!(args("").unwrap().window_size(), (768, 720));
+        assert_eq!(args("--scale 4").unwrap().window_size(), (1024, 960));
+        assert_eq!(args("--scale 0").unwrap_err(), ConfigError::InvalidScale("0".into()));
+        assert_eq!(args("--scale 7").unwrap_err(), ConfigError::InvalidScale("7".into()));
+        assert_eq!(args("--scale").unwrap_err(), ConfigError::MissingValue("--scale".into()));
+    }
+
+    #[test]
+    fn mirroring_takes_a_mode_name() {
+        assert_eq!(args("--mirroring Vertical").unwrap().mirroring, Some(Mirroring::VERTICAL));
+        assert_eq!(args("--mirroring four").unwrap().mirroring, Some(Mirroring::FOUR_SCREEN));
+        assert_eq!(args("--mirroring single").unwrap_err(), ConfigError::InvalidMirroring("single".into()));
+    }
+}
@@ -1,16 +1,29 @@
 pub mod opcodes;
+pub mod test;
 use crate::bus::Bus;
+use log::{trace, warn};
+use std::collections::HashSet;
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CPU {
     // dev flags
-    debug: bool,
+    pub log_level: LogLevel,
     brk_stop: bool,
+    //panic on unknown opcodes instead of skipping them as NOPs
+    pub strict_opcodes: bool,
 
-    pub nmi_flag: bool,
+    // debugger state
+    breakpoints: HashSet<u16>,
+    read_watchpoints: HashSet<u16>,
+    write_watchpoints: HashSet<u16>,
+    watch_hit: Option<(u16, Access)>,
+    halted_at: Option<u16>,
 
     //cycle counter
     cycles: u8,
+    //cycles of the current instruction already clocked into the bus
+    bus_cycles: u16,
     pub tot_cycles: u32,
 
     // registers
@@ -25,6 +38,23 @@ pub struct CPU {
     pub mem_bus: Bus,
 }
 
+/*
+Log Level:
+
+How much of the CPU's execution is traced through the `log` crate
+(at trace level, so the logger still has to let it through).
+    Off   - nothing
+    Trace - one line per instruction (PC, opcode, registers, cycles)
+    Instr - the trace line plus a detailed dump from each instruction
+*/
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LogLevel {
+    Off,
+    Trace,
+    Instr,
+}
+
 #[derive(Debug, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum AddressingMode {
@@ -41,6 +71,33 @@ pub enum AddressingMode {
     NoneAddressing,
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+//outcome of a single debugger step
+#[derive(Debug, PartialEq)]
+pub enum StepResult {
+    //instruction executed normally
+    Completed,
+    //halted before executing the instruction at this PC
+    Breakpoint(u16),
+    //instruction executed and accessed a watched address
+    Watchpoint(u16, Access),
+    //halted before executing a BRK at this PC (stop on BRK armed)
+    Brk(u16),
+}
+
 pub trait Mem {
     fn mem_read(&mut self, addr: u16) -> u8;
 
@@ -51,33 +108,57 @@ pub trait Mem {
     fn mem_write16(&mut self, pos: u16, data: u16);
 }
 
+/*
+Bus Timing:
+
+Every CPU memory access takes one cycle, so the bus (and the PPU
+with it) is clocked after each access instead of in bulk once the
+instruction is done. Reads of PPU registers mid-instruction then
+see the PPU at the right dot. Only the instruction's cycle budget
+is clocked this way, the remainder (internal cycles, page crossing
+penalties, DMA) is clocked when the instruction completes.
+*/
 impl Mem for CPU {
     // read from memory address
     fn mem_read(&mut self, addr: u16) -> u8 {
-        self.mem_bus.mem_read(addr)
+        self.check_watchpoint(addr, Access::Read);
+        let val = self.mem_bus.mem_read(addr);
+        self.bus_cycle();
+        val
     }
     // reads as little endian, returns big endian
     fn mem_read16(&mut self, addr: u16) -> u16 {
-        self.mem_bus.mem_read16(addr)
+        let lo = self.mem_read(addr) as u16;
+        let hi = self.mem_read(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
     }
 
     // write to memory address
     fn mem_write(&mut self, addr: u16, val: u8) {
+        self.check_watchpoint(addr, Access::Write);
         self.mem_bus.mem_write(addr, val);
+        self.bus_cycle();
     }
     // reads value as big endian, writes as little endian
     fn mem_write16(&mut self, addr: u16, val: u16) {
-        self.mem_bus.mem_write16(addr, val);
+        self.mem_write(addr, (val >> 8) as u8);
+        self.mem_write(addr.wrapping_add(1), (val & 0x00FF) as u8);
     }
 }
 
 impl CPU {
     pub fn new(mem_bus: Bus) -> Self {
         CPU {
-            debug: false,
+            log_level: LogLevel::Off,
             brk_stop: false,
-            nmi_flag: false,
+            strict_opcodes: false,
+            breakpoints: HashSet::new(),
+            read_watchpoints: HashSet::new(),
+            write_watchpoints: HashSet::new(),
+            watch_hit: None,
+            halted_at: None,
             cycles: 0,
+            bus_cycles: 0,
             tot_cycles: 0,
             reg_a: 0,       //accumulator
             reg_x: 0,       //X register
@@ -111,46 +192,144 @@ impl CPU {
     }
 
     //endian-ness conversion
-    fn change_endian(&self, val: u16) -> u16 {
-        let hi = val & 0x00FF;
-        let lo = val >> 8;
-        (hi << 8) | lo
+    //clocks one CPU cycle into the bus, if the current instruction has any left
+    fn bus_cycle(&mut self) {
+        if self.bus_cycles < self.cycles as u16 {
+            self.mem_bus.tick(3);
+            self.bus_cycles += 1;
+        }
     }
 
+    /*
+    Stack:
+
+    The stack lives in page 1 ($0100-$01FF). SP is only 8 bits, so
+    it wraps from $00 to $FF (and back) without ever leaving the
+    page. 16-bit values go on high byte first, so they sit in memory
+    little endian like everything else and pull back in one piece.
+    */
     // push byte onto stack
     fn stack_push(&mut self, val: u8) {
         self.mem_write(0x0100 + self.reg_sp as u16, val);
-        if self.reg_sp == 0x00 {
-            self.reg_sp = 0xFF;
-        } else {
-            self.reg_sp -= 1;
-        }
+        self.reg_sp = self.reg_sp.wrapping_sub(1);
     }
     // push two bytes onto stack
     fn stack_push16(&mut self, val: u16) {
-        let lo = (val >> 8) as u8;
-        let hi = (val & 0x00FF) as u8;
-        self.stack_push(lo);
+        let hi = (val >> 8) as u8;
+        let lo = (val & 0x00FF) as u8;
         self.stack_push(hi);
+        self.stack_push(lo);
     }
 
     // pull byte from stack
     fn stack_pull(&mut self) -> u8 {
-        if self.reg_sp == 0xFF {
-            self.reg_sp = 0x00;
-        } else {
-            self.reg_sp += 1;
-        }
-        
+        self.reg_sp = self.reg_sp.wrapping_add(1);
         self.mem_read(0x0100 + self.reg_sp as u16)
     }
     fn stack_pull16(&mut self) -> u16 {
-        let hi = self.stack_pull() as u16;
         let lo = self.stack_pull() as u16;
+        let hi = self.stack_pull() as u16;
 
         (hi << 8) | lo
     }
 
+    //Debugger Hooks
+    //arms/disarms halting before the next BRK, it disarms itself once hit
+    pub fn set_brk_stop(&mut self, enabled: bool) {
+        self.brk_stop = enabled;
+    }
+    pub fn brk_stop(&self) -> bool {
+        self.brk_stop
+    }
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+    pub fn add_watchpoint(&mut self, addr: u16, kind: WatchKind) {
+        if kind != WatchKind::Write {
+            self.read_watchpoints.insert(addr);
+        }
+        if kind != WatchKind::Read {
+            self.write_watchpoints.insert(addr);
+        }
+    }
+    pub fn remove_watchpoint(&mut self, addr: u16) {
+        self.read_watchpoints.remove(&addr);
+        self.write_watchpoints.remove(&addr);
+    }
+    #[inline(always)]
+    fn check_watchpoint(&mut self, addr: u16, access: Access) {
+        let watched = match access {
+            Access::Read => &self.read_watchpoints,
+            Access::Write => &self.write_watchpoints,
+        };
+        if !watched.is_empty() && watched.contains(&addr) {
+            self.watch_hit = Some((addr, access));
+        }
+    }
+
+    /*
+    Executes one instruction unless the PC sits on a breakpoint.
+    After halting on a breakpoint, the next call executes the
+    instruction so the driver can resume past it. With stop on BRK
+    armed it also halts in front of a BRK, disarming so the BRK
+    runs when the driver resumes.
+    */
+    pub fn step(&mut self) -> StepResult {
+        if self.brk_stop && self.mem_bus.peek(self.reg_pc) == 0x00 {
+            self.brk_stop = false;
+            return StepResult::Brk(self.reg_pc);
+        }
+        if self.halted_at != Some(self.reg_pc) && self.breakpoints.contains(&self.reg_pc) {
+            self.halted_at = Some(self.reg_pc);
+            return StepResult::Breakpoint(self.reg_pc);
+        }
+        self.halted_at = None;
+        self.interpret();
+        match self.watch_hit.take() {
+            Some((addr, access)) => StepResult::Watchpoint(addr, access),
+            None => StepResult::Completed,
+        }
+    }
+
+    /*
+    Runs at most `max_instructions` instructions and returns how
+    many were executed. Stops early, without executing it, in front
+    of a breakpoint or (with stop on BRK armed) a BRK, and right
+    after an instruction that hits a watchpoint. Bounds runaway
+    code in fuzzing and CI runs.
+    */
+    pub fn run_for(&mut self, max_instructions: u64) -> u64 {
+        let mut executed = 0;
+        while executed < max_instructions {
+            match self.step() {
+                StepResult::Completed => executed += 1,
+                StepResult::Watchpoint(..) => return executed + 1,
+                StepResult::Breakpoint(_) | StepResult::Brk(_) => break,
+            }
+        }
+        executed
+    }
+
+    /*
+    Power On:
+
+    Puts the whole machine (RAM, PPU, controllers) into its
+    documented power-on state and then runs the reset sequence.
+    Both the initial boot and a power cycle go through here so a
+    given ROM always starts from the same state.
+    */
+    pub fn power_on(&mut self) {
+        self.mem_bus.power_on();
+        self.cycles = 0;
+        self.bus_cycles = 0;
+        self.halted_at = None;
+        self.watch_hit = None;
+        self.reset();
+    }
+
     //Reset CPU Registers
     pub fn reset(&mut self) {
         self.reg_a = 0;
@@ -169,146 +348,153 @@ impl CPU {
 
     //Load Accumulator
     fn lda(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("LDA: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("LDA: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let param = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", param);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", param);
         }
         self.reg_a = param; //load accumulator with instr param
 
         self.update_nz(self.reg_a);
         
-        if self.debug {
-            println!("\tAccumulator = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator = {:x?}", self.reg_a);
         }
-        if self.debug {
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Load X Register
     fn ldx(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("LDX: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("LDX: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let param = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", param);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", param);
         }
         self.reg_x = param; //load x register with instr param
 
         self.update_nz(self.reg_x);
-        if self.debug {
-            println!("\tX Register = {:x?}", self.reg_x);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tX Register = {:x?}", self.reg_x);
         }
-        if self.debug {
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Load Y Register
     fn ldy(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("LDY: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("LDY: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let param = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", param);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", param);
         }
         self.reg_y = param; //load x register with instr param
 
         self.update_nz(self.reg_y);
-        if self.debug {
-            println!("\tY Register = {:x?}", self.reg_y);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tY Register = {:x?}", self.reg_y);
         }
-        if self.debug {
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
     //Transfer Acc to X Register
     fn tax(&mut self) {
-        if self.debug {
-            println!("TAX:");
-            println!("\tAccumulator = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("TAX:");
+            trace!("\tAccumulator = {:x?}", self.reg_a);
         }
         self.reg_x = self.reg_a;
 
         self.update_nz(self.reg_x);
-        if self.debug {
-            println!("\tX Register = {:x?}", self.reg_x);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tX Register = {:x?}", self.reg_x);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Transfer Acc to Y Register
     fn tay(&mut self) {
-        if self.debug {
-            println!("TAY:");
-            println!("\tAccumulator = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("TAY:");
+            trace!("\tAccumulator = {:x?}", self.reg_a);
         }
         self.reg_y = self.reg_a;
 
         self.update_nz(self.reg_y);
-        if self.debug {
-            println!("\tY Register = {:x?}", self.reg_y);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tY Register = {:x?}", self.reg_y);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Transfer X Register to Acc
     fn txa(&mut self) {
-        if self.debug {
-            println!("TXA:");
-            println!("\tX Register = {:x?}", self.reg_x);
+        if self.log_level >= LogLevel::Instr {
+            trace!("TXA:");
+            trace!("\tX Register = {:x?}", self.reg_x);
         }
         self.reg_a = self.reg_x;
 
         self.update_nz(self.reg_a);
-        if self.debug {
-            println!("\tAccumulator = {:x?}", self.reg_a);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator = {:x?}", self.reg_a);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Transfer Y Register to Acc
     fn tya(&mut self) {
-        if self.debug {
-            println!("TXA:");
-            println!("\tY Register = {:x?}", self.reg_y);
+        if self.log_level >= LogLevel::Instr {
+            trace!("TXA:");
+            trace!("\tY Register = {:x?}", self.reg_y);
         }
         self.reg_a = self.reg_y;
 
         self.update_nz(self.reg_a);
-        if self.debug {
-            println!("\tAccumulator = {:x?}", self.reg_a);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator = {:x?}", self.reg_a);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
+    /*
+    Decimal Mode:
+
+    The 2A03 is a 6502 with the BCD circuitry cut out. D can still be
+    set, cleared and pushed/pulled with the rest of P, but ADC/SBC
+    always do binary arithmetic and nothing else looks at it.
+    */
     //Add with Carry
     fn adc(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("ADC: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("ADC: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tAccumulator(old) = {:x?}", self.reg_a);
-            println!("\tStatus(old) = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tAccumulator(old) = {:x?}", self.reg_a);
+            trace!("\tStatus(old) = {:x?}", self.reg_stat);
         }
         let cin = self.reg_stat & 0x01;
         let res = u16::from(self.reg_a) + u16::from(val) + u16::from(cin);
@@ -327,24 +513,24 @@ impl CPU {
 
         self.reg_a = res8;
         self.update_nz(self.reg_a);
-        if self.debug {
-            println!("\tAccumulator(new) = {:x?}", self.reg_a);
-            println!("\tStatus(new) = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+            trace!("\tStatus(new) = {:x?}", self.reg_stat);
         }
     }
     //Subtract with Carry
     fn sbc(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("SBC: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("SBC: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tAccumulator(old) = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tAccumulator(old) = {:x?}", self.reg_a);
         }
         let cin = self.reg_stat & 0x01;
         let res = self.reg_a as u16 + !val as u16 + cin as u16;
@@ -363,113 +549,184 @@ impl CPU {
 
         self.reg_a = res8;
         self.update_nz(self.reg_a);
-        if self.debug {
-            println!("\tAccumulator(new) = {:x?}", self.reg_a);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
     //Logical AND
     fn and(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("AND: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("AND: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tAccumulator(old) = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tAccumulator(old) = {:x?}", self.reg_a);
         }
 
         self.reg_a &= val;
 
         self.update_nz(self.reg_a);
-        if self.debug {
-            println!("\tAccumulator(new) = {:x?}", self.reg_a);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Logical OR
     fn ora(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("ORA: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("ORA: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tAccumulator(old) = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tAccumulator(old) = {:x?}", self.reg_a);
         }
 
         self.reg_a |= val;
 
         self.update_nz(self.reg_a);
-        if self.debug {
-            println!("\tAccumulator(new) = {:x?}", self.reg_a);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Exclusive OR
     fn eor(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("EOR: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("EOR: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tAccumulator(old) = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tAccumulator(old) = {:x?}", self.reg_a);
         }
 
         self.reg_a = (self.reg_a | val) & !(self.reg_a & val);
 
         self.update_nz(self.reg_a);
-        if self.debug {
-            println!("\tAccumulator(new) = {:x?}", self.reg_a);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
     fn sax(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("SAX: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("SAX: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
-        if self.debug {
-            println!("\tX Register = {:x?}", self.reg_x);
-            println!("\tAccumulator = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tX Register = {:x?}", self.reg_x);
+            trace!("\tAccumulator = {:x?}", self.reg_a);
         }
 
         let val = self.reg_a & self.reg_x;
 
         self.mem_write(addr, val);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tStatus = {:x?}", self.reg_stat);
+        }
+    }
+
+    //AND then copy N into C
+    fn anc(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        if self.log_level >= LogLevel::Instr {
+            trace!("ANC: ");
+        }
+        if self.reg_stat & 0x80 != 0 {
+            self.reg_stat |= 0x01;
+        } else {
+            self.reg_stat &= 0xFE;
+        }
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tStatus = {:x?}", self.reg_stat);
+        }
+    }
+    //AND then LSR the accumulator
+    fn alr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.lsr(&AddressingMode::NoneAddressing);
+    }
+    //AND then ROR the accumulator, with C and V taken from bits 6 and 5 of the result
+    fn arr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        if self.log_level >= LogLevel::Instr {
+            trace!("ARR: ");
+        }
+        self.reg_a = (self.reg_a >> 1) | ((self.reg_stat & 0x01) << 7);
+        self.update_nz(self.reg_a);
+
+        if self.reg_a & 0x40 != 0 {
+            self.reg_stat |= 0x01;
+        } else {
+            self.reg_stat &= 0xFE;
+        }
+        if ((self.reg_a >> 6) ^ (self.reg_a >> 5)) & 0x01 != 0 {
+            self.reg_stat |= 0x40;
+        } else {
+            self.reg_stat &= 0xBF;
+        }
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+            trace!("\tStatus = {:x?}", self.reg_stat);
+        }
+    }
+    //X = (A AND X) - operand, setting C like CMP
+    fn axs(&mut self, mode: &AddressingMode) {
+        if self.log_level >= LogLevel::Instr {
+            trace!("AXS: ");
+        }
+        let addr = self.get_op_addr(mode);
+        let val = self.mem_read(addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tX Register(old) = {:x?}", self.reg_x);
+            trace!("\tAccumulator = {:x?}", self.reg_a);
+        }
+        let and = self.reg_a & self.reg_x;
+        if and >= val {
+            self.reg_stat |= 0x01;
+        } else {
+            self.reg_stat &= 0xFE;
+        }
+        self.reg_x = and.wrapping_sub(val);
+
+        self.update_nz(self.reg_x);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tX Register(new) = {:x?}", self.reg_x);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
     //Arithmetic Shift Left
     fn asl(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("ASL:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("ASL:");
         }
         match mode {
             AddressingMode::NoneAddressing => {
-                if self.debug {
-                    println!("\tAccumulator(old) = {:x?}", self.reg_a);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tAccumulator(old) = {:x?}", self.reg_a);
                 }
                 if self.reg_a & 0x80 != 0 {
                     self.reg_stat |= 0x01;
@@ -479,19 +736,19 @@ impl CPU {
                 self.reg_a <<= 1;
 
                 self.update_nz(self.reg_a);
-                if self.debug {
-                    println!("\tAccumulator(new) = {:x?}", self.reg_a);
-                    println!("\tStatus = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+                    trace!("\tStatus = {:x?}", self.reg_stat);
                 }
             }
             _ => {
                 let addr = self.get_op_addr(mode);
-                if self.debug {
-                    println!("\tMem Address = {:x?}", addr);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tMem Address = {:x?}", addr);
                 }
                 let val = self.mem_read(addr);
-                if self.debug {
-                    println!("\tData(old) = {:x?}", val);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tData(old) = {:x?}", val);
                 }
 
                 if val & 0x80 != 0 {
@@ -502,22 +759,22 @@ impl CPU {
                 self.mem_write(addr, val << 1);
 
                 self.update_nz(val << 1);
-                if self.debug {
-                    println!("\tData(new) = {:x?}", self.mem_read(addr));
-                    println!("\tStatus = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tData(new) = {:x?}", self.mem_read(addr));
+                    trace!("\tStatus = {:x?}", self.reg_stat);
                 }
             }
         }
     }
     //Logical Shift Right
     fn lsr(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("LSR:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("LSR:");
         }
         match mode {
             AddressingMode::NoneAddressing => {
-                if self.debug {
-                    println!("\tAccumulator(old) = {:x?}", self.reg_a);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tAccumulator(old) = {:x?}", self.reg_a);
                 }
                 if self.reg_a & 0x01 != 0 {
                     self.reg_stat |= 0x01;
@@ -527,19 +784,19 @@ impl CPU {
                 self.reg_a >>= 1;
 
                 self.update_nz(self.reg_a);
-                if self.debug {
-                    println!("\tAccumulator(new) = {:x?}", self.reg_a);
-                    println!("\tStatus = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+                    trace!("\tStatus = {:x?}", self.reg_stat);
                 }
             }
             _ => {
                 let addr = self.get_op_addr(mode);
-                if self.debug {
-                    println!("\tMem Address = {:x?}", addr);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tMem Address = {:x?}", addr);
                 }
                 let val = self.mem_read(addr);
-                if self.debug {
-                    println!("\tData(old) = {:x?}", val);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tData(old) = {:x?}", val);
                 }
 
                 if val & 0x01 != 0 {
@@ -550,23 +807,23 @@ impl CPU {
                 self.mem_write(addr, val >> 1);
 
                 self.update_nz(val >> 1);
-                if self.debug {
-                    println!("\tData(new) = {:x?}", self.mem_read(addr));
-                    println!("\tStatus = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tData(new) = {:x?}", self.mem_read(addr));
+                    trace!("\tStatus = {:x?}", self.reg_stat);
                 }
             }
         }
     }
     //Rotate Left
     fn rol(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("ROL:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("ROL:");
         }
         match mode {
             AddressingMode::NoneAddressing => {
-                if self.debug {
-                    println!("\tAccumulator(old) = {:x?}", self.reg_a);
-                    println!("\tStatus(old) = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tAccumulator(old) = {:x?}", self.reg_a);
+                    trace!("\tStatus(old) = {:x?}", self.reg_stat);
                 }
                 let mut oldcarry: u8 = 0;
                 if self.reg_stat & 0x01 != 0 {
@@ -582,20 +839,20 @@ impl CPU {
 
                 self.update_nz(self.reg_a);
                 
-                if self.debug {
-                    println!("\tAccumulator(new) = {:x?}", self.reg_a);
-                    println!("\tStatus(new) = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+                    trace!("\tStatus(new) = {:x?}", self.reg_stat);
                 }
             }
             _ => {
                 let addr = self.get_op_addr(mode);
-                if self.debug {
-                    println!("\tMem Address = {:x?}", addr);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tMem Address = {:x?}", addr);
                 }
                 let val = self.mem_read(addr);
-                if self.debug {
-                    println!("\tData(old) = {:x?}", val);
-                    println!("\tStatus(old) = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tData(old) = {:x?}", val);
+                    trace!("\tStatus(old) = {:x?}", self.reg_stat);
                 }
 
                 let mut oldcarry: u8 = 0;
@@ -610,23 +867,23 @@ impl CPU {
                 self.mem_write(addr, (val << 1) | oldcarry);
 
                 self.update_nz((val << 1) | oldcarry);
-                if self.debug {
-                    println!("\tData(new) = {:x?}", self.mem_read(addr));
-                    println!("\tStatus = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tData(new) = {:x?}", self.mem_read(addr));
+                    trace!("\tStatus = {:x?}", self.reg_stat);
                 }
             }
         }
     }
     //Rotate Right
     fn ror(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("ROR:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("ROR:");
         }
         match mode {
             AddressingMode::NoneAddressing => {
-                if self.debug {
-                    println!("\tAccumulator(old) = {:x?}", self.reg_a);
-                    println!("\tStatus(old) = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tAccumulator(old) = {:x?}", self.reg_a);
+                    trace!("\tStatus(old) = {:x?}", self.reg_stat);
                 }
                 let mut oldcarry: u8 = 0;
                 if self.reg_stat & 0x01 != 0 {
@@ -642,20 +899,20 @@ impl CPU {
 
                 self.update_nz(self.reg_a);
                 
-                if self.debug {
-                    println!("\tAccumulator(new) = {:x?}", self.reg_a);
-                    println!("\tStatus(new) = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tAccumulator(new) = {:x?}", self.reg_a);
+                    trace!("\tStatus(new) = {:x?}", self.reg_stat);
                 }
             }
             _ => {
                 let addr = self.get_op_addr(mode);
-                if self.debug {
-                    println!("\tMem Address = {:x?}", addr);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tMem Address = {:x?}", addr);
                 }
                 let val = self.mem_read(addr);
-                if self.debug {
-                    println!("\tData(old) = {:x?}", val);
-                    println!("\tStatus(old) = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tData(old) = {:x?}", val);
+                    trace!("\tStatus(old) = {:x?}", self.reg_stat);
                 }
 
                 let mut oldcarry: u8 = 0;
@@ -670,9 +927,9 @@ impl CPU {
                 self.mem_write(addr, (val >> 1) | (oldcarry * 0x80));
 
                 self.update_nz((val >> 1) | (oldcarry * 0x80));
-                if self.debug {
-                    println!("\tData(new) = {:x?}", self.mem_read(addr));
-                    println!("\tStatus = {:x?}", self.reg_stat);
+                if self.log_level >= LogLevel::Instr {
+                    trace!("\tData(new) = {:x?}", self.mem_read(addr));
+                    trace!("\tStatus = {:x?}", self.reg_stat);
                 }
             }
         }
@@ -680,81 +937,81 @@ impl CPU {
 
     //Branch if Carry Clear
     fn bcc(&mut self) {
-        if self.debug {
-            println!("BCC:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BCC:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
         if self.reg_stat & 0x01 == 0 {
-            if self.debug {
-                println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-                println!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+                trace!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
             }
             let mut offset = self.mem_read(self.reg_pc) as u16;
             if offset &0x0080 != 0 {
                 offset |= 0xFF00;
             }
             self.reg_pc = self.reg_pc.wrapping_add(offset).wrapping_add(0x0001_u16);
-            if self.debug {
-                println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
             }
         }
     }
     //Branch if Carry Set
     fn bcs(&mut self) {
-        if self.debug {
-            println!("BCS:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BCS:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
         if self.reg_stat & 0x01 != 0 {
-            if self.debug {
-                println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-                println!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+                trace!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
             }
             let mut offset = self.mem_read(self.reg_pc) as u16;
             if offset &0x0080 != 0 {
                 offset |= 0xFF00;
             }
             self.reg_pc = self.reg_pc.wrapping_add(offset).wrapping_add(0x0001_u16);
-            if self.debug {
-                println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
             }
         }
     }
     //Branch if Equal
     fn beq(&mut self) {
-        if self.debug {
-            println!("BEQ:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BEQ:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
         if self.reg_stat & 0x02 != 0 {
-            if self.debug {
-                println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-                println!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+                trace!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
             }
             let mut offset = self.mem_read(self.reg_pc) as u16;
             if offset &0x0080 != 0 {
                 offset |= 0xFF00;
             }
             self.reg_pc = self.reg_pc.wrapping_add(offset).wrapping_add(0x0001_u16);
-            if self.debug {
-                println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
             }
         }
     }
     //Bit Test
     fn bit(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("BIT:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("BIT:");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tAccumulator = {:x?}", self.reg_a);
-            println!("\tStatus(old) = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tAccumulator = {:x?}", self.reg_a);
+            trace!("\tStatus(old) = {:x?}", self.reg_stat);
         }
 
         if val & self.reg_a == 0 {
@@ -774,169 +1031,168 @@ impl CPU {
         } else {
             self.reg_stat &= 0x7F;
         }
-        if self.debug {
-            println!("\tStatus(new) = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tStatus(new) = {:x?}", self.reg_stat);
         }
 
     }
     //Branch if Minus
     fn bmi(&mut self) {
-        if self.debug {
-            println!("BMI:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BMI:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
         if self.reg_stat & 0x80 != 0 {
-            if self.debug {
-                println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-                println!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+                trace!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
             }
             let mut offset = self.mem_read(self.reg_pc) as u16;
             if offset &0x0080 != 0 {
                 offset |= 0xFF00;
             }
             self.reg_pc = self.reg_pc.wrapping_add(offset).wrapping_add(0x0001_u16);
-            if self.debug {
-                println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
             }
         }
     }
     //Branch if Not Equal
     fn bne(&mut self) {
-        if self.debug {
-            println!("BNE:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BNE:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
         if self.reg_stat & 0x02 == 0 {
-            if self.debug {
-                println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-                println!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+                trace!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
             }
             let mut offset = self.mem_read(self.reg_pc) as u16;
             if offset &0x0080 != 0 {
                 offset |= 0xFF00;
             }
             self.reg_pc = self.reg_pc.wrapping_add(offset).wrapping_add(0x0001_u16);
-            if self.debug {
-                println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
             }
         }
     }
     //Branch if Positive
     fn bpl(&mut self) {
-        if self.debug {
-            println!("BPL:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BPL:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
         if self.reg_stat & 0x80 == 0 {
-            if self.debug {
-                println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-                println!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+                trace!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
             }
             let mut offset = self.mem_read(self.reg_pc) as u16;
             if offset &0x0080 != 0 {
                 offset |= 0xFF00;
             }
             self.reg_pc = self.reg_pc.wrapping_add(offset).wrapping_add(0x0001_u16);
-            if self.debug {
-                println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
             }
         }
     }
     //Branch if Overflow Clear
     fn bvc(&mut self) {
-        if self.debug {
-            println!("BVC:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BVC:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
         if self.reg_stat & 0x40 == 0 {
-            if self.debug {
-                println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-                println!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+                trace!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
             }
             let mut offset = self.mem_read(self.reg_pc) as u16;
             if offset &0x0080 != 0 {
                 offset |= 0xFF00;
             }
             self.reg_pc = self.reg_pc.wrapping_add(offset).wrapping_add(0x0001_u16);
-            if self.debug {
-                println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
             }
         }
     }
     //Branch if Overflow Set
     fn bvs(&mut self) {
-        if self.debug {
-            println!("BVS:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BVS:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
         if self.reg_stat & 0x40 != 0 {
-            if self.debug {
-                println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-                println!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+                trace!("\tOffset = {:x?}", self.mem_read(self.reg_pc));
             }
             let mut offset = self.mem_read(self.reg_pc) as u16;
             if offset &0x0080 != 0 {
                 offset |= 0xFF00;
             }
             self.reg_pc = self.reg_pc.wrapping_add(offset).wrapping_add(0x0001_u16);
-            if self.debug {
-                println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            if self.log_level >= LogLevel::Instr {
+                trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
             }
         }
     }
 
     //Force Interrupt
     fn brk(&mut self){
-        if self.debug {
-            println!("BRK: ");
-            println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-            println!("\tStatus(old) = {:x?}", self.reg_stat);
-            println!("\tStack Pointer(old) = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("BRK: ");
+            trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+            trace!("\tStatus(old) = {:x?}", self.reg_stat);
+            trace!("\tStack Pointer(old) = {:x?}", self.reg_sp);
         }
         self.stack_push16(self.reg_pc);
         self.stack_push(self.reg_stat| 0x30);
         self.reg_pc = self.mem_read16(0xFFFE);
         self.reg_stat |= 0x10;
-        if self.debug {
-            println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
-            println!("\tStatus(new) = {:x?}", self.reg_stat);
-            println!("\tStack Pointer(new) = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            trace!("\tStatus(new) = {:x?}", self.reg_stat);
+            trace!("\tStack Pointer(new) = {:x?}", self.reg_sp);
         }
     }
     //Return from Interrupt
     fn rti(&mut self) {
-        if self.debug {
-            println!("RTI: ");
-            println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-            println!("\tStatus(old) = {:x?}", self.reg_stat);
-            println!("\tStack Pointer(old) = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("RTI: ");
+            trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+            trace!("\tStatus(old) = {:x?}", self.reg_stat);
+            trace!("\tStack Pointer(old) = {:x?}", self.reg_sp);
         }
         self.reg_stat = self.stack_pull();
         self.reg_stat |= 0x20;
         self.reg_stat &= 0xEF;
 
-        let addr = self.stack_pull16();
-        self.reg_pc = self.change_endian(addr);
-        if self.debug {
-            println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
-            println!("\tStatus(new) = {:x?}", self.reg_stat);
-            println!("\tStack Pointer(new) = {:x?}", self.reg_sp);
+        self.reg_pc = self.stack_pull16();
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            trace!("\tStatus(new) = {:x?}", self.reg_stat);
+            trace!("\tStack Pointer(new) = {:x?}", self.reg_sp);
         }
     }
 
     //Compare
     fn cmp(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("CMP:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("CMP:");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let mut val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tAccumulator = {:x?}", self.reg_a);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tAccumulator = {:x?}", self.reg_a);
         }
         val = !val;
 
@@ -982,23 +1238,23 @@ impl CPU {
         }
 
         self.update_nz(res);
-        if self.debug {
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Compare X Register
     fn cpx(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("CPX:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("CPX:");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let mut val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tX Register = {:x?}", self.reg_x);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tX Register = {:x?}", self.reg_x);
         }
         val = !val;
 
@@ -1044,23 +1300,23 @@ impl CPU {
         }
 
         self.update_nz(res);
-        if self.debug {
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Compare Y Register
     fn cpy(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("CPY:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("CPY:");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let mut val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData = {:x?}", val);
-            println!("\tY Register = {:x?}", self.reg_y);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData = {:x?}", val);
+            trace!("\tY Register = {:x?}", self.reg_y);
         }
         val = !val;
 
@@ -1106,8 +1362,8 @@ impl CPU {
         }
 
         self.update_nz(res);
-        if self.debug {
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
@@ -1115,103 +1371,103 @@ impl CPU {
     fn clc(&mut self) {
         self.reg_stat &= 0xFE;
 
-        if self.debug {
-            println!("CLC:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("CLC:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Set Carry Flag
     fn sec(&mut self) {
         self.reg_stat |= 0x01;
 
-        if self.debug {
-            println!("SEC:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("SEC:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
-    //Clear Decimal Mode
+    //Clear Decimal Mode (flag only, see Decimal Mode)
     fn cld(&mut self) {
         self.reg_stat &= 0xF7;
 
-        if self.debug {
-            println!("CLD:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("CLD:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
-    //Set Decimal Mode
+    //Set Decimal Mode (flag only, see Decimal Mode)
     fn sed(&mut self) {
         self.reg_stat |= 0x08;
 
-        if self.debug {
-            println!("SED:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("SED:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Clear Interrupt Disable
     fn cli(&mut self) {
         self.reg_stat &= 0xFB;
 
-        if self.debug {
-            println!("CLI:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("CLI:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Set Interrupt Disable
     fn sei(&mut self) {
         self.reg_stat |= 0x04;
 
-        if self.debug {
-            println!("SEI:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("SEI:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Clear Overflow Flag
     fn clv(&mut self) {
         self.reg_stat &= 0xBF;
 
-        if self.debug {
-            println!("CLV:");
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("CLV:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
     //Store Accumulator Value in Memory
     fn sta(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("STA:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("STA:");
         }
         let addr = self.get_op_addr(mode);
         self.mem_write(addr, self.reg_a);
-        if self.debug {
-            println!("\tAddress = {:x?}", addr);
-            println!("\tAccumulator = {:x?}", self.reg_a);
-            println!("\tMem Content = {:x?}", self.mem_read(addr));
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAddress = {:x?}", addr);
+            trace!("\tAccumulator = {:x?}", self.reg_a);
+            trace!("\tMem Content = {:x?}", self.mem_read(addr));
         }
     }
     //Store X Register Value in Memory
     fn stx(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("STX:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("STX:");
         }
         let addr = self.get_op_addr(mode);
         self.mem_write(addr, self.reg_x);
-        if self.debug {
-            println!("\tAddress = {:x?}", addr);
-            println!("\tX Register = {:x?}", self.reg_x);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAddress = {:x?}", addr);
+            trace!("\tX Register = {:x?}", self.reg_x);
             //println!("\tMem Content = {:x?}", self.mem_read(addr));
         }
     }
     //Store Y Register Value in Memory
     fn sty(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("STY:");
+        if self.log_level >= LogLevel::Instr {
+            trace!("STY:");
         }
         let addr = self.get_op_addr(mode);
         self.mem_write(addr, self.reg_y);
-        if self.debug {
-            println!("\tAddress = {:x?}", addr);
-            println!("\tY Register = {:x?}", self.reg_y);
-            println!("\tMem Content = {:x?}", self.mem_read(addr));
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAddress = {:x?}", addr);
+            trace!("\tY Register = {:x?}", self.reg_y);
+            trace!("\tMem Content = {:x?}", self.mem_read(addr));
         }
     }
 
@@ -1221,41 +1477,41 @@ impl CPU {
 
         self.update_nz(self.reg_x);
 
-        if self.debug {
-            println!("TSX:");
-            println!("\tX Register = {:x?}", self.reg_x);
-            println!("\tStack Pointer = {:x?}", self.reg_sp);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("TSX:");
+            trace!("\tX Register = {:x?}", self.reg_x);
+            trace!("\tStack Pointer = {:x?}", self.reg_sp);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Transfer x to stack pointer
     fn txs(&mut self) {
         self.reg_sp = self.reg_x;
 
-        if self.debug {
-            println!("TXS:");
-            println!("\tX Register = {:x?}", self.reg_x);
-            println!("\tStack Pointer = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("TXS:");
+            trace!("\tX Register = {:x?}", self.reg_x);
+            trace!("\tStack Pointer = {:x?}", self.reg_sp);
         }
     }
 
     //Push accumulator on stack
     fn pha(&mut self) {
         self.stack_push(self.reg_a);
-        if self.debug {
-            println!("PHA:");
-            println!("\tAccumulator = {:x?}", self.reg_a);
-            println!("\tStack Pointer = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("PHA:");
+            trace!("\tAccumulator = {:x?}", self.reg_a);
+            trace!("\tStack Pointer = {:x?}", self.reg_sp);
         }
     }
     //Push status register on stack
     fn php(&mut self) {
 
         self.stack_push(self.reg_stat| 0x30);
-        if self.debug {
-            println!("PHP:");
-            println!("\tStatus = {:x?}", self.reg_stat);
-            println!("\tStack Pointer = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("PHP:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
+            trace!("\tStack Pointer = {:x?}", self.reg_sp);
         }
     }
 
@@ -1264,11 +1520,11 @@ impl CPU {
         self.reg_a = self.stack_pull();
 
         self.update_nz(self.reg_a);
-        if self.debug {
-            println!("PLA:");
-            println!("\tAccumulator = {:x?}", self.reg_a);
-            println!("\tStack Pointer = {:x?}", self.reg_sp);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("PLA:");
+            trace!("\tAccumulator = {:x?}", self.reg_a);
+            trace!("\tStack Pointer = {:x?}", self.reg_sp);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Pull status register from stack
@@ -1277,187 +1533,164 @@ impl CPU {
         
         self.reg_stat |= 0x20;
         self.reg_stat &= 0xEF;
-        if self.debug {
-            println!("PLP:");
-            println!("\tStatus = {:x?}", self.reg_stat);
-            println!("\tStack Pointer = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("PLP:");
+            trace!("\tStatus = {:x?}", self.reg_stat);
+            trace!("\tStack Pointer = {:x?}", self.reg_sp);
         }
     }
 
     //Increment Memory
     fn inc(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("INC: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("INC: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData(old) = {:x?}", val);
-        }
-        if val == 0xFF {
-            self.mem_write(addr, 0);
-        } else {
-            self.mem_write(addr, val + 1);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData(old) = {:x?}", val);
         }
+        let res = val.wrapping_add(1);
+        self.mem_write(addr, res);
 
-        self.update_nz(val.wrapping_add(1));
-        if self.debug {
-            println!("\tData(new) = {:x?}", self.mem_read(addr));
-            println!("\tStatus = {:x?}", self.reg_stat);
+        self.update_nz(res);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData(new) = {:x?}", self.mem_read(addr));
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Decrement Memory
     fn dec(&mut self, mode: &AddressingMode) {
-        if self.debug {
-            println!("DEC: ");
+        if self.log_level >= LogLevel::Instr {
+            trace!("DEC: ");
         }
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("\tMem Address = {:x?}", addr);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tMem Address = {:x?}", addr);
         }
         let val = self.mem_read(addr);
-        if self.debug {
-            println!("\tData(old) = {:x?}", val);
-        }
-        if val == 0x00 {
-            self.mem_write(addr, 0xFF);
-        } else {
-            self.mem_write(addr, val - 1);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData(old) = {:x?}", val);
         }
+        let res = val.wrapping_sub(1);
+        self.mem_write(addr, res);
 
-        self.update_nz(val.wrapping_sub(1));
-        if self.debug {
-            println!("\tData(new) = {:x?}", self.mem_read(addr));
-            println!("\tStatus = {:x?}", self.reg_stat);
+        self.update_nz(res);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tData(new) = {:x?}", self.mem_read(addr));
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Increment X
     fn inx(&mut self) {
-        if self.debug {
-            println!("INX:");
-            println!("\tX Register(old) = {:x?}", self.reg_x);
-        }
-        if self.reg_x == 0xFF {
-            self.reg_x = 0;
-        } else {
-            self.reg_x += 1;
+        if self.log_level >= LogLevel::Instr {
+            trace!("INX:");
+            trace!("\tX Register(old) = {:x?}", self.reg_x);
         }
+        self.reg_x = self.reg_x.wrapping_add(1);
 
         self.update_nz(self.reg_x);
-        if self.debug {
-            println!("\tX Register(new) = {:x?}", self.reg_x);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tX Register(new) = {:x?}", self.reg_x);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Decrement X
     fn dex(&mut self) {
-        if self.debug {
-            println!("DEX:");
-            println!("\tX Register(old) = {:x?}", self.reg_x);
-        }
-        if self.reg_x == 0x00 {
-            self.reg_x = 0xFF;
-        } else {
-            self.reg_x -= 1;
+        if self.log_level >= LogLevel::Instr {
+            trace!("DEX:");
+            trace!("\tX Register(old) = {:x?}", self.reg_x);
         }
+        self.reg_x = self.reg_x.wrapping_sub(1);
 
         self.update_nz(self.reg_x);
-        if self.debug {
-            println!("\tX Register(new) = {:x?}", self.reg_x);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tX Register(new) = {:x?}", self.reg_x);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
     //Increment Y
     fn iny(&mut self) {
-        if self.debug {
-            println!("INY:");
-            println!("\tY Register(old) = {:x?}", self.reg_y);
-        }
-        if self.reg_y == 0xFF {
-            self.reg_y = 0;
-        } else {
-            self.reg_y += 1;
+        if self.log_level >= LogLevel::Instr {
+            trace!("INY:");
+            trace!("\tY Register(old) = {:x?}", self.reg_y);
         }
+        self.reg_y = self.reg_y.wrapping_add(1);
 
         self.update_nz(self.reg_y);
-        if self.debug {
-            println!("\tY Register(new) = {:x?}", self.reg_y);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tY Register(new) = {:x?}", self.reg_y);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
     //Decrement Y
     fn dey(&mut self) {
-        if self.debug {
-            println!("DEY:");
-            println!("\tY Register(old) = {:x?}", self.reg_y);
-        }
-        if self.reg_y == 0x00 {
-            self.reg_y = 0xFF;
-        } else {
-            self.reg_y -= 1;
+        if self.log_level >= LogLevel::Instr {
+            trace!("DEY:");
+            trace!("\tY Register(old) = {:x?}", self.reg_y);
         }
+        self.reg_y = self.reg_y.wrapping_sub(1);
 
         self.update_nz(self.reg_y);
-        if self.debug {
-            println!("\tY Register(new) = {:x?}", self.reg_y);
-            println!("\tStatus = {:x?}", self.reg_stat);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tY Register(new) = {:x?}", self.reg_y);
+            trace!("\tStatus = {:x?}", self.reg_stat);
         }
     }
 
     //Jump
     fn jmp(&mut self, mode: &AddressingMode) {
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("JMP:");
-            println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+        if self.log_level >= LogLevel::Instr {
+            trace!("JMP:");
+            trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
         }
 
         self.reg_pc = addr;
-        if self.debug {
-            println!("\tAddress = {:x?}", addr);
-            println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tAddress = {:x?}", addr);
+            trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
         }
     }
     //Jump to Subroutine
     fn jsr(&mut self, mode: &AddressingMode) {
         let addr = self.get_op_addr(mode);
-        if self.debug {
-            println!("JSR:");
-            println!("\tAddress = {:x?}", addr);
-            println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-            println!("\tStack Pointer(old) = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("JSR:");
+            trace!("\tAddress = {:x?}", addr);
+            trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+            trace!("\tStack Pointer(old) = {:x?}", self.reg_sp);
         }
 
         self.stack_push16(self.reg_pc + 1);
         self.reg_pc = addr;
-        if self.debug {
-            println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
-            println!("\tStack Pointer(new) = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            trace!("\tStack Pointer(new) = {:x?}", self.reg_sp);
         }
     }
     //Return from Subroutine
     fn rts(&mut self) {
-        if self.debug {
-            println!("RTS:");
-            println!("\tProgram Counter(old) = {:x?}", self.reg_pc);
-            println!("\tStack Pointer(old) = {:x?}", self.reg_sp);
+        if self.log_level >= LogLevel::Instr {
+            trace!("RTS:");
+            trace!("\tProgram Counter(old) = {:x?}", self.reg_pc);
+            trace!("\tStack Pointer(old) = {:x?}", self.reg_sp);
         }
-        let addr = self.stack_pull16();
-        self.reg_pc = self.change_endian(addr) + 1;
-        if self.debug {
-            println!("\tProgram Counter(new) = {:x?}", self.reg_pc);
-            println!("\tStack Pointer(new) = {:x?}", self.reg_sp);
+        self.reg_pc = self.stack_pull16().wrapping_add(1);
+        if self.log_level >= LogLevel::Instr {
+            trace!("\tProgram Counter(new) = {:x?}", self.reg_pc);
+            trace!("\tStack Pointer(new) = {:x?}", self.reg_sp);
         }
     }
 
     //No Operation
     fn nop(&self) {
-        if self.debug {
-            println!("NOP:")
+        if self.log_level >= LogLevel::Instr {
+            trace!("NOP:")
         }
     }
 
@@ -1483,36 +1716,54 @@ impl CPU {
         }
     }
 
+    //one line trace of the instruction about to execute (PC already points past the opcode)
+    fn log_instr(&self, opcode: &opcodes::OpCode) {
+        if self.log_level >= LogLevel::Trace {
+            trace!(
+                "{:04X}  {:02X} {}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+                self.reg_pc.wrapping_sub(1), opcode.code, opcode.mnemonic,
+                self.reg_a, self.reg_x, self.reg_y, self.reg_stat, self.reg_sp, self.tot_cycles
+            );
+        }
+    }
+
     //bytecode interpreter
     pub fn interpret(&mut self) {
         if self.reg_pc < 0xFFFF {
-            if self.mem_bus.poll_nmi_status() && !self.nmi_flag {
+            if self.mem_bus.take_nmi() {
                 self.interrupt_nmi();
-                self.nmi_flag = true;
                 self.cycles += 2;
             }
             else {
-                if !self.mem_bus.poll_nmi_status() {
-                    self.nmi_flag = false;
-                }
                 let code = self.mem_read(self.reg_pc);
                 if self.brk_stop && code == 0x00 {
                     return;
                 }
-                if self.debug {
-                    println!("${:x?}", self.reg_pc);
-                }
                 self.reg_pc+=1;
 
                 let pc_state = self.reg_pc;
-                let opcode = opcodes::OPCODES_MAP.get(&code).unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
+                let opcode = match opcodes::OPCODES_MAP.get(&code) {
+                    Some(opcode) => *opcode,
+                    None if !self.strict_opcodes => {
+                        warn!("unknown opcode ${:02X} at ${:04X}, skipping as NOP", code, pc_state - 1);
+                        &opcodes::UNKNOWN_OPCODES_MAP[&code]
+                    },
+                    None => panic!("OpCode {:x} is not recognized", code),
+                };
+                self.log_instr(opcode);
                 self.cycles += opcode.cycles;
+                //the opcode fetch itself
+                self.bus_cycle();
 
                 //match opcodes to instructions
                 match opcode.mnemonic {
                     "ADC" => self.adc(&opcode.mode),
+                    "ALR" => self.alr(&opcode.mode),
+                    "ANC" => self.anc(&opcode.mode),
                     "AND" => self.and(&opcode.mode),
+                    "ARR" => self.arr(&opcode.mode),
                     "ASL" => self.asl(&opcode.mode),
+                    "AXS" => self.axs(&opcode.mode),
                     "BCC" => self.bcc(),
                     "BCS" => self.bcs(),
                     "BEQ" => self.beq(),
@@ -1578,6 +1829,8 @@ impl CPU {
                 }
                 
                 //Extra Cycles
+                //only reads pay for a page crossing, stores and read-modify-writes
+                //always take the fixed (already longer) cycle count from the table
                 match opcode.mnemonic {
                     "ADC" | "AND" | "CMP" |
                     "EOR" | "LDA" | "LDX" |
@@ -1612,11 +1865,398 @@ impl CPU {
                     self.reg_pc += opcode.len as u16 - 1;
                 }
             }
-            if self.cycles > 0 {
-                self.mem_bus.tick(3 * self.cycles);
-                self.tot_cycles += self.cycles as u32;
+            let mut cycles = self.cycles as u16;
+            if self.mem_bus.take_oam_dma() {
+                //513 stall cycles, +1 to align when the transfer starts on an odd cycle
+                cycles += 513 + ((self.tot_cycles + cycles as u32) % 2) as u16;
+            }
+            if cycles > 0 {
+                self.mem_bus.tick(3 * (cycles - self.bus_cycles));
+                self.tot_cycles += cycles as u32;
                 self.cycles = 0;
+                self.bus_cycles = 0;
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::mapper::tests::ines;
+    use crate::rom::{Mirroring, Rom};
+
+    //an NROM image with `program` at $8000, where the reset vector points
+    pub(crate) fn rom(program: &[u8]) -> Vec<u8> {
+        let mut raw = ines(0, 1, 1);
+        raw[16..16 + program.len()].copy_from_slice(program);
+        raw[16 + 0x3FFC] = 0x00;
+        raw[16 + 0x3FFD] = 0x80;
+        raw
+    }
+
+    //a powered-on CPU about to run `program`, with the PPU warm-up off
+    pub(crate) fn cpu(program: &[u8]) -> CPU {
+        let mut cpu = CPU::new(Bus::new(Rom::new(&rom(program)).unwrap(), None).unwrap());
+        cpu.mem_bus.ppu.set_warm_up(false);
+        cpu.power_on();
+        cpu
+    }
+
+    #[test]
+    fn breakpoints_halt_before_the_instruction() {
+        //LDX #1, INX, INX, NOP
+        let mut cpu = cpu(&[0xA2, 0x01, 0xE8, 0xE8, 0xEA]);
+        cpu.add_breakpoint(0x8003);
+        assert_eq!(cpu.step(), StepResult::Completed);
+        assert_eq!(cpu.step(), StepResult::Completed);
+        assert_eq!(cpu.step(), StepResult::Breakpoint(0x8003));
+        assert_eq!(cpu.reg_x, 2);
+        //resuming executes the instruction under the breakpoint
+        assert_eq!(cpu.step(), StepResult::Completed);
+        assert_eq!(cpu.reg_x, 3);
+    }
+
+    #[test]
+    fn run_for_stops_in_front_of_a_breakpoint() {
+        let mut cpu = cpu(&[0xA2, 0x01, 0xE8, 0xE8, 0xEA]);
+        cpu.add_breakpoint(0x8003);
+        assert_eq!(cpu.run_for(10), 2);
+        assert_eq!(cpu.reg_pc, 0x8003);
+        cpu.remove_breakpoint(0x8003);
+        assert_eq!(cpu.run_for(2), 2);
+        assert_eq!(cpu.reg_pc, 0x8005);
+    }
+
+    #[test]
+    fn watchpoints_halt_after_the_access() {
+        //LDA $10, STA $11, NOP
+        let mut cpu = cpu(&[0xA5, 0x10, 0x85, 0x11, 0xEA]);
+        cpu.add_watchpoint(0x0010, WatchKind::Read);
+        cpu.add_watchpoint(0x0011, WatchKind::Write);
+        assert_eq!(cpu.step(), StepResult::Watchpoint(0x0010, Access::Read));
+        assert_eq!(cpu.reg_pc, 0x8002);
+        assert_eq!(cpu.step(), StepResult::Watchpoint(0x0011, Access::Write));
+        assert_eq!(cpu.step(), StepResult::Completed);
+    }
+
+    //what a program (or the screen) could observe of the machine
+    pub(crate) fn machine_state(cpu: &CPU) -> Vec<u8> {
+        let ppu = &cpu.mem_bus.ppu;
+        let mut state = vec![cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.reg_stat, cpu.reg_sp];
+        state.extend(cpu.reg_pc.to_le_bytes());
+        state.extend(cpu.tot_cycles.to_le_bytes());
+        state.extend((0..0x800).map(|addr| cpu.mem_bus.peek(addr)));
+        state.extend(ppu.vram);
+        state.extend(ppu.oam_data);
+        state.extend(ppu.palette_table);
+        state.extend([ppu.ctrl, ppu.stat]);
+        state.extend(ppu.cycles.to_le_bytes());
+        state.extend(ppu.scanlines.to_le_bytes());
+        state
+    }
+
+    #[test]
+    fn fresh_machines_power_on_identically() {
+        //LDA #$55, STA $0200, STA $2004, STA $2000
+        let program = [0xA9, 0x55, 0x8D, 0x00, 0x02, 0x8D, 0x04, 0x20, 0x8D, 0x00, 0x20];
+        let fresh = machine_state(&cpu(&program));
+        assert_eq!(machine_state(&cpu(&program)), fresh);
+
+        //a power cycle goes through the same state
+        let mut cpu = cpu(&program);
+        cpu.run_for(4);
+        cpu.mem_bus.set_mirroring(Mirroring::ONE_SCREEN_UPPER);
+        assert_ne!(machine_state(&cpu), fresh);
+        cpu.power_on();
+        assert_eq!(machine_state(&cpu), fresh);
+        assert_eq!(cpu.mem_bus.ppu.mirroring, Mirroring::HORIZONTAL);
+    }
+
+    #[test]
+    fn oam_dma_stalls_the_cpu() {
+        //LDA #$02, STA $4014
+        let mut odd = cpu(&[0xA9, 0x02, 0x8D, 0x14, 0x40]);
+        odd.run_for(1);
+        let dot = |cpu: &CPU| cpu.mem_bus.ppu.scanlines as u32 * 341 + cpu.mem_bus.ppu.cycles as u32;
+        let start = dot(&odd);
+        //the transfer starts on an odd cycle (7 + 2 + 4), so it takes 514
+        odd.run_for(1);
+        assert_eq!(odd.tot_cycles, 7 + 2 + 4 + 514);
+        assert_eq!(dot(&odd), start + 3 * (4 + 514));
+
+        //LDA $00, LDA #$02, STA $4014
+        let mut even = cpu(&[0xA5, 0x00, 0xA9, 0x02, 0x8D, 0x14, 0x40]);
+        even.run_for(3);
+        assert_eq!(even.tot_cycles, 7 + 3 + 2 + 4 + 513);
+
+        let mut unstalled = cpu(&[0xA9, 0x02, 0x8D, 0x14, 0x40]);
+        unstalled.mem_bus.oam_dma_stall = false;
+        unstalled.run_for(2);
+        assert_eq!(unstalled.tot_cycles, 7 + 2 + 4);
+    }
+
+    //runs `count` instructions of `program`
+    fn run(program: &[u8], count: u64) -> CPU {
+        let mut cpu = cpu(program);
+        assert_eq!(cpu.run_for(count), count);
+        cpu
+    }
+
+    const CARRY: u8 = 0x01;
+    const ZERO: u8 = 0x02;
+    const DECIMAL: u8 = 0x08;
+    const OVERFLOW: u8 = 0x40;
+    const NEGATIVE: u8 = 0x80;
+
+    #[test]
+    fn anc_copies_negative_into_carry() {
+        //LDA #$F0, ANC #$81
+        let cpu = run(&[0xA9, 0xF0, 0x0B, 0x81], 2);
+        assert_eq!(cpu.reg_a, 0x80);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | CARRY), NEGATIVE | CARRY);
+        //SEC, LDA #$70, ANC #$F0 ($2B)
+        let cpu = run(&[0x38, 0xA9, 0x70, 0x2B, 0xF0], 3);
+        assert_eq!(cpu.reg_a, 0x70);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | CARRY), 0);
+    }
+
+    #[test]
+    fn alr_ands_then_shifts_right() {
+        //LDA #$FF, ALR #$03
+        let cpu = run(&[0xA9, 0xFF, 0x4B, 0x03], 2);
+        assert_eq!(cpu.reg_a, 0x01);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | ZERO | CARRY), CARRY);
+        //LDA #$FF, ALR #$01
+        let cpu = run(&[0xA9, 0xFF, 0x4B, 0x01], 2);
+        assert_eq!(cpu.reg_a, 0x00);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | ZERO | CARRY), ZERO | CARRY);
+    }
+
+    #[test]
+    fn arr_ands_then_rotates_right() {
+        //SEC, LDA #$FF, ARR #$C0
+        let cpu = run(&[0x38, 0xA9, 0xFF, 0x6B, 0xC0], 3);
+        assert_eq!(cpu.reg_a, 0xE0);
+        //C from bit 6, V from bit 6 xor bit 5
+        assert_eq!(cpu.reg_stat & (NEGATIVE | OVERFLOW | CARRY), NEGATIVE | CARRY);
+        //CLC, LDA #$FF, ARR #$40
+        let cpu = run(&[0x18, 0xA9, 0xFF, 0x6B, 0x40], 3);
+        assert_eq!(cpu.reg_a, 0x20);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | OVERFLOW | CARRY), OVERFLOW);
+    }
+
+    #[test]
+    fn axs_subtracts_from_a_and_x() {
+        //LDA #$F0, LDX #$3C, AXS #$10
+        let cpu = run(&[0xA9, 0xF0, 0xA2, 0x3C, 0xCB, 0x10], 3);
+        assert_eq!(cpu.reg_x, 0x20);
+        assert_eq!(cpu.reg_a, 0xF0);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | ZERO | CARRY), CARRY);
+        //LDA #$F0, LDX #$3C, AXS #$31
+        let cpu = run(&[0xA9, 0xF0, 0xA2, 0x3C, 0xCB, 0x31], 3);
+        assert_eq!(cpu.reg_x, 0xFF);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | ZERO | CARRY), NEGATIVE);
+    }
+
+    #[test]
+    fn unofficial_nops_skip_their_operands() {
+        //NOP, NOP #, NOP zp,X, NOP abs, NOP abs,X
+        let cpu = run(&[0x1A, 0x80, 0xFF, 0x14, 0x10, 0x0C, 0x00, 0x02, 0x1C, 0xFF, 0x02], 5);
+        assert_eq!(cpu.reg_pc, 0x800B);
+        assert_eq!((cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.reg_stat), (0, 0, 0, 0x24));
+        assert_eq!(cpu.tot_cycles, 7 + 2 + 2 + 4 + 4 + 4);
+    }
+
+    #[test]
+    fn unknown_opcodes_are_skipped_as_nops() {
+        //$02 (1 byte), $8B #$FF (2 bytes), $9C $00 $02 (3 bytes), LDA #$01
+        let cpu = run(&[0x02, 0x8B, 0xFF, 0x9C, 0x00, 0x02, 0xA9, 0x01], 4);
+        assert_eq!(cpu.reg_pc, 0x8008);
+        assert_eq!(cpu.reg_a, 0x01);
+    }
+
+    #[test]
+    #[should_panic]
+    fn strict_opcodes_panic_on_unknown_opcodes() {
+        let mut cpu = cpu(&[0x02]);
+        cpu.strict_opcodes = true;
+        cpu.step();
+    }
+
+    #[test]
+    fn increments_and_decrements_wrap() {
+        //LDX #$FF, INX, LDY #$FF, INY
+        let cpu = run(&[0xA2, 0xFF, 0xE8, 0xA0, 0xFF, 0xC8], 4);
+        assert_eq!((cpu.reg_x, cpu.reg_y), (0x00, 0x00));
+        assert_eq!(cpu.reg_stat & (NEGATIVE | ZERO), ZERO);
+        //LDX #$00, DEX, LDY #$00, DEY
+        let cpu = run(&[0xA2, 0x00, 0xCA, 0xA0, 0x00, 0x88], 4);
+        assert_eq!((cpu.reg_x, cpu.reg_y), (0xFF, 0xFF));
+        assert_eq!(cpu.reg_stat & (NEGATIVE | ZERO), NEGATIVE);
+        //LDA #$FF, STA $10, INC $10
+        let cpu = run(&[0xA9, 0xFF, 0x85, 0x10, 0xE6, 0x10], 3);
+        assert_eq!(cpu.mem_bus.peek(0x0010), 0x00);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | ZERO), ZERO);
+        //DEC $10
+        let cpu = run(&[0xC6, 0x10], 1);
+        assert_eq!(cpu.mem_bus.peek(0x0010), 0xFF);
+        assert_eq!(cpu.reg_stat & (NEGATIVE | ZERO), NEGATIVE);
+    }
+
+    //LDA $2002 started `dots` PPU dots before vblank is set (scanline 241, dot 1)
+    fn read_status_before_vblank(dots: u16) -> u8 {
+        let mut cpu = cpu(&[0xAD, 0x02, 0x20]);
+        cpu.mem_bus.ppu.scanlines = 240;
+        cpu.mem_bus.ppu.cycles = 342 - dots;
+        cpu.step();
+        cpu.reg_a
+    }
+
+    #[test]
+    fn status_reads_see_the_ppu_mid_instruction() {
+        //the PPU is clocked as the instruction goes, the read lands 12 dots in rather than at its start
+        assert_eq!(read_status_before_vblank(12) & 0x80, 0x80);
+        assert_eq!(read_status_before_vblank(13) & 0x80, 0x00);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_running_cpu_survives_a_bincode_round_trip() {
+        //INC $10, JMP $8000
+        let mut cpu = cpu(&[0xE6, 0x10, 0x4C, 0x00, 0x80]);
+        cpu.run_for(10_000);
+        let saved = bincode::serialize(&cpu).unwrap();
+        let mut restored: CPU = bincode::deserialize(&saved).unwrap();
+        assert_eq!(bincode::serialize(&restored).unwrap(), saved);
+        assert_eq!(machine_state(&restored), machine_state(&cpu));
+
+        cpu.run_for(20_000);
+        restored.run_for(20_000);
+        assert_eq!(machine_state(&restored), machine_state(&cpu));
+    }
+
+    #[test]
+    fn decimal_mode_leaves_arithmetic_binary() {
+        //SED, CLC, LDA #$09, ADC #$01
+        let cpu = run(&[0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01], 4);
+        assert_eq!(cpu.reg_a, 0x0A);
+        //SED, SEC, LDA #$10, SBC #$01
+        let cpu = run(&[0xF8, 0x38, 0xA9, 0x10, 0xE9, 0x01], 4);
+        assert_eq!(cpu.reg_a, 0x0F);
+        assert_eq!(cpu.reg_stat & CARRY, CARRY);
+    }
+
+    #[test]
+    fn the_decimal_flag_survives_the_stack() {
+        //SED, PHP, PLA
+        let cpu = run(&[0xF8, 0x08, 0x68], 3);
+        assert_eq!(cpu.reg_a & DECIMAL, DECIMAL);
+        //LDA #$08, PHA, PLP, PHP, PLA (PHP also sets bits 4 and 5)
+        let cpu = run(&[0xA9, 0x08, 0x48, 0x28, 0x08, 0x68], 5);
+        assert_eq!(cpu.reg_a, 0x38);
+        //SED, CLD, PHP, PLA
+        let cpu = run(&[0xF8, 0xD8, 0x08, 0x68], 4);
+        assert_eq!(cpu.reg_a & DECIMAL, 0);
+    }
+
+    #[test]
+    fn the_stack_wraps_within_page_one() {
+        //LDX #0, TXS, JSR $8010, NOP ... $8010: RTS
+        let mut program = vec![0xEA; 0x11];
+        program[..6].copy_from_slice(&[0xA2, 0x00, 0x9A, 0x20, 0x10, 0x80]);
+        program[0x10] = 0x60;
+        let mut cpu = run(&program, 3);
+        //the return address minus one, high byte first
+        assert_eq!(cpu.mem_bus.peek(0x0100), 0x80);
+        assert_eq!(cpu.mem_bus.peek(0x01FF), 0x05);
+        assert_eq!(cpu.mem_bus.peek(0x0200), 0x00);
+        assert_eq!(cpu.reg_sp, 0xFE);
+        cpu.step();
+        assert_eq!((cpu.reg_pc, cpu.reg_sp), (0x8006, 0x00));
+
+        //LDA #$5A, STA $0100, LDX #$FF, TXS, LDA #0, PLA
+        let cpu = run(&[0xA9, 0x5A, 0x8D, 0x00, 0x01, 0xA2, 0xFF, 0x9A, 0xA9, 0x00, 0x68], 6);
+        assert_eq!((cpu.reg_a, cpu.reg_sp), (0x5A, 0x00));
+    }
+
+    #[test]
+    fn stop_on_brk_halts_in_front_of_the_brk() {
+        //LDX #1, BRK
+        let mut cpu = run(&[0xA2, 0x01, 0x00], 1);
+        let (cycles, sp) = (cpu.tot_cycles, cpu.reg_sp);
+        cpu.set_brk_stop(true);
+        cpu.interpret();
+        assert_eq!((cpu.reg_pc, cpu.reg_sp, cpu.tot_cycles), (0x8002, sp, cycles));
+        assert_eq!(cpu.step(), StepResult::Brk(0x8002));
+        assert!(!cpu.brk_stop());
+        //resuming runs the BRK, pushing PC and status
+        assert_eq!(cpu.step(), StepResult::Completed);
+        assert_eq!(cpu.reg_sp, sp.wrapping_sub(3));
+    }
+
+    //cycles `code` takes with its operand indexed from `base` by 1 (X and Y)
+    fn indexed_cycles(code: u8, mode: &AddressingMode, base: u16) -> u32 {
+        let program = match mode {
+            AddressingMode::Indirect_Y => vec![code, 0x10],
+            _ => vec![code, base as u8, (base >> 8) as u8],
+        };
+        let mut cpu = cpu(&program);
+        cpu.mem_bus.poke(0x0010, base as u8);
+        cpu.mem_bus.poke(0x0011, (base >> 8) as u8);
+        cpu.reg_x = 1;
+        cpu.reg_y = 1;
+        let start = cpu.tot_cycles;
+        cpu.step();
+        cpu.tot_cycles - start
+    }
+
+    #[test]
+    fn only_reads_pay_for_a_page_crossing() {
+        let reads = ["ADC", "AND", "CMP", "EOR", "LDA", "LDX", "LDY", "ORA", "SBC", "LAX", "NOP"];
+        for op in opcodes::OPCODES_MAP.values() {
+            if !matches!(op.mode, AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y) {
+                continue;
+            }
+            let same_page = indexed_cycles(op.code, &op.mode, 0x0200);
+            let crossing = indexed_cycles(op.code, &op.mode, 0x02FF);
+            assert_eq!(same_page, op.cycles as u32, "${:02X} {}", op.code, op.mnemonic);
+            let penalty = reads.contains(&op.mnemonic) as u32;
+            assert_eq!(crossing - same_page, penalty, "${:02X} {}", op.code, op.mnemonic);
+        }
+    }
+
+    #[test]
+    fn run_for_stops_exactly_at_the_limit() {
+        //INX, JMP $8000 forever
+        let mut looping = cpu(&[0xE8, 0x4C, 0x00, 0x80]);
+        assert_eq!(looping.run_for(0), 0);
+        assert_eq!(looping.run_for(11), 11);
+        assert_eq!((looping.reg_x, looping.reg_pc), (6, 0x8001));
+        assert_eq!(looping.run_for(1), 1);
+        assert_eq!((looping.reg_x, looping.reg_pc), (6, 0x8000));
+
+        //a BRK ends the run early, without being executed or counted
+        let mut brk = cpu(&[0xA9, 0x01, 0xA9, 0x02, 0x00]);
+        brk.set_brk_stop(true);
+        assert_eq!(brk.run_for(100), 2);
+        assert_eq!((brk.reg_a, brk.reg_pc), (0x02, 0x8004));
+    }
+
+    #[test]
+    fn one_nmi_edge_is_serviced_exactly_once() {
+        //JMP $8000 forever, the NMI handler at $8010 counts into $00: INC $00, RTI
+        let mut raw = rom(&[0x4C, 0x00, 0x80]);
+        raw[16 + 0x10..16 + 0x13].copy_from_slice(&[0xE6, 0x00, 0x40]);
+        raw[16 + 0x3FFA] = 0x10;
+        raw[16 + 0x3FFB] = 0x80;
+        let mut cpu = CPU::new(Bus::new(Rom::new(&raw).unwrap(), None).unwrap());
+        cpu.mem_bus.ppu.set_warm_up(false);
+        cpu.power_on();
+        //the PPU's NMI output turning on once
+        cpu.mem_bus.ppu.nmi_interrupt = true;
+        assert!(cpu.mem_bus.poll_nmi_status());
+        assert_eq!(cpu.run_for(30), 30);
+        assert_eq!(cpu.mem_read(0x00), 1);
+        assert!(!cpu.mem_bus.poll_nmi_status());
+    }
+}
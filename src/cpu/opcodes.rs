@@ -58,6 +58,12 @@ lazy_static!{
         OpCode::new_custom(0x83, "SAX", 2, 6, AddressingMode::Indirect_X),
         OpCode::new_custom(0x8F, "SAX", 3, 4, AddressingMode::Absolute),
 
+        OpCode::new_custom(0x0B, "ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new_custom(0x2B, "ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new_custom(0x4B, "ALR", 2, 2, AddressingMode::Immediate),
+        OpCode::new_custom(0x6B, "ARR", 2, 2, AddressingMode::Immediate),
+        OpCode::new_custom(0xCB, "AXS", 2, 2, AddressingMode::Immediate),
+
         OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
@@ -172,9 +178,9 @@ lazy_static!{
         OpCode::new_custom(0xA7, "LAX", 2, 3, AddressingMode::ZeroPage),
         OpCode::new_custom(0xB7, "LAX", 2, 4, AddressingMode::ZeroPage_Y),
         OpCode::new_custom(0xAF, "LAX", 3, 4, AddressingMode::Absolute),
-        OpCode::new_custom(0xBF, "LAX", 3, 4, AddressingMode::Absolute_Y),
+        OpCode::new_custom(0xBF, "LAX", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_Y),
         OpCode::new_custom(0xA3, "LAX", 2, 6, AddressingMode::Indirect_X),
-        OpCode::new_custom(0xB3, "LAX", 2, 5, AddressingMode::Indirect_Y),
+        OpCode::new_custom(0xB3, "LAX", 2, 5 /*+1 if page crossed*/, AddressingMode::Indirect_Y),
 
         OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
@@ -205,12 +211,12 @@ lazy_static!{
         OpCode::new_custom(0xE2, "NOP", 2, 2, AddressingMode::Immediate),
         OpCode::new_custom(0xF4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
         OpCode::new_custom(0x0C, "NOP", 3, 4, AddressingMode::Absolute),
-        OpCode::new_custom(0x1C, "NOP", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new_custom(0x3C, "NOP", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new_custom(0x5C, "NOP", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new_custom(0x7C, "NOP", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new_custom(0xDC, "NOP", 3, 4, AddressingMode::Absolute_X),
-        OpCode::new_custom(0xFC, "NOP", 3, 4, AddressingMode::Absolute_X),
+        OpCode::new_custom(0x1C, "NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new_custom(0x3C, "NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new_custom(0x5C, "NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new_custom(0x7C, "NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new_custom(0xDC, "NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new_custom(0xFC, "NOP", 3, 4 /*+1 if page crossed*/, AddressingMode::Absolute_X),
         OpCode::new_custom(0x1A, "NOP", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new_custom(0x3A, "NOP", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new_custom(0x5A, "NOP", 1, 2, AddressingMode::NoneAddressing),
@@ -319,4 +325,93 @@ lazy_static!{
         }
         map
     };
-}
\ No newline at end of file
+
+    /*
+    Unknown Opcodes:
+
+    Every byte missing from OPCODES_MAP (KIL/JAM, SHX, TAS, ...) as a NOP
+    of the documented length, used when the CPU is not in strict mode.
+    */
+    pub static ref UNKNOWN_OPCODES_MAP: HashMap<u8, OpCode> = {
+        let mut map = HashMap::new();
+        for code in 0..=0xFF_u8 {
+            if !OPCODES_MAP.contains_key(&code) {
+                map.insert(code, OpCode::new_custom(code, "NOP", unknown_opcode_len(code), 2, AddressingMode::NoneAddressing));
+            }
+        }
+        map
+    };
+}
+
+//instruction length implied by the opcode's column in the 6502 opcode matrix
+fn unknown_opcode_len(code: u8) -> u8 {
+    match code & 0x1F {
+        0x02 | 0x12 => 1,
+        0x0C..=0x0F | 0x19 | 0x1B..=0x1F => 3,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+    NMOS 6502 base cycle counts and instruction lengths, from the
+    nesdev opcode matrix, indexed by opcode. 0 cycles marks the
+    KIL/JAM opcodes, which are not in OPCODES_MAP.
+    */
+    const CYCLES: [u8; 256] = [
+        7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5,
+        2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4,
+        2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4,
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+        2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6,
+        2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7,
+    ];
+
+    const LENGTHS: [u8; 256] = [
+        1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        3, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        1, 2, 1, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+        2, 2, 2, 2, 2, 2, 2, 2, 1, 2, 1, 2, 3, 3, 3, 3,
+        2, 2, 1, 2, 2, 2, 2, 2, 1, 3, 1, 3, 3, 3, 3, 3,
+    ];
+
+    #[test]
+    fn opcodes_match_the_reference_table() {
+        let mut mismatches = Vec::new();
+        for op in CPU_OPS_CODES.iter() {
+            let code = op.code as usize;
+            if (op.len, op.cycles) != (LENGTHS[code], CYCLES[code]) {
+                mismatches.push(format!(
+                    "${:02X} {}: len {} cycles {}, expected len {} cycles {}",
+                    op.code, op.mnemonic, op.len, op.cycles, LENGTHS[code], CYCLES[code]
+                ));
+            }
+        }
+        assert!(mismatches.is_empty(), "\n{}", mismatches.join("\n"));
+        assert_eq!(OPCODES_MAP.len(), CPU_OPS_CODES.len(), "an opcode is listed twice");
+    }
+}
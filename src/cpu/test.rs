@@ -1,8 +1,16 @@
-use crate::cpu::CPU;
 use lazy_static::lazy_static;
 
+/*
+Test Programs:
+
+easy6502 style programs, which draw to a 32x32 display mapped at
+$0200-$05FF (one byte per pixel, colour in the low nibble) and
+read a random byte from $FE and the last key pressed from $FF.
+They run outside of the NES hardware through demo.rs.
+*/
+
 lazy_static! {
-    pub static ref test_bmp_display: Vec<u8> = vec![
+    pub static ref TEST_BMP_DISPLAY: Vec<u8> = vec![
         0xA9, 0x00,         //$8000 LDA #0x00     ; set pointer at $10 to $0200
         0x85, 0x10,         //$8002 STA 0x10
         0xA9, 0x02,         //$8004 LDA #0x02
@@ -15,17 +23,17 @@ lazy_static! {
         0xA9, 0x07,         //$800C LDA #0x07     ; colour code to be used to fill the display
 
                             //loop:
-        0x91, 0x10,         //$800E STA (0x10), X ; store colour to the value of the pointer + y
+        0x91, 0x10,         //$800E STA (0x10), Y ; store colour to the value of the pointer + y
         0xC8,               //$8010 INY           ; increment index - prepare to fill next pixel
-        0xD0, 0xFE,         //$8011 BNE loop      ; branch until page done - stops when Y==0
+        0xD0, 0xFB,         //$8011 BNE loop      ; branch until page done - stops when Y==0
 
         0xE6, 0x11,         //$8013 INC 0x11      ; increment high byte of pointer
         0xE4, 0x11,         //$8015 CPX 0x11      ; compare with max value
-        0xD0, 0xF8,         //$8017 BNE loop      ; continue if not done
+        0xD0, 0xF5,         //$8017 BNE loop      ; continue if not done
         0x00                //$8019 BRK           ; done - return to debugger
     ];
 
-    pub static ref snake_code: Vec<u8> = vec![
+    pub static ref SNAKE_CODE: Vec<u8> = vec![
         0x20, 0x06, 0x06, 
         0x20, 0x38, 0x06, 
         0x20, 0x0d, 0x06, 
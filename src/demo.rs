@@ -0,0 +1,163 @@
+use crate::bus::Bus;
+use crate::cpu::test::{SNAKE_CODE, TEST_BMP_DISPLAY};
+use crate::cpu::CPU;
+use crate::display::Frame;
+use crate::rom::{Rom, RomError};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/*
+Demo Mode:
+
+Runs the easy6502 test programs (see cpu/test.rs) on the emulated
+CPU. They live on a bare NROM cartridge whose PRG-ROM holds the
+program (or, for programs that must sit in RAM at $0600, only an
+idle loop), and the PPU is never switched on. Instead the 32x32
+display at $0200-$05FF is read straight out of CPU RAM and scaled
+up into the middle of the NES frame.
+
+Before every instruction $FE gets a new random byte, $FF holds the
+last key pressed (as an ASCII code, easy6502's w/a/s/d for snake).
+BRK lands in the idle loop, which is where a finished program
+stays.
+*/
+
+const DISPLAY: u16 = 0x0200;
+const DISPLAY_SIZE: usize = 32;
+//7x7 NES pixels per display pixel, centred in the 256x240 frame
+const PIXEL_SCALE: usize = 7;
+const OFFSET_X: usize = (256 - DISPLAY_SIZE * PIXEL_SCALE) / 2;
+const OFFSET_Y: usize = (240 - DISPLAY_SIZE * PIXEL_SCALE) / 2;
+const RANDOM_ADDR: u16 = 0x00FE;
+const KEY_ADDR: u16 = 0x00FF;
+//CPU cycles per frame, slow enough for snake to be playable
+pub const CYCLES_PER_FRAME: u32 = 500;
+//JMP to itself, the reset vector of RAM programs and the BRK/IRQ vector of all of them
+const IDLE_LOOP: u16 = 0xF000;
+
+//colour of each low nibble, as easy6502 draws them
+const COLOURS: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0xFF, 0xFF, 0xFF), (0x88, 0x00, 0x00), (0xAA, 0xFF, 0xEE),
+    (0xCC, 0x44, 0xCC), (0x00, 0xCC, 0x55), (0x00, 0x00, 0xAA), (0xEE, 0xEE, 0x77),
+    (0xDD, 0x88, 0x55), (0x66, 0x44, 0x00), (0xFF, 0x77, 0x77), (0x33, 0x33, 0x33),
+    (0x77, 0x77, 0x77), (0xAA, 0xFF, 0x66), (0x00, 0x88, 0xFF), (0xBB, 0xBB, 0xBB),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DemoProgram {
+    //the easy6502 snake game, loaded into RAM at $0600
+    Snake,
+    //fills the display with yellow, runs from ROM at $8000
+    Bmp,
+}
+
+pub struct Demo {
+    pub cpu: CPU,
+    rng: StdRng,
+}
+
+impl Demo {
+    //`seed` drives the $FE random bytes
+    pub fn new(program: DemoProgram, seed: u64) -> Result<Self, RomError> {
+        let mut prg = vec![0xEA; 0x4000];
+        let idle = (IDLE_LOOP & 0x3FFF) as usize;
+        prg[idle..idle + 3].copy_from_slice(&[0x4C, IDLE_LOOP as u8, (IDLE_LOOP >> 8) as u8]);
+        let reset = match program {
+            DemoProgram::Snake => IDLE_LOOP,
+            DemoProgram::Bmp => {
+                prg[..TEST_BMP_DISPLAY.len()].copy_from_slice(&TEST_BMP_DISPLAY);
+                0x8000
+            },
+        };
+        //vectors: NMI, RESET, IRQ/BRK
+        prg[0x3FFA..].copy_from_slice(&[
+            IDLE_LOOP as u8, (IDLE_LOOP >> 8) as u8,
+            reset as u8, (reset >> 8) as u8,
+            IDLE_LOOP as u8, (IDLE_LOOP >> 8) as u8,
+        ]);
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        raw.extend(prg);
+        raw.extend(vec![0x00; 0x2000]);
+
+        let mut cpu = CPU::new(Bus::new(Rom::new(&raw)?, None)?);
+        cpu.power_on();
+        if program == DemoProgram::Snake {
+            for (i, &byte) in SNAKE_CODE.iter().enumerate() {
+                cpu.mem_bus.poke(0x0600 + i as u16, byte);
+            }
+            cpu.reg_pc = 0x0600;
+        }
+        Ok(Demo { cpu, rng: StdRng::seed_from_u64(seed) })
+    }
+
+    //the ASCII code of a key press, read by the program from $FF
+    pub fn set_key(&mut self, key: u8) {
+        self.cpu.mem_bus.poke(KEY_ADDR, key);
+    }
+
+    pub fn step_frame(&mut self) {
+        let start = self.cpu.tot_cycles;
+        while self.cpu.tot_cycles.wrapping_sub(start) < CYCLES_PER_FRAME {
+            let random = self.rng.gen();
+            self.cpu.mem_bus.poke(RANDOM_ADDR, random);
+            self.cpu.step();
+        }
+    }
+
+    //RGB pixels of the 32x32 display, row by row
+    pub fn pixels(&self) -> [u8; DISPLAY_SIZE * DISPLAY_SIZE * 3] {
+        let mut pixels = [0; DISPLAY_SIZE * DISPLAY_SIZE * 3];
+        for (i, rgb) in pixels.chunks_exact_mut(3).enumerate() {
+            let (r, g, b) = COLOURS[(self.cpu.mem_bus.peek(DISPLAY + i as u16) & 0x0F) as usize];
+            rgb.copy_from_slice(&[r, g, b]);
+        }
+        pixels
+    }
+
+    //draws the display scaled up on a black background
+    pub fn render(&self, frame: &mut Frame) {
+        frame.clear((0, 0, 0));
+        let pixels = self.pixels();
+        for (i, rgb) in pixels.chunks_exact(3).enumerate() {
+            let (x, y) = (i % DISPLAY_SIZE, i / DISPLAY_SIZE);
+            for dy in 0..PIXEL_SCALE {
+                for dx in 0..PIXEL_SCALE {
+                    frame.set_pixel(
+                        OFFSET_X + x * PIXEL_SCALE + dx,
+                        OFFSET_Y + y * PIXEL_SCALE + dy,
+                        (rgb[0], rgb[1], rgb[2]),
+                    );
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snake_runs_and_draws_for_a_few_frames() {
+        let mut demo = Demo::new(DemoProgram::Snake, 1).unwrap();
+        demo.set_key(b'd');
+        for _ in 0..10 {
+            demo.step_frame();
+        }
+        //still in the game loop rather than parked on the idle loop after a BRK
+        assert!((0x0600..0x0600 + SNAKE_CODE.len() as u16).contains(&demo.cpu.reg_pc));
+        //the snake is white, the apple some other colour
+        let pixels = demo.pixels();
+        let white = pixels.chunks_exact(3).filter(|rgb| *rgb == [0xFF, 0xFF, 0xFF]).count();
+        assert!(white >= 2, "{} white pixels", white);
+        //and it moves
+        for _ in 0..10 {
+            demo.step_frame();
+        }
+        assert!(demo.pixels() != pixels);
+        let mut frame = Frame::new((0x12, 0x34, 0x56));
+        demo.render(&mut frame);
+        assert_eq!(frame.data[..3], [0, 0, 0]);
+    }
+}
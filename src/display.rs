@@ -1,8 +1,9 @@
-use crate::ppu::PPU;
-use crate::rom::Mirroring;
+use crate::ppu::{PPU, LineRegisters, SpriteInfo};
 
 const WIDTH: usize = 256;
 const HEIGHT: usize = 240;
+//scanlines a typical TV hides at both the top and the bottom of the picture
+pub const OVERSCAN_LINES: usize = 8;
 
 pub struct Rect {
     pub x1: usize,
@@ -11,6 +12,29 @@ pub struct Rect {
     pub y2: usize,
 }
 
+//what show_tile draws through: pixels outside the viewport are clipped, the rest shifted by the scroll
+pub struct TileView {
+    pub viewport: Rect,
+    pub x_scroll: isize,
+    pub y_scroll: isize,
+}
+
+impl TileView {
+    //the whole screen, unscrolled
+    pub fn screen() -> Self {
+        TileView {
+            viewport: Rect {
+                x1: 0,
+                y1: 0,
+                x2: WIDTH,
+                y2: HEIGHT,
+            },
+            x_scroll: 0,
+            y_scroll: 0,
+        }
+    }
+}
+
 pub struct Frame {
     pub data: [u8; WIDTH * HEIGHT * 4],
  }
@@ -18,307 +42,898 @@ pub struct Frame {
  impl Frame {
  
     pub fn new(color: (u8, u8, u8)) -> Self {
-        let mut data = [0x00; WIDTH * HEIGHT * 4];
-        for i in 0..(WIDTH * HEIGHT) {
-            data[i * 4 + 0] = color.0;
-            data[i * 4 + 1] = color.1;
-            data[i * 4 + 2] = color.2;
-            data[i * 4 + 3] = 0xFF;
-        }
-        Frame {
-            data: data,
+        let mut frame = Frame {
+            data: [0x00; WIDTH * HEIGHT * 4],
+        };
+        frame.clear(color);
+        frame
+    }
+
+    //fills the existing buffer with an opaque backdrop colour
+    pub fn clear(&mut self, color: (u8, u8, u8)) {
+        let pixel = [color.0, color.1, color.2, 0xFF];
+        for chunk in self.data.chunks_exact_mut(4) {
+            chunk.copy_from_slice(&pixel);
         }
     }
  
+    //fills one row of the frame
+    pub fn fill_line(&mut self, y: usize, color: (u8, u8, u8)) {
+        let pixel = [color.0, color.1, color.2, 0xFF];
+        let row = y * 4 * WIDTH;
+        if let Some(row) = self.data.get_mut(row..row + 4 * WIDTH) {
+            for chunk in row.chunks_exact_mut(4) {
+                chunk.copy_from_slice(&pixel);
+            }
+        }
+    }
+
+    //pixels past the right or bottom edge are dropped rather than wrapping onto the next row
     #[inline(always)]
     pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
-        static mut base: usize = 0;
-        unsafe {
-            base = y * 4 * WIDTH + x * 4;
-            if base < self.data.len() {
-                self.data[base] = rgb.0;
-                self.data[base + 1] = rgb.1;
-                self.data[base + 2] = rgb.2;
-            }
+        if x >= WIDTH || y >= HEIGHT {
+            return;
         }
+        let base = y * 4 * WIDTH + x * 4;
+        self.data[base] = rgb.0;
+        self.data[base + 1] = rgb.1;
+        self.data[base + 2] = rgb.2;
     }
  }
+
+/*
+Layers:
+
+Background and sprites are each drawn into a layer of their own and
+composited into the frame in one pass. A layer is RGBA, its alpha
+byte is the opacity mask (0xFF where a pixel was drawn, 0x00 where
+it is transparent). The sprite layer also keeps which of its pixels
+have the priority bit set, putting them behind the background. The
+layers are kept after rendering for the debug views.
+*/
+pub struct Layers {
+    pub background: Vec<u8>,
+    pub sprites: Vec<u8>,
+    pub sprite_behind: Vec<bool>,
+}
+
+impl Default for Layers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Layers {
+    pub fn new() -> Self {
+        Layers {
+            background: vec![0x00; WIDTH * HEIGHT * 4],
+            sprites: vec![0x00; WIDTH * HEIGHT * 4],
+            sprite_behind: vec![false; WIDTH * HEIGHT],
+        }
+    }
+
+    //makes one row of both layers transparent
+    pub fn clear_line(&mut self, y: usize) {
+        let row = y * WIDTH;
+        self.background[row * 4..(row + WIDTH) * 4].fill(0x00);
+        self.sprites[row * 4..(row + WIDTH) * 4].fill(0x00);
+        self.sprite_behind[row..row + WIDTH].fill(false);
+    }
+
+    pub fn set_background(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+        if x < WIDTH && y < HEIGHT {
+            let base = (y * WIDTH + x) * 4;
+            self.background[base..base + 4].copy_from_slice(&[rgb.0, rgb.1, rgb.2, 0xFF]);
+        }
+    }
+
+    //sprites off the right edge are cut off rather than wrapping onto the next line
+    pub fn set_sprite(&mut self, x: usize, y: usize, rgb: (u8, u8, u8), behind: bool) {
+        if x < WIDTH && y < HEIGHT {
+            let base = (y * WIDTH + x) * 4;
+            self.sprites[base..base + 4].copy_from_slice(&[rgb.0, rgb.1, rgb.2, 0xFF]);
+            self.sprite_behind[y * WIDTH + x] = behind;
+        }
+    }
+
+    /*
+    Composites one line into the frame. An opaque sprite pixel wins
+    unless it is behind an opaque background pixel, then comes the
+    background, then the backdrop. PPUMASK bits 1 and 2 hide the
+    background and sprites in the leftmost 8 pixels.
+    */
+    pub fn composite_line(&self, frame: &mut Frame, y: usize, backdrop: (u8, u8, u8), mask: u8) {
+        for x in 0..WIDTH {
+            let i = y * WIDTH + x;
+            let background = self.background[i * 4 + 3] != 0 && (x >= 8 || mask & 0x02 != 0);
+            let sprite = self.sprites[i * 4 + 3] != 0 && (x >= 8 || mask & 0x04 != 0);
+            let layer = if sprite && !(self.sprite_behind[i] && background) {
+                &self.sprites
+            } else if background {
+                &self.background
+            } else {
+                frame.set_pixel(x, y, backdrop);
+                continue;
+            };
+            frame.set_pixel(x, y, (layer[i * 4], layer[i * 4 + 1], layer[i * 4 + 2]));
+        }
+    }
+}
+
+/*
+Attribute Table:
+
+Each attribute byte covers a 4x4 tile (32x32 pixel) cell of the
+nametable, eight cells per row. The bottom row of cells is only
+half used since the nametable is 30 tiles tall. Each 2x2 tile
+quadrant of a cell gets two bits -
+    7  bit  0
+    ---- ----
+    BRBL TRTL
+    tiles 0-1 of a row/column are the left/top half, 2-3 the right/bottom
+*/
  #[inline(always)]
  pub fn bg_palette(ppu: &PPU, name_table_offset: usize, tile_column: usize, tile_row: usize) -> [u8;4] {
     let attr_table_idx: usize = tile_row / 4 * 8 + tile_column / 4;
     let attr_byte: u8 = ppu.vram[0x03C0 + attr_table_idx + name_table_offset];
 
-    let palette_idx: u8 = match ((tile_column %4) >> 1) + ((tile_row % 4) & 2) {
-        0 => attr_byte & 0b11,
-        1 => (attr_byte >> 2) & 0b11,
-        2 => (attr_byte >> 4) & 0b11,
-        3 => (attr_byte >> 6) & 0b11,
-        _ => panic!("should not happen"),
-    };
+    //bit 1 of the column picks right over left, bit 1 of the row picks bottom over top
+    let quadrant = ((tile_row % 4) & 2) | ((tile_column % 4) >> 1);
+    let palette_idx: u8 = (attr_byte >> (quadrant * 2)) & 0b11;
     let palette_start: usize = (1 + palette_idx * 4) as usize;
     [
         ppu.palette_table[0], 
-        ppu.palette_table[palette_start+1], 
         ppu.palette_table[palette_start], 
+        ppu.palette_table[palette_start+1], 
         ppu.palette_table[palette_start+2]
     ]
  }
  #[inline(always)]
  pub fn sprite_palette(ppu: &PPU, palette_idx: u8) -> [u8; 4] {
     let start: usize = 0x11 + (palette_idx * 4) as usize;
+    //colour 0 is transparent, the backdrop stands in for it
     [
-        0,
-        ppu.palette_table[start + 1],
+        ppu.palette_table[0],
         ppu.palette_table[start],
+        ppu.palette_table[start + 1],
         ppu.palette_table[start + 2],
     ]
 }
 #[inline(always)]
- pub fn show_tile(frame: &mut Frame, chr_rom: &Vec<u8>, bank: usize, tile_n: usize, viewport: Rect, x_offset: usize, y_offset: usize, x_scroll: isize, y_scroll: isize, palette: [u8; 4]) {
+ pub fn show_tile(frame: &mut Frame, ppu: &PPU, bank: usize, tile_n: usize, (x_offset, y_offset): (usize, usize), view: &TileView, palette: [u8; 4]) {
     if bank > 1 {
         return;
     }
  
-    let tile: &[u8] = &chr_rom[(bank * 0x1000 + tile_n * 16)..=(bank * 0x1000 + tile_n * 16 + 15)];
-
-    static mut upper: u8 = 0;
-    static mut lower: u8 = 0;
-    
-    static mut value: u8 = 0;
-
-    static mut rgb: (u8, u8, u8) = SYSTEM_PALLETE[0];
+    let tile: &[u8] = ppu.chr_tile(bank, tile_n);
 
     for y in 0..=7 {
-        unsafe {
-            upper = tile[y];
-            lower = tile[y + 8];
-        }
+        //plane 0 holds bit 0 of each pixel, plane 1 (8 bytes on) holds bit 1
+        let mut upper = tile[y + 8];
+        let mut lower = tile[y];
  
         for x in (0..=7).rev() {
-            unsafe {
-                value = (1 & upper) << 1 | (1 & lower);
-                upper >>= 1;
-                lower >>= 1;
-                rgb = SYSTEM_PALLETE[palette[value as usize] as usize];
-                if (
-                    x_offset + x >= viewport.x1 && 
-                    x_offset + x <  viewport.x2 && 
-                    y_offset + y >= viewport.y1 && 
-                    y_offset + y <  viewport.y2 &&
-                    value > 0
-                 ) {
-                    frame.set_pixel(((x_offset + x) as isize - x_scroll) as usize, ((y_offset + y) as isize - y_scroll) as usize, rgb);
-                }
+            let value = (1 & upper) << 1 | (1 & lower);
+            upper >>= 1;
+            lower >>= 1;
+            let rgb = ppu.system_palette[palette[value as usize] as usize];
+            if x_offset + x >= view.viewport.x1 && 
+                x_offset + x <  view.viewport.x2 && 
+                y_offset + y >= view.viewport.y1 && 
+                y_offset + y <  view.viewport.y2 &&
+                value > 0
+            {
+                frame.set_pixel(((x_offset + x) as isize - view.x_scroll) as usize, ((y_offset + y) as isize - view.y_scroll) as usize, rgb);
             }
         }
     }
  }
+ //one 8x8 tile of a sprite as it lands on screen, 8x16 sprites are two of these
+ pub struct SpriteTile {
+    pub bank: usize,
+    pub tile: usize,
+    pub x: usize,
+    pub y: usize,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    pub behind: bool,
+    pub palette: [u8; 4],
+ }
+
  #[inline(always)]
- pub fn show_sprite_tile(frame: &mut Frame, chr_rom: &Vec<u8>, bank: usize, tile_n: usize, x_offset: usize, y_offset: usize, flip_vertical: bool, flip_horizontal: bool, palette: [u8; 4], scanline_start: usize, scanline_stop: usize) {
-    if bank > 1 {
+ pub fn show_sprite_tile(layers: &mut Layers, ppu: &PPU, sprite: &SpriteTile, scanline_start: usize, scanline_stop: usize) {
+    if sprite.bank > 1 {
         return;
     }
  
-    let tile = &chr_rom[(bank * 0x1000 + tile_n * 16)..=(bank * 0x1000 + tile_n * 16 + 15)];
+    let tile = ppu.chr_tile(sprite.bank, sprite.tile);
+    let x_offset = sprite.x;
 
-    
-    for y in 0..=7 {
-        let mut upper = tile[y];
-        let mut lower = tile[y + 8];
- 
-        if y_offset + y >= scanline_start &&  y_offset + y <= scanline_stop {
-            for x in (0..=7).rev() {
-                let value = (1 & upper) << 1 | (1 & lower);
-                upper >>= 1;
-                lower >>= 1;
-                let rgb = SYSTEM_PALLETE[palette[value as usize] as usize];
-                if value > 0 {
-                    match (flip_horizontal, flip_vertical) {
-                        (false, false) => frame.set_pixel(x_offset + x, y_offset + y, rgb),
-                        (true, false) => frame.set_pixel(x_offset + 7 - x, y_offset + y, rgb),
-                        (false, true) => frame.set_pixel(x_offset + x, y_offset + 7 - y, rgb),
-                        (true, true) => frame.set_pixel(x_offset + 7 - x, y_offset + 7 - y, rgb),
-                    }
-                }
+    //every pixel lands on the line being drawn, flipping only picks which tile row it comes from
+    for line in scanline_start.max(sprite.y)..=scanline_stop.min(sprite.y + 7) {
+        let row = if sprite.flip_vertical { 7 - (line - sprite.y) } else { line - sprite.y };
+        let mut upper = tile[row + 8];
+        let mut lower = tile[row];
+
+        for x in (0..=7).rev() {
+            let value = (1 & upper) << 1 | (1 & lower);
+            upper >>= 1;
+            lower >>= 1;
+            let rgb = ppu.system_palette[sprite.palette[value as usize] as usize];
+            if value > 0 {
+                let x = if sprite.flip_horizontal { x_offset + 7 - x } else { x_offset + x };
+                layers.set_sprite(x, line, rgb, sprite.behind);
             }
         }
     }
  }
 
- pub fn render(ppu: &PPU, frame: &mut Frame, scanline_start: usize, scanline_stop: usize) {
-    
-    let scroll_x = ppu.scroll_x as usize;
-    let scroll_y = ppu.scroll_y as usize;
-    let bg_bank = ((ppu.ctrl & 0x10) >> 4) as usize;
-
-    let (main_nametable, second_nametable) = match (&ppu.mirroring, (ppu.v & 0x0C00) >> 10) {
-        (Mirroring::FOUR_SCREEN,_) => {
-            (0x0000, 0x0000)
-        },
-        (_, 0x00) | (Mirroring::VERTICAL, 0x02) |
-        (Mirroring::HORIZONTAL, 0x01) => {
-            (0x0000, 0x0400)
-        },
-        (Mirroring::VERTICAL, 0x01) | (_, 0x03) |
-        (Mirroring::HORIZONTAL, 0x02) => {
-            (0x0400, 0x0000)
-        },(_,_) => {
-            (0x0000, 0x0000)
-        },
+ //draws the sprite at OAM offset `i`, both halves of it in 8x16 mode
+ fn draw_sprite(ppu: &PPU, layers: &mut Layers, i: usize, ctrl: u8, scanline_start: usize, scanline_stop: usize) {
+    let info = SpriteInfo::from_oam(&ppu.oam_data[i..i + 4]);
+    let mut sprite = SpriteTile {
+        bank: ((ctrl & 0x08) >> 3) as usize,
+        tile: info.tile as usize,
+        x: info.x as usize,
+        y: info.y as usize,
+        flip_horizontal: info.flip_horizontal,
+        flip_vertical: info.flip_vertical,
+        behind: info.behind_background,
+        palette: sprite_palette(ppu, info.palette),
     };
-    for i in (0..ppu.oam_data.len()).step_by(4).rev() {
-        if ppu.oam_data[i + 2] & 0x20 != 0 {
-            let tile_idx = ppu.oam_data[i + 1] as u16;
-            let tile_x = ppu.oam_data[i + 3] as usize;
-            let tile_y = ppu.oam_data[i] as usize;
 
-            let flip_vertical = ppu.oam_data[i + 2] >> 7 & 1 == 1;
-            let flip_horizontal = ppu.oam_data[i + 2] >> 6 & 1 == 1;
+    if ctrl & 0x20 == 0 {
+        show_sprite_tile(layers, ppu, &sprite, scanline_start, scanline_stop);
+    } else {
+        //the top half is the even tile, flipping vertically swaps the halves
+        let tile_idx = sprite.tile;
+        let (top, bottom) = if info.flip_vertical {
+            (tile_idx | 0x01, tile_idx & 0xFE)
+        } else {
+            (tile_idx & 0xFE, tile_idx | 0x01)
+        };
+        sprite.bank = tile_idx & 0x01;
+        sprite.tile = top;
+        show_sprite_tile(layers, ppu, &sprite, scanline_start, scanline_stop);
+        sprite.tile = bottom;
+        sprite.y += 8;
+        show_sprite_tile(layers, ppu, &sprite, scanline_start, scanline_stop);
+    }
+ }
 
-            let palette_idx = ppu.oam_data[i + 2] & 0x03;
-            let sprite_palette = sprite_palette(ppu, palette_idx);
+ /*
+ Background pixels for one scanline, scrolled as the registers
+ latched for that line say. The four nametables make up a 512x480
+ plane that wraps both ways, each pixel is looked up in it through
+ the PPU's own mirroring so every layout picks the same memory the
+ CPU writes to.
+ */
+ fn draw_background_line(ppu: &PPU, layers: &mut Layers, line: usize, regs: &LineRegisters) {
+    let bank = ((regs.ctrl & 0x10) >> 4) as usize;
+    let name_table = regs.name_table as usize;
+    let y = (line + regs.scroll_y as usize + 240 * (name_table >> 1)) % 480;
+    let tile_row = (y % 240) / 8;
+    let fine_y = y % 8;
 
-            if ppu.ctrl & 0x20 == 0 {
-                let sprite_bank = ((ppu.ctrl & 0x08) >> 3) as usize;
+    for screen_x in 0..256 {
+        let x = (screen_x + regs.scroll_x as usize + 256 * (name_table & 1)) % 512;
+        let offset = ppu.mirror_vram_addr((0x2000 + ((y / 240) * 2 + x / 256) * 0x400) as u16) as usize;
+        let tile_column = (x % 256) / 8;
+        let tile = ppu.chr_tile(bank, ppu.vram[offset + tile_row * 32 + tile_column] as usize);
 
-                show_sprite_tile(frame, &ppu.chr_rom, sprite_bank, tile_idx as usize, tile_x, tile_y, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
-            } else {
-                if flip_vertical {
-                    show_sprite_tile(frame, &ppu.chr_rom, tile_idx as usize & 0x01, tile_idx as usize | 0x01, tile_x, tile_y, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
-                    show_sprite_tile(frame, &ppu.chr_rom, tile_idx as usize & 0x01, tile_idx as usize & 0xFE, tile_x, tile_y + 8, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
-                } else {
-                    show_sprite_tile(frame, &ppu.chr_rom, tile_idx as usize & 0x01, tile_idx as usize & 0xFE, tile_x, tile_y, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
-                    show_sprite_tile(frame, &ppu.chr_rom, tile_idx as usize & 0x01, tile_idx as usize | 0x01, tile_x, tile_y + 8, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
+        //plane 0 holds bit 0 of each pixel, plane 1 (8 bytes on) holds bit 1
+        let bit = 7 - x % 8;
+        let value = ((tile[fine_y + 8] >> bit) & 1) << 1 | ((tile[fine_y] >> bit) & 1);
+        if value > 0 {
+            let palette = bg_palette(ppu, offset, tile_column, tile_row);
+            layers.set_background(screen_x, line, ppu.system_palette[palette[value as usize] as usize]);
+        }
+    }
+ }
+
+ /*
+ PPUMASK Colour Effects:
+
+ Greyscale keeps only the brightness (upper) bits of the palette
+ value. Each emphasis bit (0x20 red, 0x40 green, 0x80 blue) darkens
+ the other two channels, approximated here as a 3/4 scale of the
+ palette's RGB.
+ */
+ pub fn masked_color(ppu: &PPU, value: u8, mask: u8) -> (u8, u8, u8) {
+    let value = if mask & 0x01 != 0 { value & 0x30 } else { value };
+    let (mut r, mut g, mut b) = ppu.system_palette[(value & 0x3F) as usize];
+    let dim = |channel: u8| (channel as u16 * 3 / 4) as u8;
+    if mask & 0x20 != 0 {
+        g = dim(g);
+        b = dim(b);
+    }
+    if mask & 0x40 != 0 {
+        r = dim(r);
+        b = dim(b);
+    }
+    if mask & 0x80 != 0 {
+        r = dim(r);
+        g = dim(g);
+    }
+    (r, g, b)
+ }
+
+ /*
+ Draws each scanline with the PPUCTRL/PPUMASK/scroll values that
+ were live when it started, so mid-frame writes split the picture
+ on the right line. Sprites are evaluated once per scanline, at
+ most eight per line unless the sprite limit is off.
+ They are drawn into the sprite layer in reverse OAM order, so the
+ lowest numbered sprite owns each pixel, priority bit included. As
+ on hardware, a sprite behind the background therefore also hides
+ higher numbered sprites in front of it.
+ */
+ pub fn render(ppu: &PPU, layers: &mut Layers, frame: &mut Frame, scanline_start: usize, scanline_stop: usize) {
+    for line in scanline_start..scanline_stop {
+        let regs = &ppu.line_registers[line];
+        let show_background = regs.mask & 0x08 != 0;
+        let show_sprites = regs.mask & 0x10 != 0;
+        let height = if regs.ctrl & 0x20 != 0 { 16 } else { 8 };
+        let sprites = ppu.evaluate_sprites_sized(line, height, ppu.sprite_limit);
+
+        layers.clear_line(line);
+        if show_background {
+            draw_background_line(ppu, layers, line, regs);
+        }
+        if show_sprites {
+            for &i in sprites.iter().rev() {
+                draw_sprite(ppu, layers, i, regs.ctrl, line, line);
+            }
+        }
+        layers.composite_line(frame, line, masked_color(ppu, regs.backdrop, regs.mask), regs.mask);
+    }
+ }
+
+/*
+Debug Overlay:
+
+A post-pass over a finished frame (a copy of it, the emulated
+picture is never touched) that outlines every sprite on screen in
+red and draws a yellow line on each scanline where the scroll or
+nametable differs from the line above, i.e. at every split.
+*/
+pub const OVERLAY_SPRITE_COLOR: (u8, u8, u8) = (0xFF, 0x00, 0x00);
+pub const OVERLAY_SPLIT_COLOR: (u8, u8, u8) = (0xFF, 0xFF, 0x00);
+
+pub fn draw_overlay(ppu: &PPU, frame: &mut Frame) {
+    let height = if ppu.ctrl & 0x20 != 0 { 16 } else { 8 };
+    for sprite in ppu.sprites() {
+        //the same top line the sprite layer draws from, OAM Y $F0 and up never shows
+        let top = sprite.y as usize;
+        if top >= HEIGHT {
+            continue;
+        }
+        let (left, right) = (sprite.x as usize, (sprite.x as usize + 7).min(WIDTH - 1));
+        let bottom = (top + height - 1).min(HEIGHT - 1);
+        for x in left..=right {
+            frame.set_pixel(x, top, OVERLAY_SPRITE_COLOR);
+            frame.set_pixel(x, bottom, OVERLAY_SPRITE_COLOR);
+        }
+        for y in top..=bottom {
+            frame.set_pixel(left, y, OVERLAY_SPRITE_COLOR);
+            frame.set_pixel(right, y, OVERLAY_SPRITE_COLOR);
+        }
+    }
+    for (y, pair) in ppu.line_registers.windows(2).enumerate() {
+        let (above, line) = (&pair[0], &pair[1]);
+        if (line.scroll_x, line.scroll_y, line.name_table) != (above.scroll_x, above.scroll_y, above.name_table) {
+            for x in 0..WIDTH {
+                frame.set_pixel(x, y + 1, OVERLAY_SPLIT_COLOR);
+            }
+        }
+    }
+}
+
+//the RGBA rows of a frame buffer left visible with overscan cropping (256x224)
+pub fn crop_overscan(data: &[u8]) -> &[u8] {
+    &data[OVERSCAN_LINES * WIDTH * 4..(HEIGHT - OVERSCAN_LINES) * WIDTH * 4]
+}
+
+/*
+Debug Views:
+
+Alternate screens that replace the game picture while debugging
+rendering. The pattern table view shows all 512 tiles of both
+pattern tables, the nametable view shows all four logical
+nametables (scaled down by half to fit the screen). The layer views
+show the background or sprite layer of the last frame on its own.
+*/
+pub enum DebugView {
+    PatternTables,
+    Nametables,
+    BackgroundLayer,
+    SpriteLayer,
+}
+
+pub fn render_debug_view(ppu: &PPU, layers: &Layers, view: &DebugView) -> Frame {
+    match view {
+        DebugView::PatternTables => {
+            //both tables side by side, centered vertically
+            let mut frame = Frame::new(ppu.system_palette[ppu.palette_table[0] as usize]);
+            let y_offset = (HEIGHT - 128) / 2;
+            for table in 0..2 {
+                let pattern_table = render_pattern_table(ppu, table);
+                for y in 0..128 {
+                    let src = y * WIDTH * 4;
+                    let dst = ((y + y_offset) * WIDTH + table * 128) * 4;
+                    frame.data[dst..dst + 128 * 4].copy_from_slice(&pattern_table.data[src..src + 128 * 4]);
+                }
+            }
+            frame
+        },
+        DebugView::Nametables => {
+            let atlas = render_nametables(ppu);
+            let mut frame = Frame::new((0, 0, 0));
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let base = (y * 2 * WIDTH * 2 + x * 2) * 4;
+                    frame.set_pixel(x, y, (atlas[base], atlas[base + 1], atlas[base + 2]));
                 }
             }
+            frame
+        },
+        DebugView::BackgroundLayer => render_layer(&layers.background),
+        DebugView::SpriteLayer => render_layer(&layers.sprites),
+    }
+}
+
+//one layer of the last rendered frame on black, without the other layer or the backdrop
+fn render_layer(layer: &[u8]) -> Frame {
+    let mut frame = Frame::new((0, 0, 0));
+    for (i, pixel) in layer.chunks_exact(4).enumerate() {
+        if pixel[3] != 0 {
+            frame.set_pixel(i % WIDTH, i / WIDTH, (pixel[0], pixel[1], pixel[2]));
         }
     }
+    frame
+}
+
+//draws the 256 tiles of a pattern table as a 16x16 grid in the top left 128x128 pixels
+pub fn render_pattern_table(ppu: &PPU, table: usize) -> Frame {
+    let mut frame = Frame::new(ppu.system_palette[ppu.palette_table[0] as usize]);
+    let palette = [
+        ppu.palette_table[0],
+        ppu.palette_table[1],
+        ppu.palette_table[2],
+        ppu.palette_table[3],
+    ];
+    let view = TileView::screen();
+    for tile_n in 0..256 {
+        show_tile(&mut frame, ppu, table, tile_n, ((tile_n % 16) * 8, (tile_n / 16) * 8), &view, palette);
+    }
+    frame
+}
 
+//draws one logical nametable ($2000, $2400, $2800, $2C00) without scrolling
+pub fn render_nametable(ppu: &PPU, name_table: usize) -> Frame {
+    let mut frame = Frame::new(ppu.system_palette[ppu.palette_table[0] as usize]);
+    let bg_bank = ((ppu.ctrl & 0x10) >> 4) as usize;
+    let name_table_offset = ppu.mirror_vram_addr(0x2000 + (name_table as u16) * 0x400) as usize;
+    let view = TileView::screen();
     for i in 0..0x03C0 {
-        let tile_n = ppu.vram[i + main_nametable] as usize;
+        let tile_n = ppu.vram[i + name_table_offset] as usize;
         let x = i % 32_usize;
         let y = i / 32_usize;
-        let palette = bg_palette(ppu, main_nametable, x, y);
-
-        if y * 8 >= scanline_start && y * 8 < scanline_stop {
-        
-            show_tile(
-                frame, 
-                &ppu.chr_rom, 
-                bg_bank, 
-                tile_n, 
-                Rect {
-                    x1: scroll_x,
-                    y1: scroll_y,
-                    x2: 256,
-                    y2: 240,
-                },
-                x * 8,
-                y * 8,
-                scroll_x as isize, 
-                scroll_y as isize, 
-                palette
-            );
-        }
-    }
-    if scroll_x > 0 {
-        for i in 0..0x03C0 {
-            let tile_n = ppu.vram[i + second_nametable] as usize;
-            let x = i % 32_usize;
-            let y = i / 32_usize;
-            let palette = bg_palette(ppu, second_nametable, x, y);
-            
-            if y * 8 >= scanline_start && y * 8 < scanline_stop {
-                show_tile(
-                    frame, 
-                    &ppu.chr_rom, 
-                    bg_bank, 
-                    tile_n, 
-                    Rect {
-                        x1: 0,
-                        y1: 0,
-                        x2: 256 + scroll_x,
-                        y2: 240 + scroll_y,
-                    },
-                    x * 8 + 256,
-                    y * 8 + 0,
-                    scroll_x as isize, 
-                    0, 
-                    palette
-                );
+        show_tile(&mut frame, ppu, bg_bank, tile_n, (x * 8, y * 8), &view, bg_palette(ppu, name_table_offset, x, y));
+    }
+    frame
+}
+
+//lays out all four nametables in a 512x480 RGBA buffer, in the same 2x2 arrangement as the address space
+pub fn render_nametables(ppu: &PPU) -> Vec<u8> {
+    let mut atlas = vec![0xFF; WIDTH * 2 * HEIGHT * 2 * 4];
+    for name_table in 0..4 {
+        let frame = render_nametable(ppu, name_table);
+        let x_offset = (name_table % 2) * WIDTH;
+        let y_offset = (name_table / 2) * HEIGHT;
+        for y in 0..HEIGHT {
+            let src = y * WIDTH * 4;
+            let dst = ((y + y_offset) * WIDTH * 2 + x_offset) * 4;
+            atlas[dst..dst + WIDTH * 4].copy_from_slice(&frame.data[src..src + WIDTH * 4]);
+        }
+    }
+    atlas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::SYSTEM_PALLETE;
+    use crate::rom::Mirroring;
+
+    const BACKDROP: u8 = 0x0F;
+    const BG_COLOR: u8 = 0x16;
+    const SPRITE_COLOR: u8 = 0x30;
+
+    /*
+    Tile 1 is solid colour 1 and tile 2 solid colour 2. The left half
+    of the screen is background tile 1 (BG_COLOR), the right half is
+    transparent. Sprite palette 0 draws colour 2 as SPRITE_COLOR.
+    Every line renders background and sprites, left column included,
+    and all sprites start out hidden below the screen.
+    */
+    fn scene() -> PPU {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        chr[0x28..0x30].fill(0xFF);
+        let mut ppu = PPU::new(chr, false, Mirroring::HORIZONTAL);
+        ppu.set_warm_up(false);
+        for row in 0..30 {
+            ppu.vram[row * 32..row * 32 + 16].fill(1);
+        }
+        ppu.palette_table[0x00] = BACKDROP;
+        ppu.palette_table[0x01] = BG_COLOR;
+        ppu.palette_table[0x12] = SPRITE_COLOR;
+        ppu.oam_data.fill(0xFF);
+        for line in ppu.line_registers.iter_mut() {
+            line.mask = 0x1E;
+            line.backdrop = BACKDROP;
+        }
+        ppu
+    }
+
+    //places sprite `n` (y, tile, attributes, x)
+    fn set_sprite(ppu: &mut PPU, n: usize, sprite: [u8; 4]) {
+        ppu.oam_data[n * 4..n * 4 + 4].copy_from_slice(&sprite);
+    }
+
+    fn render_frame(ppu: &PPU) -> Frame {
+        let mut frame = Frame::new((0, 0, 0));
+        render(ppu, &mut Layers::new(), &mut frame, 0, HEIGHT);
+        frame
+    }
+
+    fn pixel(frame: &Frame, x: usize, y: usize) -> (u8, u8, u8) {
+        let base = (y * WIDTH + x) * 4;
+        (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+    }
+
+    #[test]
+    fn clearing_a_frame_matches_a_new_one() {
+        let mut frame = Frame::new((1, 2, 3));
+        frame.set_pixel(10, 20, (0xFF, 0xFF, 0xFF));
+        frame.clear((0x40, 0x50, 0x60));
+        assert!(frame.data == Frame::new((0x40, 0x50, 0x60)).data);
+    }
+
+    #[test]
+    fn sprite_priority_against_the_background() {
+        let mut ppu = scene();
+        //in front of the background, behind it, and behind over transparent background
+        set_sprite(&mut ppu, 0, [16, 2, 0x00, 8]);
+        set_sprite(&mut ppu, 1, [16, 2, 0x20, 40]);
+        set_sprite(&mut ppu, 2, [16, 2, 0x20, 200]);
+        //a lower numbered sprite behind the background also hides the sprite in front of it
+        set_sprite(&mut ppu, 3, [48, 2, 0x20, 40]);
+        set_sprite(&mut ppu, 4, [48, 1, 0x00, 40]);
+        set_sprite(&mut ppu, 5, [48, 2, 0x20, 200]);
+        set_sprite(&mut ppu, 6, [48, 1, 0x00, 200]);
+        let frame = render_frame(&ppu);
+        let color = |value: u8| SYSTEM_PALLETE[value as usize];
+        assert_eq!(pixel(&frame, 10, 19), color(SPRITE_COLOR));
+        assert_eq!(pixel(&frame, 42, 19), color(BG_COLOR));
+        assert_eq!(pixel(&frame, 202, 19), color(SPRITE_COLOR));
+        assert_eq!(pixel(&frame, 42, 51), color(BG_COLOR));
+        assert_eq!(pixel(&frame, 202, 51), color(SPRITE_COLOR));
+        assert_eq!(pixel(&frame, 100, 100), color(BG_COLOR));
+        assert_eq!(pixel(&frame, 250, 100), color(BACKDROP));
+    }
+
+    //nine sprites side by side on lines 100-107, over the transparent half
+    fn nine_sprites() -> PPU {
+        let mut ppu = scene();
+        for n in 0..9 {
+            set_sprite(&mut ppu, n, [100, 2, 0x00, 130 + 10 * n as u8]);
+        }
+        ppu
+    }
+
+    fn sprites_drawn(frame: &Frame) -> usize {
+        (0..9).filter(|n| pixel(frame, 132 + 10 * n, 103) == SYSTEM_PALLETE[SPRITE_COLOR as usize]).count()
+    }
+
+    #[test]
+    fn only_eight_sprites_render_per_line() {
+        let mut ppu = nine_sprites();
+        let frame = render_frame(&ppu);
+        assert_eq!(sprites_drawn(&frame), 8);
+        //the ninth (highest numbered) sprite is the one dropped
+        assert_eq!(pixel(&frame, 212, 103), SYSTEM_PALLETE[BACKDROP as usize]);
+
+        ppu.mask_write(0x18);
+        while ppu.scanlines < 100 {
+            ppu.step_dot();
+        }
+        assert_eq!(ppu.stat & 0x20, 0);
+        ppu.step_scanline();
+        assert_eq!(ppu.stat & 0x20, 0x20);
+    }
+
+    #[test]
+    fn all_sprites_render_with_the_limit_off() {
+        let mut ppu = nine_sprites();
+        ppu.sprite_limit = false;
+        assert_eq!(sprites_drawn(&render_frame(&ppu)), 9);
+
+        //the flag is console state, it is set the same either way
+        ppu.mask_write(0x18);
+        while ppu.scanlines <= 100 {
+            ppu.step_dot();
+        }
+        assert_eq!(ppu.stat & 0x20, 0x20);
+        ppu.step_frame();
+        assert_eq!(ppu.stat & 0x20, 0);
+    }
+
+    #[test]
+    fn palettes_read_their_own_four_entries() {
+        let mut ppu = scene();
+        for (i, entry) in ppu.palette_table.iter_mut().enumerate() {
+            *entry = 0x20 + i as u8;
+        }
+        //top left, top right, bottom left, bottom right quadrants use palettes 0-3
+        ppu.vram[0x3C0] = 0b11_10_01_00;
+        for (p, (column, row)) in [(0, 0), (2, 0), (0, 2), (2, 2)].into_iter().enumerate() {
+            let start = 0x21 + 4 * p as u8;
+            assert_eq!(bg_palette(&ppu, 0, column, row), [0x20, start, start + 1, start + 2]);
+            assert_eq!(sprite_palette(&ppu, p as u8), [0x20, start + 0x10, start + 0x11, start + 0x12]);
+        }
+    }
+
+    #[test]
+    fn tile_colours_follow_the_palette_order() {
+        let mut ppu = scene();
+        //tile 3, every row: colours 0, 1, 2, 3, 0, 1, 2, 3
+        ppu.chr_mut()[0x30..0x38].fill(0b0101_0101);
+        ppu.chr_mut()[0x38..0x40].fill(0b0011_0011);
+        ppu.vram[0] = 3;
+        //palette 1 for the top left quadrant
+        ppu.vram[0x3C0] = 0b00_00_00_01;
+        ppu.palette_table[0x05..0x08].copy_from_slice(&[0x21, 0x22, 0x23]);
+        let frame = render_frame(&ppu);
+        let expected = [BACKDROP, 0x21, 0x22, 0x23, BACKDROP, 0x21, 0x22, 0x23];
+        for (x, value) in expected.into_iter().enumerate() {
+            assert_eq!(pixel(&frame, x, 0), SYSTEM_PALLETE[value as usize], "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn attribute_quadrants_cover_every_tile() {
+        let mut ppu = scene();
+        for (i, entry) in ppu.palette_table.iter_mut().enumerate() {
+            *entry = i as u8;
+        }
+        //cell k: top left palette k % 4, then top right, bottom left, bottom right counting up
+        for name_table in [0x000, 0x400] {
+            for cell in 0..64 {
+                ppu.vram[name_table + 0x3C0 + cell] = (0..4).fold(0, |byte, q| byte | (((cell + q) % 4) << (q * 2)) as u8);
             }
         }
-    } else if scroll_y > 0 {
-        for i in 0..0x03C0 {
-            let tile_n = ppu.vram[i + second_nametable] as usize;
-            let x = i % 32_usize;
-            let y = i / 32_usize;
-            let palette = bg_palette(ppu, second_nametable, x, y);
-            
-            if y * 8 + 240 - scroll_y >= scanline_start && y * 8 + 240 - scroll_y < scanline_stop {
-                show_tile(
-                    frame, 
-                    &ppu.chr_rom, 
-                    bg_bank, 
-                    tile_n, 
-                    Rect {
-                        x1: 0,
-                        y1: 0,
-                        x2: 256 + scroll_x,
-                        y2: 240 + scroll_y,
-                    },
-                    x * 8,
-                    y * 8 + 240,
-                    0, 
-                    scroll_y as isize, 
-                    palette
-                );
+        for name_table in [0x000, 0x400] {
+            for row in 0..30 {
+                for column in 0..32 {
+                    let cell = row / 4 * 8 + column / 4;
+                    let quadrant = (row % 4) / 2 * 2 + (column % 4) / 2;
+                    let palette = ((cell + quadrant) % 4) as u8;
+                    assert_eq!(bg_palette(&ppu, name_table, column, row)[1], 1 + 4 * palette, "tile {},{} of ${:03X}", column, row, name_table);
+                }
             }
         }
     }
-    for i in (0..ppu.oam_data.len()).step_by(4).rev() {
-        if ppu.oam_data[i + 2] & 0x20 == 0 {
-            let tile_idx = ppu.oam_data[i + 1] as u16;
-            let tile_x = ppu.oam_data[i + 3] as usize;
-            let tile_y = ppu.oam_data[i] as usize;
 
-            let flip_vertical = ppu.oam_data[i + 2] >> 7 & 1 == 1;
-            let flip_horizontal = ppu.oam_data[i + 2] >> 6 & 1 == 1;
+    /*
+    Physical nametable k is filled with tile k, drawn in palette colour k
+    (tile 0 is transparent). For each base nametable the top-left pixel
+    shows the main table, the right edge scrolled 8 pixels shows its
+    horizontal neighbour and the bottom edge scrolled 8 lines its
+    vertical one.
+    */
+    #[test]
+    fn each_base_nametable_selects_the_mirrored_tables() {
+        let colours = [BACKDROP, 0x16, 0x2A, 0x12];
+        let layouts = [
+            (Mirroring::HORIZONTAL, [0, 0, 1, 1]),
+            (Mirroring::VERTICAL, [0, 1, 0, 1]),
+            (Mirroring::ONE_SCREEN_LOWER, [0, 0, 0, 0]),
+            (Mirroring::ONE_SCREEN_UPPER, [1, 1, 1, 1]),
+            (Mirroring::FOUR_SCREEN, [0, 1, 2, 3]),
+        ];
+        for (mirroring, physical) in layouts {
+            let mut chr = vec![0; 0x2000];
+            chr[0x10..0x18].fill(0xFF);
+            chr[0x28..0x30].fill(0xFF);
+            chr[0x30..0x40].fill(0xFF);
+            let mut ppu = PPU::new(chr, false, mirroring.clone());
+            ppu.set_warm_up(false);
+            for table in 0..4 {
+                ppu.vram[table * 0x400..table * 0x400 + 0x3C0].fill(table as u8);
+            }
+            ppu.palette_table[..4].copy_from_slice(&colours);
+            for base in 0..4 {
+                for (line, regs) in ppu.line_registers.iter_mut().enumerate() {
+                    regs.mask = 0x0A;
+                    regs.backdrop = BACKDROP;
+                    regs.name_table = base as u8;
+                    regs.scroll_x = 0;
+                    regs.scroll_y = if line == 239 { 8 } else { 0 };
+                }
+                ppu.line_registers[0].scroll_x = 8;
+                let frame = render_frame(&ppu);
+                let colour = |table: usize| SYSTEM_PALLETE[colours[physical[table]] as usize];
+                assert_eq!(pixel(&frame, 0, 1), colour(base), "{:?} main of {}", mirroring, base);
+                assert_eq!(pixel(&frame, 255, 0), colour(base ^ 1), "{:?} right of {}", mirroring, base);
+                assert_eq!(pixel(&frame, 0, 239), colour(base ^ 2), "{:?} below {}", mirroring, base);
+            }
+        }
+    }
 
-            let palette_idx = ppu.oam_data[i + 2] & 0x03;
-            let sprite_palette = sprite_palette(ppu, palette_idx);
+    #[test]
+    fn overscan_cropping_starts_at_scanline_8() {
+        let mut frame = Frame::new((0, 0, 0));
+        for y in 0..HEIGHT {
+            frame.set_pixel(0, y, (y as u8, 0, 0));
+        }
+        let visible = crop_overscan(&frame.data);
+        assert_eq!(visible.len(), WIDTH * 224 * 4);
+        for row in 0..224 {
+            assert_eq!(visible[row * WIDTH * 4], (row + 8) as u8);
+        }
+    }
 
-            if ppu.ctrl & 0x20 == 0 {
-                let sprite_bank = ((ppu.ctrl & 0x08) >> 3) as usize;
+    #[test]
+    fn overlay_outlines_sprites_and_marks_splits() {
+        let mut ppu = scene();
+        set_sprite(&mut ppu, 0, [0x20, 2, 0x00, 0x30]);
+        for line in ppu.line_registers[100..].iter_mut() {
+            line.scroll_x = 8;
+        }
+        let frame = render_frame(&ppu);
+        let mut overlay = Frame { data: frame.data };
+        draw_overlay(&ppu, &mut overlay);
+        //the box's corners and edges, on the lines the sprite is drawn on
+        for (x, y) in [(0x30, 0x20), (0x37, 0x20), (0x30, 0x27), (0x37, 0x27), (0x33, 0x20), (0x30, 0x25)] {
+            assert_eq!(pixel(&overlay, x, y), OVERLAY_SPRITE_COLOR, "({}, {})", x, y);
+        }
+        //inside and outside it the picture is left alone
+        for (x, y) in [(0x33, 0x25), (0x2F, 0x25), (0x38, 0x25), (0x33, 0x1F), (0x33, 0x28)] {
+            assert_eq!(pixel(&overlay, x, y), pixel(&frame, x, y), "({}, {})", x, y);
+        }
+        //the split line sits on the first line with the new scroll
+        assert_eq!(pixel(&overlay, 200, 100), OVERLAY_SPLIT_COLOR);
+        assert_eq!(pixel(&overlay, 200, 99), pixel(&frame, 200, 99));
+        assert_eq!(pixel(&overlay, 200, 101), pixel(&frame, 200, 101));
+    }
 
-                show_sprite_tile(frame, &ppu.chr_rom, sprite_bank, tile_idx as usize, tile_x, tile_y, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
-            } else {
-                if flip_vertical {
-                    show_sprite_tile(frame, &ppu.chr_rom, tile_idx as usize & 0x01, tile_idx as usize | 0x01, tile_x, tile_y, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
-                    show_sprite_tile(frame, &ppu.chr_rom, tile_idx as usize & 0x01, tile_idx as usize & 0xFE, tile_x, tile_y + 8, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
-                } else {
-                    show_sprite_tile(frame, &ppu.chr_rom, tile_idx as usize & 0x01, tile_idx as usize & 0xFE, tile_x, tile_y, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
-                    show_sprite_tile(frame, &ppu.chr_rom, tile_idx as usize & 0x01, tile_idx as usize | 0x01, tile_x, tile_y + 8, flip_vertical, flip_horizontal, sprite_palette, scanline_start, scanline_stop);
+    #[test]
+    fn scrolled_seam_takes_attributes_from_the_next_nametable() {
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        let mut ppu = PPU::new(chr, false, Mirroring::VERTICAL);
+        //tile 1 everywhere, palette 0 in nametable 0 and palette 2 in nametable 1
+        for (table, attributes) in [(0x000, 0x00), (0x400, 0xAA)] {
+            ppu.vram[table..table + 0x3C0].fill(1);
+            ppu.vram[table + 0x3C0..table + 0x400].fill(attributes);
+        }
+        ppu.palette_table[0x01] = 0x16;
+        ppu.palette_table[0x09] = 0x2A;
+        //not a multiple of 8, the seam falls inside a tile column
+        for line in ppu.line_registers.iter_mut() {
+            line.mask = 0x0A;
+            line.scroll_x = 13;
+        }
+        let frame = render_frame(&ppu);
+        let (left, right) = (ppu.system_palette[0x16], ppu.system_palette[0x2A]);
+        for y in [0, 100, 239] {
+            for x in 0..WIDTH {
+                let expected = if x < WIDTH - 13 { left } else { right };
+                assert_eq!(pixel(&frame, x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn compositing_the_layers_gives_the_combined_frame() {
+        let mut ppu = scene();
+        //in front of the background, behind it, over transparent background and in the left column
+        set_sprite(&mut ppu, 0, [16, 2, 0x00, 40]);
+        set_sprite(&mut ppu, 1, [32, 2, 0x20, 40]);
+        set_sprite(&mut ppu, 2, [48, 2, 0x20, 200]);
+        set_sprite(&mut ppu, 3, [64, 2, 0x00, 0]);
+        for line in ppu.line_registers[64..72].iter_mut() {
+            line.mask = 0x18;
+        }
+        let mut layers = Layers::new();
+        let mut frame = Frame::new((0, 0, 0));
+        render(&ppu, &mut layers, &mut frame, 0, HEIGHT);
+
+        //the kept layers hold each part on its own
+        let opaque = |layer: &[u8], x: usize, y: usize| layer[(y * WIDTH + x) * 4 + 3] == 0xFF;
+        assert!(opaque(&layers.background, 40, 32) && opaque(&layers.sprites, 40, 32));
+        assert!(!opaque(&layers.background, 200, 48) && opaque(&layers.sprites, 200, 48));
+        assert!(layers.sprite_behind[32 * WIDTH + 40] && !layers.sprite_behind[16 * WIDTH + 40]);
+
+        let (backdrop, bg, sprite) = (SYSTEM_PALLETE[BACKDROP as usize], SYSTEM_PALLETE[BG_COLOR as usize], SYSTEM_PALLETE[SPRITE_COLOR as usize]);
+        let mut expected = Frame::new(backdrop);
+        for y in 0..HEIGHT {
+            let left = if (64..72).contains(&y) { 8 } else { 0 };
+            for x in left..128 {
+                expected.set_pixel(x, y, bg);
+            }
+        }
+        for (x, y) in [(40, 16), (200, 48), (0, 64)] {
+            for dy in 0..8 {
+                for dx in 0..8 {
+                    expected.set_pixel(x + dx, y + dy, sprite);
                 }
             }
         }
+        //the left column is clipped on 64-71, taking the sprite there with it
+        for y in 64..72 {
+            for x in 0..8 {
+                expected.set_pixel(x, y, backdrop);
+            }
+        }
+        assert!(frame.data == expected.data);
+    }
+
+    #[test]
+    fn sprites_off_the_right_edge_do_not_wrap() {
+        let mut ppu = scene();
+        set_sprite(&mut ppu, 0, [100, 2, 0x00, 252]);
+        let frame = render_frame(&ppu);
+        let sprite = SYSTEM_PALLETE[SPRITE_COLOR as usize];
+        for y in 100..108 {
+            for x in 252..WIDTH {
+                assert_eq!(pixel(&frame, x, y), sprite, "({}, {})", x, y);
+            }
+            //the four columns past the edge would land at the start of the next row
+            for x in 0..4 {
+                assert_ne!(pixel(&frame, x, y + 1), sprite, "({}, {})", x, y + 1);
+            }
+        }
+        //nor does a pixel past the edge of a frame
+        let mut frame = Frame::new((0, 0, 0));
+        frame.set_pixel(WIDTH + 2, 10, (0xFF, 0xFF, 0xFF));
+        frame.set_pixel(2, HEIGHT, (0xFF, 0xFF, 0xFF));
+        assert!(frame.data == Frame::new((0, 0, 0)).data);
     }
- }
 
- #[rustfmt::skip]
-
-pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
-   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
-   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
-   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
-   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
-   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
-   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
-   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
-   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
-   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
-   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
-   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
-   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
-   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
-];
\ No newline at end of file
+    #[test]
+    fn flipped_sprites_land_on_their_own_rows() {
+        //tile 4 is only its top row, tile 5 only its third row, in both pattern tables
+        let mut ppu = scene();
+        for table in [0x0000, 0x1000] {
+            ppu.chr_mut()[table + 0x40] = 0xFF;
+            ppu.chr_mut()[table + 0x52] = 0xFF;
+        }
+        ppu.palette_table[0x11] = SPRITE_COLOR;
+        set_sprite(&mut ppu, 0, [50, 4, 0x00, 200]);
+        set_sprite(&mut ppu, 1, [50, 4, 0x80, 208]);
+        set_sprite(&mut ppu, 2, [50, 5, 0xC0, 216]);
+        let sprite = SYSTEM_PALLETE[SPRITE_COLOR as usize];
+        let rows = |frame: &Frame, x: usize| (0..HEIGHT).filter(|&y| pixel(frame, x, y) == sprite).collect::<Vec<_>>();
+        let frame = render_frame(&ppu);
+        assert_eq!(rows(&frame, 200), vec![50]);
+        assert_eq!(rows(&frame, 208), vec![57]);
+        assert_eq!(rows(&frame, 216), vec![55]);
+
+        //8x16, tiles 4 and 5 stacked, flipping swaps the halves as well as the rows
+        for line in ppu.line_registers.iter_mut() {
+            line.ctrl = 0x20;
+        }
+        let frame = render_frame(&ppu);
+        assert_eq!(rows(&frame, 200), vec![50, 60]);
+        assert_eq!(rows(&frame, 208), vec![55, 65]);
+        set_sprite(&mut ppu, 0, [50, 5, 0x00, 200]);
+        set_sprite(&mut ppu, 1, [50, 5, 0x80, 208]);
+        let frame = render_frame(&ppu);
+        //odd tiles take both halves from the right pattern table, which holds the same tiles
+        assert_eq!(rows(&frame, 200), vec![50, 60]);
+        assert_eq!(rows(&frame, 208), vec![55, 65]);
+    }
+}
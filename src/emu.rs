@@ -0,0 +1,642 @@
+use crate::bus::Bus;
+use crate::cheats::Cheats;
+use crate::cpu::{CPU, StepResult};
+use crate::display;
+use crate::input::Button;
+use crate::monitor::Monitor;
+use crate::movie::{Fm2, Movie, MovieError, Player};
+use crate::palette::{self, Palette};
+use crate::profile::Profile;
+use crate::rewind::Rewind;
+use crate::rom::{Header, Mirroring, Region, Rom, RomError};
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+
+/*
+Emulation Core:
+
+Frame-level driving of the emulated hardware, shared by the
+windowed frontend and the headless runner. Nothing in here may
+depend on the windowing or OpenGL stack.
+*/
+
+/*
+Step Function:
+
+Executes a single instruction, dropping into the monitor when
+a breakpoint, watchpoint or stop on BRK halts the CPU.
+*/
+#[inline(always)]
+pub fn step(cpu_6502: &mut CPU, monitor: &mut Monitor) {
+    match cpu_6502.step() {
+        StepResult::Completed => (),
+        StepResult::Breakpoint(pc) => {
+            info!("Breakpoint hit at ${:04X}", pc);
+            monitor.run(cpu_6502);
+        },
+        StepResult::Watchpoint(addr, access) => {
+            info!("Watchpoint hit: {:?} ${:04X} (PC = ${:04X})", access, addr, cpu_6502.reg_pc);
+            monitor.run(cpu_6502);
+        },
+        StepResult::Brk(pc) => {
+            info!("BRK hit at ${:04X}", pc);
+            monitor.run(cpu_6502);
+        },
+    }
+}
+
+/*
+Update Function:
+
+Runs once per frame of app execution. Drives the CPU and PPU
+functionality for the duration of a single screen update.
+With `draw` off the frame is emulated in full (timing, sprite 0
+hits) but nothing is rendered, the previous picture stays.
+*/
+
+#[inline(always)]
+pub fn update(cpu_6502: &mut CPU, layers: &mut display::Layers, frame: &mut display::Frame, monitor: &mut Monitor, draw: bool){
+    //each block is drawn once the CPU has run through it, from the registers latched per scanline
+    for scanline in (0..240).step_by(8) {
+        while (cpu_6502.mem_bus.ppu.scanlines as usize) < scanline + 8 {
+            step(cpu_6502, monitor);
+        }
+        if draw {
+            display::render(&cpu_6502.mem_bus.ppu, layers, frame, scanline, scanline + 8);
+        }
+    }
+
+    while cpu_6502.mem_bus.ppu.scanlines >= 240 {
+        step(cpu_6502, monitor);
+    }
+    
+}
+
+/*
+Emulator:
+
+High-level handle over a whole console, for frontends and for
+embedding the core elsewhere. Owns the CPU (and through the bus,
+every other hardware component), the current display frame, the
+debug monitor, the input recorder/player, and the rewind buffer.
+*/
+pub struct Emulator {
+    pub cpu: CPU,
+    frame: display::Frame,
+    //what the frame was composited from, see display::Layers
+    layers: display::Layers,
+    monitor: Monitor,
+    recording: Option<Movie>,
+    playback: Option<Player>,
+    rewind: Rewind,
+    pub cheats: Cheats,
+    //render one frame out of every `frameskip`
+    frameskip: u32,
+    skip_countdown: u32,
+    rendered: bool,
+    rom_md5: [u8; 16],
+    rom_hash: u32,
+    //from the header or a profile, there is no PAL/Dendy timing yet so this is informational
+    region: Option<Region>,
+}
+
+impl Emulator {
+    //builds a powered-on console from a raw iNES image
+    pub fn new(raw: &[u8]) -> Result<Self, RomError> {
+        Self::with_mirroring(raw, None)
+    }
+
+    //like new, but a Some mirroring overrides the one in the header
+    pub fn with_mirroring(raw: &[u8], mirroring: Option<Mirroring>) -> Result<Self, RomError> {
+        let rom = Rom::new(raw)?;
+        let rom_md5 = rom.md5();
+        let rom_hash = rom.hash();
+        let region = Header::parse(raw)?.region;
+        Ok(Emulator {
+            cpu: Self::boot(rom, mirroring)?,
+            frame: display::Frame::new((0, 0, 0)),
+            layers: display::Layers::new(),
+            monitor: Monitor::new(),
+            recording: None,
+            playback: None,
+            rewind: Rewind::default(),
+            cheats: Cheats::new(),
+            frameskip: 1,
+            skip_countdown: 0,
+            rendered: false,
+            rom_md5,
+            rom_hash,
+            region,
+        })
+    }
+
+    fn boot(rom: Rom, mirroring: Option<Mirroring>) -> Result<CPU, RomError> {
+        //generate Memory Bus
+        if let Some(mirroring) = &mirroring {
+            info!("Mirror Type: {:?} (forced)", mirroring);
+        }
+        let bus = Bus::new(rom, mirroring)?;
+
+        //generate CPU
+        let mut cpu_6502 = CPU::new(bus);
+        cpu_6502.power_on();
+        Ok(cpu_6502)
+    }
+
+    //switches the console off and on again, back to the power-on state
+    pub fn power_cycle(&mut self) {
+        self.cpu.power_on();
+        self.rewind.clear();
+        self.frame.clear((0, 0, 0));
+        self.layers = display::Layers::new();
+        self.skip_countdown = 0;
+    }
+
+    /*
+    Save States:
+
+    The whole machine, bincode encoded. Display preferences (the
+    colour palette, the sprite limit, the sprite 0 hit delay, PPU
+    warm-up, the key maps, expansion lines) are kept from the running
+    console rather than taken from the state.
+    */
+    #[cfg(feature = "serde")]
+    pub fn save_state(&self) -> Result<Vec<u8>, bincode::Error> {
+        bincode::serialize(&self.cpu)
+    }
+    #[cfg(feature = "serde")]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), bincode::Error> {
+        let mut cpu: CPU = bincode::deserialize(state)?;
+        cpu.mem_bus.ppu.system_palette = self.cpu.mem_bus.ppu.system_palette;
+        cpu.mem_bus.ppu.sprite_limit = self.cpu.mem_bus.ppu.sprite_limit;
+        cpu.mem_bus.ppu.sprite_0_hit_delay = self.cpu.mem_bus.ppu.sprite_0_hit_delay;
+        cpu.mem_bus.ppu.set_warm_up(self.cpu.mem_bus.ppu.warm_up());
+        cpu.mem_bus.port1.key_map = self.cpu.mem_bus.port1.key_map;
+        cpu.mem_bus.port2.key_map = self.cpu.mem_bus.port2.key_map;
+        cpu.mem_bus.expansion = self.cpu.mem_bus.expansion.clone();
+        self.cpu = cpu;
+        Ok(())
+    }
+
+    //replaces the colours used to render palette values
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.mem_bus.ppu.system_palette = palette;
+    }
+
+    pub fn load_rom(path: &Path) -> Result<Self, RomError> {
+        Self::load_rom_with_mirroring(path, None)
+    }
+    pub fn load_rom_with_mirroring(path: &Path, mirroring: Option<Mirroring>) -> Result<Self, RomError> {
+        let raw = fs::read(path)?;
+        Self::with_mirroring(&raw, mirroring)
+    }
+
+    //MD5 of the loaded ROM's PRG and CHR data, see Rom::md5
+    pub fn rom_md5(&self) -> [u8; 16] {
+        self.rom_md5
+    }
+
+    //CRC32 of the loaded ROM's PRG and CHR data, see Rom::hash
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    pub fn region(&self) -> Option<Region> {
+        self.region
+    }
+
+    /*
+    Applies the settings a per-game profile sets on top of the
+    current ones. Cheats that fail to parse are skipped with a
+    warning. The profile's mirroring has to be passed to
+    with_mirroring instead, it can't change after boot.
+    */
+    pub fn apply_profile(&mut self, profile: &Profile) {
+        if profile.region.is_some() {
+            self.region = profile.region;
+        }
+        if let Some(path) = &profile.palette {
+            self.set_palette(palette::load_palette(path));
+        }
+        if let Some(key_map) = profile.key_map {
+            self.cpu.mem_bus.port1.key_map = key_map;
+        }
+        for code in &profile.cheats {
+            if let Err(e) = self.cheats.add(code) {
+                warn!("Skipping profile cheat: {}", e);
+            }
+        }
+    }
+
+    //runs the console until the next frame has been rendered
+    pub fn step_frame(&mut self) {
+        //movie input replaces the controller state for the whole frame
+        if let Some(player) = &mut self.playback {
+            match player.next_frame() {
+                Some([port1, port2]) => {
+                    self.cpu.mem_bus.port1.set_button_states(port1);
+                    self.cpu.mem_bus.port2.set_button_states(port2);
+                },
+                None => self.playback = None,
+            }
+        }
+        if let Some(movie) = &mut self.recording {
+            movie.frames.push([
+                self.cpu.mem_bus.port1.button_states(),
+                self.cpu.mem_bus.port2.button_states(),
+            ]);
+        }
+        self.rendered = self.skip_countdown == 0;
+        self.skip_countdown = if self.rendered { self.frameskip - 1 } else { self.skip_countdown - 1 };
+        update(&mut self.cpu, &mut self.layers, &mut self.frame, &mut self.monitor, self.rendered);
+        self.cpu.mem_bus.port1.next_frame();
+        self.cpu.mem_bus.port2.next_frame();
+        self.cheats.apply(&mut self.cpu);
+        self.rewind.record(&self.cpu);
+    }
+
+    /*
+    Runs the CPU until the PPU moves on to the next scanline, for
+    looking at raster effects one line at a time. An instruction is
+    far shorter than a scanline, so usually exactly one boundary is
+    crossed, but the OAM DMA stall after a $4014 write runs the PPU
+    on by about four and a half lines at once. Every visible line
+    passed is drawn into the frame, the rest of the frame keeps what
+    it had. Returns the scanline the PPU is now on.
+    */
+    pub fn step_scanline(&mut self) -> u16 {
+        let start = self.cpu.mem_bus.ppu.scanlines;
+        while self.cpu.mem_bus.ppu.scanlines == start {
+            step(&mut self.cpu, &mut self.monitor);
+        }
+        let end = self.cpu.mem_bus.ppu.scanlines;
+        let mut line = start;
+        while line != end {
+            if line < 240 {
+                display::render(&self.cpu.mem_bus.ppu, &mut self.layers, &mut self.frame, line as usize, line as usize + 1);
+            }
+            //the pre-render line 261 wraps round to 0
+            line = (line + 1) % 262;
+        }
+        end
+    }
+
+    //runs at most `max_instructions` CPU instructions without drawing, see CPU::run_for
+    pub fn run_for(&mut self, max_instructions: u64) -> u64 {
+        self.cpu.run_for(max_instructions)
+    }
+
+    /*
+    Steps back to the most recent rewind snapshot and renders the
+    frame that follows it. Returns false (and does nothing) once
+    the buffer is used up, or while a movie is recording or playing
+    since rewinding would desync it.
+    */
+    pub fn rewind(&mut self) -> bool {
+        if self.recording.is_some() || self.playback.is_some() {
+            return false;
+        }
+        match self.rewind.pop() {
+            Some(cpu) => {
+                self.cpu = cpu;
+                update(&mut self.cpu, &mut self.layers, &mut self.frame, &mut self.monitor, true);
+                self.rendered = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    //the background and sprite layers of the most recently rendered frame
+    pub fn layers(&self) -> &display::Layers {
+        &self.layers
+    }
+
+    //RGBA pixels of the most recently rendered frame
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame.data
+    }
+
+    //seeds the noise CPU RAM powers up with (None clears it), takes effect with a power cycle
+    pub fn set_ram_seed(&mut self, seed: Option<u64>) {
+        self.cpu.mem_bus.ram_seed = seed;
+        self.power_cycle();
+    }
+
+    //whether the PPU ignores register writes for its first frame (see PPU_WARM_UP), off boots test code faster
+    pub fn set_ppu_warm_up(&mut self, enabled: bool) {
+        self.cpu.mem_bus.ppu.set_warm_up(enabled);
+    }
+
+    //renders only every `frames`th frame (1 renders all of them), the rest are emulated without drawing
+    pub fn set_frameskip(&mut self, frames: u32) {
+        self.frameskip = frames.max(1);
+        self.skip_countdown = 0;
+    }
+
+    //whether the last step_frame/rewind drew a new picture
+    pub fn frame_rendered(&self) -> bool {
+        self.rendered
+    }
+
+    //port is the controller port number (1 or 2)
+    pub fn set_button(&mut self, port: u8, button: Button, pressed: bool) {
+        match port {
+            1 => self.cpu.mem_bus.port1.set_button(button, pressed),
+            2 => self.cpu.mem_bus.port2.set_button(button, pressed),
+            _ => panic!("invalid controller port {}", port),
+        }
+    }
+
+    //port is the controller port number (1 or 2), a rate of 0 turns turbo off
+    pub fn set_turbo(&mut self, port: u8, button: Button, rate: u8) {
+        match port {
+            1 => self.cpu.mem_bus.port1.set_turbo(button, rate),
+            2 => self.cpu.mem_bus.port2.set_turbo(button, rate),
+            _ => panic!("invalid controller port {}", port),
+        }
+    }
+
+    /*
+    Input Recording:
+
+    Recording logs the controller state of every following frame.
+    Playback power cycles the console first so the movie always
+    starts from the same machine state it was recorded against.
+    */
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Movie::new());
+    }
+    pub fn stop_recording(&mut self) -> Option<Movie> {
+        self.recording.take()
+    }
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+    pub fn start_playback(&mut self, movie: Movie) {
+        self.power_cycle();
+        self.playback = Some(Player::new(movie));
+    }
+    //plays a recording file, an .fm2 movie or the native text format
+    pub fn play_file(&mut self, path: &Path) -> Result<(), MovieError> {
+        let is_fm2 = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("fm2"));
+        let movie = if is_fm2 {
+            let fm2 = Fm2::load(path)?;
+            if fm2.rom_checksum.is_some_and(|checksum| checksum != self.rom_md5) {
+                warn!("{} was recorded on a different ROM ({}), playback may desync", path.display(), fm2.rom_filename);
+            }
+            fm2.movie
+        } else {
+            Movie::load(path)?
+        };
+        self.start_playback(movie);
+        Ok(())
+    }
+    pub fn stop_playback(&mut self) {
+        self.playback = None;
+    }
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+    pub fn playback_remaining(&self) -> usize {
+        self.playback.as_ref().map_or(0, |player| player.remaining())
+    }
+
+    //side effect free memory access for tools, see Bus::peek/poke
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.cpu.mem_bus.peek(addr)
+    }
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        self.cpu.mem_bus.poke(addr, data);
+    }
+    //PPU address space ($0000-$3FFF: CHR, nametables, palette)
+    pub fn ppu_peek(&self, addr: u16) -> u8 {
+        self.cpu.mem_bus.ppu.peek_vram(addr)
+    }
+    pub fn ppu_poke(&mut self, addr: u16, data: u8) {
+        self.cpu.mem_bus.ppu.poke_vram(addr, data);
+    }
+    pub fn oam_peek(&self, addr: u8) -> u8 {
+        self.cpu.mem_bus.ppu.peek_oam(addr)
+    }
+    pub fn oam_poke(&mut self, addr: u8, data: u8) {
+        self.cpu.mem_bus.ppu.poke_oam(addr, data);
+    }
+
+    //pauses emulation and hands control to the stdin monitor
+    pub fn run_monitor(&mut self) {
+        self.monitor.run(&mut self.cpu);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::cpu::tests::rom;
+    use std::thread;
+
+    /*
+    Debug builds move the frame buffer around by value a few times
+    while building a console, more than the 2MB test threads get
+    once there are two. Runs the test with a main thread sized stack.
+    */
+    pub(crate) fn on_big_stack(test: impl FnOnce() + Send + 'static) {
+        thread::Builder::new().stack_size(8 << 20).spawn(test).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn a_frame_runs_the_cpu_and_fills_the_frame_buffer() {
+        //loop: INC $10, JMP loop
+        let mut emulator = Emulator::new(&rom(&[0xE6, 0x10, 0x4C, 0x00, 0x80])).unwrap();
+        emulator.set_ppu_warm_up(false);
+        emulator.step_frame();
+        let count = emulator.peek(0x0010);
+        assert!(count > 0);
+        //the counter wraps many times a frame, so only check that it moved
+        emulator.step_frame();
+        assert_ne!(emulator.peek(0x0010), count);
+        assert!(emulator.frame_rendered());
+        assert_eq!(emulator.frame_buffer().len(), 256 * 240 * 4);
+    }
+
+    /*
+    Reads pad 1 into $10 in a loop and shows it as the backdrop
+    colour, so the frame depends on the input.
+    */
+    pub(crate) const SHOW_PAD: [u8; 51] = [
+        0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, //strobe $4016
+        0xA2, 0x08, 0xAD, 0x16, 0x40, 0x4A, 0x26, 0x10, 0xCA, 0xD0, 0xF7, //shift 8 buttons into $10
+        0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20, //$2006 = $3F00
+        0xA5, 0x10, 0x29, 0x3F, 0x8D, 0x07, 0x20, //$2007 = $10 & $3F
+        0xA9, 0x3F, 0x8D, 0x06, 0x20, 0xA9, 0x00, 0x8D, 0x06, 0x20, 0x4C, 0x00, 0x80, //point v back at the backdrop, loop
+    ];
+
+    pub(crate) fn frame_hash(emulator: &Emulator) -> u32 {
+        crc32fast::hash(emulator.frame_buffer())
+    }
+
+    #[test]
+    fn replaying_a_recording_reproduces_the_frame() {
+        on_big_stack(|| {
+            let mut recorder = Emulator::new(&rom(&SHOW_PAD)).unwrap();
+            recorder.start_recording();
+            for (frame, button) in [Button::A, Button::Start, Button::Left, Button::Down].into_iter().enumerate() {
+                recorder.set_button(1, button, frame % 2 == 0);
+                recorder.step_frame();
+            }
+            //A and Left are still held
+            assert_eq!(recorder.peek(0x0010), 0x82);
+            let movie = recorder.stop_recording().unwrap();
+            assert_eq!(movie.frames.len(), 4);
+
+            let mut player = Emulator::new(&rom(&SHOW_PAD)).unwrap();
+            player.start_playback(movie);
+            for _ in 0..4 {
+                player.step_frame();
+            }
+            assert_eq!(player.playback_remaining(), 0);
+            assert_eq!(frame_hash(&player), frame_hash(&recorder));
+            assert_eq!(player.peek(0x0010), recorder.peek(0x0010));
+        });
+    }
+
+    #[test]
+    fn frameskip_runs_every_frame_but_draws_every_other() {
+        on_big_stack(|| {
+            let mut skipping = Emulator::new(&rom(&SHOW_PAD)).unwrap();
+            let mut drawing = Emulator::new(&rom(&SHOW_PAD)).unwrap();
+            skipping.set_frameskip(2);
+            let mut drawn = 0;
+            for frame in 0..6 {
+                skipping.set_button(1, Button::A, frame % 3 == 0);
+                drawing.set_button(1, Button::A, frame % 3 == 0);
+                skipping.step_frame();
+                drawing.step_frame();
+                assert_eq!(skipping.cpu.tot_cycles, drawing.cpu.tot_cycles);
+                assert_eq!(skipping.peek(0x0010), drawing.peek(0x0010));
+                assert_eq!(skipping.frame_rendered(), frame % 2 == 0);
+                if skipping.frame_rendered() {
+                    drawn += 1;
+                    assert_eq!(frame_hash(&skipping), frame_hash(&drawing));
+                }
+            }
+            assert_eq!(drawn, 3);
+        });
+    }
+
+    fn ram(emulator: &Emulator) -> Vec<u8> {
+        (0..0x800).map(|addr| emulator.peek(addr)).collect()
+    }
+
+    #[test]
+    fn the_same_ram_seed_powers_up_the_same_machine() {
+        on_big_stack(|| {
+            //INC $10, JMP $8000
+            let program = [0xE6, 0x10, 0x4C, 0x00, 0x80];
+            let mut first = Emulator::new(&rom(&program)).unwrap();
+            let mut second = Emulator::new(&rom(&program)).unwrap();
+            first.set_ram_seed(Some(42));
+            second.set_ram_seed(Some(42));
+            let power_on = ram(&first);
+            assert!(power_on.iter().any(|&byte| byte != 0));
+            assert_eq!(ram(&second), power_on);
+            for _ in 0..10 {
+                first.step_frame();
+                second.step_frame();
+                assert_eq!(ram(&first), ram(&second));
+            }
+
+            //a power cycle repeats the fill, another seed gives another one
+            first.power_cycle();
+            assert_eq!(ram(&first), power_on);
+            second.set_ram_seed(Some(43));
+            assert_ne!(ram(&second), power_on);
+            second.set_ram_seed(None);
+            assert!(ram(&second).iter().all(|&byte| byte == 0));
+        });
+    }
+
+    /*
+    A title screen that polls pad 1 until Start is pressed, then
+    moves on by setting the game state at $20.
+    */
+    const TITLE_SCREEN: [u8; 33] = [
+        0xA9, 0x01, 0x8D, 0x16, 0x40, 0xA9, 0x00, 0x8D, 0x16, 0x40, //strobe $4016
+        0xAD, 0x16, 0x40, 0xAD, 0x16, 0x40, 0xAD, 0x16, 0x40, //skip A, B and Select
+        0xAD, 0x16, 0x40, 0x29, 0x01, 0xF0, 0xE6, //Start, poll again while released
+        0xA9, 0x01, 0x85, 0x20, 0x4C, 0x1E, 0x80, //$20 = 1, stop
+    ];
+
+    #[test]
+    fn a_replay_played_from_boot_presses_start() {
+        on_big_stack(|| {
+            let path = std::env::temp_dir().join(format!("nes_emu_replay_{}.txt", std::process::id()));
+            std::fs::write(&path, "# Start on the fourth frame\n00 00\n00 00\n00 00\n10 00\n00 00\n").unwrap();
+            let mut emulator = Emulator::new(&rom(&TITLE_SCREEN)).unwrap();
+            let loaded = emulator.play_file(&path);
+            std::fs::remove_file(&path).unwrap();
+            loaded.unwrap();
+            for _ in 0..3 {
+                emulator.step_frame();
+            }
+            assert_eq!(emulator.peek(0x0020), 0);
+            emulator.step_frame();
+            assert_eq!(emulator.peek(0x0020), 1);
+            emulator.step_frame();
+            assert_eq!(emulator.playback_remaining(), 0);
+            //control goes back to the player once the replay runs out
+            emulator.step_frame();
+            assert!(!emulator.is_playing());
+        });
+    }
+
+    #[test]
+    fn stepping_a_scanline_advances_exactly_one() {
+        on_big_stack(|| {
+            let mut emulator = Emulator::new(&rom(&[0x4C, 0x00, 0x80])).unwrap();
+            //through the end of a frame and the wrap back to line 0
+            for _ in 0..300 {
+                let before = emulator.cpu.mem_bus.ppu.scanlines;
+                let after = emulator.step_scanline();
+                assert_eq!(after, (before + 1) % 262);
+                assert_eq!(emulator.cpu.mem_bus.ppu.scanlines, after);
+            }
+
+            //LDA #$02, STA $4014, JMP $8000, the DMA stall alone is over 4 lines
+            let mut emulator = Emulator::new(&rom(&[0xA9, 0x02, 0x8D, 0x14, 0x40, 0x4C, 0x00, 0x80])).unwrap();
+            let unset = (0x01, 0x02, 0x03);
+            let mut longest = 0;
+            for _ in 0..300 {
+                emulator.frame.clear(unset);
+                let before = emulator.cpu.mem_bus.ppu.scanlines;
+                let after = emulator.step_scanline();
+                let passed = (after + 262 - before) % 262;
+                assert!((1..=6).contains(&passed), "{} to {}", before, after);
+                longest = longest.max(passed);
+                //every visible line passed on the way is drawn, the others are left alone
+                for line in 0..240 {
+                    let drawn = (line + 262 - before) % 262 < passed;
+                    let pixel = &emulator.frame_buffer()[line as usize * 256 * 4..][..3];
+                    assert_eq!(pixel != [unset.0, unset.1, unset.2], drawn, "line {} stepping {} to {}", line, before, after);
+                }
+            }
+            assert!(longest > 4);
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn loading_a_state_keeps_the_display_preferences() {
+        on_big_stack(|| {
+            let mut emulator = Emulator::new(&rom(&[0x4C, 0x00, 0x80])).unwrap();
+            let state = emulator.save_state().unwrap();
+            emulator.cpu.mem_bus.ppu.sprite_limit = false;
+            emulator.cpu.mem_bus.ppu.sprite_0_hit_delay = 1;
+            emulator.cpu.mem_bus.ppu.set_warm_up(false);
+            emulator.load_state(&state).unwrap();
+            let ppu = &emulator.cpu.mem_bus.ppu;
+            assert_eq!((ppu.sprite_limit, ppu.sprite_0_hit_delay, ppu.warm_up()), (false, 1, false));
+        });
+    }
+}
@@ -0,0 +1,95 @@
+use thiserror::Error;
+
+/*
+Game Genie:
+
+The Game Genie sits between the cartridge and the console and
+patches CPU reads from $8000-$FFFF. A 6 letter code replaces the
+byte at an address outright. An 8 letter code also carries a
+compare byte and only patches when the ROM holds that value, so
+the code stays harmless when a different PRG bank is mapped in.
+
+Each letter is a 4 bit value -
+    A P Z L G I T Y E O X U K S V N
+    0 1 2 3 4 5 6 7 8 9 A B C D E F
+and the address/data/compare bits are scrambled across them.
+*/
+
+const LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum GenieError {
+    #[error("game genie codes are 6 or 8 letters, got {0}")]
+    InvalidLength(usize),
+    #[error("'{0}' is not a game genie letter")]
+    InvalidLetter(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GenieCode {
+    pub addr: u16,
+    pub data: u8,
+    pub compare: Option<u8>,
+}
+
+impl GenieCode {
+    pub fn decode(code: &str) -> Result<Self, GenieError> {
+        let n = code
+            .chars()
+            .map(|c| {
+                LETTERS
+                    .find(c.to_ascii_uppercase())
+                    .map(|v| v as u16)
+                    .ok_or(GenieError::InvalidLetter(c))
+            })
+            .collect::<Result<Vec<u16>, GenieError>>()?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(GenieError::InvalidLength(n.len()));
+        }
+
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8) | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4) | ((n[1] & 8) << 4)
+            | (n[4] & 7) | (n[3] & 8);
+        let data = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+        if n.len() == 6 {
+            Ok(GenieCode {
+                addr,
+                data: (data | (n[5] & 8)) as u8,
+                compare: None,
+            })
+        } else {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            Ok(GenieCode {
+                addr,
+                data: (data | (n[7] & 8)) as u8,
+                compare: Some(compare as u8),
+            })
+        }
+    }
+
+    //the byte the CPU sees at `addr` when the ROM holds `value`
+    pub fn apply(&self, addr: u16, value: u8) -> u8 {
+        match self.compare {
+            _ if addr != self.addr => value,
+            Some(compare) if compare != value => value,
+            _ => self.data,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_codes_decode() {
+        assert_eq!(GenieCode::decode("GOSSIP"), Ok(GenieCode { addr: 0xD1DD, data: 0x14, compare: None }));
+        assert_eq!(GenieCode::decode("zexpygla"), Ok(GenieCode { addr: 0x94A7, data: 0x02, compare: Some(0x03) }));
+        assert_eq!(GenieCode::decode("GOSSI"), Err(GenieError::InvalidLength(5)));
+        assert_eq!(GenieCode::decode("GOSSIB"), Err(GenieError::InvalidLetter('B')));
+    }
+}
@@ -0,0 +1,134 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/*
+Golden Frames:
+
+Pins the rendered output of a ROM so rendering changes show up as
+failures. A golden file stores how many frames were run from
+power-on and the CRC32 of the frame buffer afterwards, in the same
+"key: value" lines the headless runner prints -
+
+    frames: 60
+    frame_crc32: 1a2b3c4d
+
+Goldens live next to their ROM (roms/test.nes -> roms/test.golden).
+When a change to the picture is intended, run with BLESS_ENV set
+(NES_EMU_BLESS=1) to write the new hash instead of comparing.
+*/
+
+pub const BLESS_ENV: &str = "NES_EMU_BLESS";
+
+#[derive(Debug, Error)]
+pub enum GoldenError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("malformed golden file: {0}")]
+    Parse(String),
+    #[error("no golden recorded yet, run with {BLESS_ENV}=1 to create it")]
+    Missing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Golden {
+    pub frames: u32,
+    pub frame_crc32: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GoldenCheck {
+    Matched,
+    Mismatched { expected: Golden },
+    //written because BLESS_ENV was set
+    Blessed,
+}
+
+impl Golden {
+    pub fn new(frames: u32, frame_buffer: &[u8]) -> Self {
+        Golden { frames, frame_crc32: crc32fast::hash(frame_buffer) }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, GoldenError> {
+        let mut frames = None;
+        let mut frame_crc32 = None;
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let parse_err = || GoldenError::Parse(line.to_string());
+            let (key, value) = line.split_once(':').ok_or_else(parse_err)?;
+            match key.trim() {
+                "frames" => frames = Some(value.trim().parse::<u32>().map_err(|_| parse_err())?),
+                "frame_crc32" => frame_crc32 = Some(u32::from_str_radix(value.trim(), 16).map_err(|_| parse_err())?),
+                _ => return Err(parse_err()),
+            }
+        }
+        match (frames, frame_crc32) {
+            (Some(frames), Some(frame_crc32)) => Ok(Golden { frames, frame_crc32 }),
+            _ => Err(GoldenError::Parse("expected frames and frame_crc32".to_string())),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        format!("frames: {}\nframe_crc32: {:08x}\n", self.frames, self.frame_crc32)
+    }
+
+    pub fn path(rom: &Path) -> PathBuf {
+        rom.with_extension("golden")
+    }
+
+    //compares against the stored golden, or replaces it when blessing
+    pub fn check(&self, path: &Path) -> Result<GoldenCheck, GoldenError> {
+        if env::var_os(BLESS_ENV).is_some_and(|value| value != "0") {
+            fs::write(path, self.to_text())?;
+            return Ok(GoldenCheck::Blessed);
+        }
+        let expected = match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(GoldenError::Missing),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(if expected == *self { GoldenCheck::Matched } else { GoldenCheck::Mismatched { expected } })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::demo::{Demo, DemoProgram};
+    use crate::display::Frame;
+
+    //the easy6502 bitmap program, long enough for it to have filled the display
+    const BMP_FRAMES: u32 = 120;
+
+    #[test]
+    fn test_bmp_display_matches_its_golden() {
+        let mut demo = Demo::new(DemoProgram::Bmp, 0).unwrap();
+        for _ in 0..BMP_FRAMES {
+            demo.step_frame();
+        }
+        let mut frame = Frame::new((0, 0, 0));
+        demo.render(&mut frame);
+        //easy6502 colour 7, yellow
+        assert_eq!(frame.data[(120 * 256 + 128) * 4..][..3], [0xEE, 0xEE, 0x77]);
+        let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/goldens/test_bmp_display.golden"));
+        match Golden::new(BMP_FRAMES, &frame.data).check(path).unwrap() {
+            GoldenCheck::Mismatched { expected } => panic!(
+                "frame_crc32 {:08x} after {} frames, expected {:08x} after {}",
+                crc32fast::hash(&frame.data), BMP_FRAMES, expected.frame_crc32, expected.frames,
+            ),
+            GoldenCheck::Matched | GoldenCheck::Blessed => (),
+        }
+    }
+
+    #[test]
+    fn golden_files_round_trip() {
+        let golden = Golden { frames: 60, frame_crc32: 0x01ab23cd };
+        assert_eq!(golden.to_text(), "frames: 60\nframe_crc32: 01ab23cd\n");
+        assert_eq!(Golden::parse(&golden.to_text()).unwrap(), golden);
+        assert!(matches!(Golden::parse("frames: 60"), Err(GoldenError::Parse(_))));
+        assert!(matches!(Golden::parse("frames: 60\nframe_crc32: 0\nfps: 60"), Err(GoldenError::Parse(_))));
+        assert_eq!(Golden::path(Path::new("roms/test.nes")), Path::new("roms/test.golden"));
+    }
+}
@@ -19,77 +19,357 @@ A -> B -> Select -> Start -> Up -> Down -> Left -> Right
 
 The controller has a strobe flag as well. When in strobe mode,
 the bit-shift is disabled and reset to the A button. Resetting
-the strobe flag enables the bit-shift.
+the strobe flag latches the button states and enables the
+bit-shift. Once all eight buttons have been shifted out, reads
+return 1 until the controller is strobed again.
+
+A real D-pad cannot press Left+Right or Up+Down at once and some
+games glitch when they see it, but a keyboard can hold both. The
+opposing direction filter decides what the console sees instead.
+
+Turbo (auto-fire) is not part of the standard controller, but is
+common on third party pads. A button with turbo enabled alternates
+between pressed and released every `rate` frames while held, starting
+out pressed.
 */
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    //bit of this button within the controller's button state byte
+    pub fn mask(self) -> u8 {
+        match self {
+            Button::A => 0x80,
+            Button::B => 0x40,
+            Button::Select => 0x20,
+            Button::Start => 0x10,
+            Button::Up => 0x08,
+            Button::Down => 0x04,
+            Button::Left => 0x02,
+            Button::Right => 0x01,
+        }
+    }
+    //index of this button's bit, 0 for Right up to 7 for A
+    fn bit(self) -> usize {
+        self.mask().trailing_zeros() as usize
+    }
+}
+
+/*
+Key Map:
+
+Keyboard scancode for each button, indexed by Button::bit (Right
+first up to A). The default is WASD for the D-pad, V/B for
+Select/Start and U/I for A/B.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyMap {
+    pub scancodes: [u32; 8],
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap { scancodes: [32, 30, 31, 17, 48, 47, 23, 22] }
+    }
+}
+
+impl KeyMap {
+    pub fn set(&mut self, button: Button, scancode: u32) {
+        self.scancodes[button.bit()] = scancode;
+    }
+    pub fn button(&self, scancode: u32) -> Option<Button> {
+        [Button::A, Button::B, Button::Select, Button::Start, Button::Up, Button::Down, Button::Left, Button::Right]
+            .into_iter()
+            .find(|&button| self.scancodes[button.bit()] == scancode)
+    }
+}
+
+const HORIZONTAL: u8 = 0x03;
+const VERTICAL: u8 = 0x0C;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OpposingDirections {
+    //neither direction is reported, like an unpressed pad
+    CancelBoth,
+    //the direction pressed most recently wins
+    LastPressed,
+}
+
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Controller {
     shift: u8,
+    //buttons physically held down
+    held: u8,
+    //buttons the console sees, held buttons minus turbo buttons in their released phase
     button_states: u8,
-    strobe: bool
+    //button states captured when the strobe was released, what reads shift out
+    latched: u8,
+    strobe: bool,
+    pub opposing_directions: OpposingDirections,
+    //most recently pressed direction on each axis
+    last_directions: u8,
+    //frames per turbo phase for each button bit, 0 when turbo is off
+    turbo_rate: [u8; 8],
+    //frames each turbo button has been held for
+    turbo_frames: [u32; 8],
+    //frontend setting, not part of the console state
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub key_map: KeyMap,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Controller {
     pub fn new() -> Self {
         Controller {
             shift: 0x80,
+            held: 0x00,
             button_states: 0x00,
-            strobe: false
+            latched: 0x00,
+            strobe: false,
+            opposing_directions: OpposingDirections::CancelBoth,
+            last_directions: 0x00,
+            turbo_rate: [0; 8],
+            turbo_frames: [0; 8],
+            key_map: KeyMap::default(),
         }
     }
+    //back to the power-on state, turbo, direction and key settings are kept since they belong to the pad
+    pub fn power_on(&mut self) {
+        *self = Controller {
+            opposing_directions: self.opposing_directions,
+            turbo_rate: self.turbo_rate,
+            key_map: self.key_map,
+            ..Controller::new()
+        };
+    }
     pub fn read(&mut self) -> u8 {
-        let mut res = 0;
-        if self.button_states & self.shift != 0 {
-            res |= 0x01;
+        //while strobing the shift register keeps reloading, so A is read live
+        if self.strobe {
+            return (self.button_states & Button::A.mask() != 0) as u8;
         }
-        if !self.strobe {
-            self.shift = self.shift >> 1;
-            if self.shift == 0 {
-                self.shift = 0x80
-            }
+        //all eight buttons have been shifted out
+        if self.shift == 0 {
+            return 0x01;
         }
+        let res = (self.latched & self.shift != 0) as u8;
+        self.shift >>= 1;
         res
     }
+    //the bit the next read returns, without shifting
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            return (self.button_states & Button::A.mask() != 0) as u8;
+        }
+        if self.shift == 0 {
+            return 0x01;
+        }
+        (self.latched & self.shift != 0) as u8
+    }
     pub fn set_strobe(&mut self) {
         self.strobe = true;
         self.shift = 0x80;
     }
     pub fn reset_strobe(&mut self) {
+        if self.strobe {
+            self.latched = self.button_states;
+        }
         self.strobe = false;
     }
+    pub fn button_states(&self) -> u8 {
+        self.button_states
+    }
+    pub fn set_button_states(&mut self, states: u8) {
+        self.held = states;
+        self.button_states = states;
+    }
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            if self.held & button.mask() == 0 {
+                self.turbo_frames[button.bit()] = 0;
+            }
+            for axis in [HORIZONTAL, VERTICAL] {
+                if button.mask() & axis != 0 {
+                    self.last_directions = (self.last_directions & !axis) | button.mask();
+                }
+            }
+            self.held |= button.mask();
+        } else {
+            self.held &= !button.mask();
+        }
+        self.update_states();
+    }
+    pub fn set_opposing_directions(&mut self, mode: OpposingDirections) {
+        self.opposing_directions = mode;
+        self.update_states();
+    }
+    //rate is the number of frames per pressed/released phase, 0 turns turbo off
+    pub fn set_turbo(&mut self, button: Button, rate: u8) {
+        self.turbo_rate[button.bit()] = rate;
+        self.update_states();
+    }
+    //advances the turbo counters, called once per emulated frame
+    pub fn next_frame(&mut self) {
+        for bit in 0..8 {
+            if self.held & (1 << bit) != 0 {
+                self.turbo_frames[bit] = self.turbo_frames[bit].wrapping_add(1);
+            }
+        }
+        self.update_states();
+    }
+    //recomputes what the console sees from the held buttons
+    fn update_states(&mut self) {
+        let mut released = 0;
+        for bit in 0..8 {
+            let rate = self.turbo_rate[bit] as u32;
+            if rate != 0 && (self.turbo_frames[bit] / rate) % 2 == 1 {
+                released |= 1 << bit;
+            }
+        }
+        for axis in [HORIZONTAL, VERTICAL] {
+            if self.held & axis == axis {
+                released |= match self.opposing_directions {
+                    OpposingDirections::CancelBoth => axis,
+                    OpposingDirections::LastPressed => axis & !self.last_directions,
+                };
+            }
+        }
+        self.button_states = self.held & !released;
+    }
     pub fn keyboard_input(&mut self, key: KeyboardInput) {
-        let mut button: u8 = 0x00;
-        match key.scancode {
-            22 => { //U
-                button = 0x80;
-            },
-            23 => { //I
-                button = 0x40;
-            },
-            47 => { //V
-                button = 0x20;
-            },
-            48 => { //B
-                button = 0x10;
-            },
-            17 => { //W
-                button = 0x08;
-            },
-            31 => { //S
-                button = 0x04;
-            },
-            30 => { //A
-                button = 0x02;
-            },
-            32 => { //D
-                button = 0x01;
-            },
-            _ => (),
+        if let Some(button) = self.key_map.button(key.scancode) {
+            self.set_button(button, key.state == ElementState::Pressed);
         }
-        if key.state == ElementState::Pressed {
-            self.button_states |= button;
-        } else {
-            self.button_states &= !button;
+    }
+}
+
+/*
+Expansion Input:
+
+Input lines beyond the two pads, like the Vs. System's coin slots
+and DIP switches (read through the upper bits of $4016/$4017) or a
+multicart's menu/reset line (somewhere in $4020-$5FFF). Each line
+is one bit at one address, either held by a key or set like a
+switch. The bits a line is mapped to are driven by it, 0 while it
+is inactive, instead of reading back as open bus.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExpansionLine {
+    pub addr: u16,
+    pub mask: u8,
+    //key that holds the line active, None for a switch set through Expansion::set
+    pub scancode: Option<u32>,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Expansion {
+    pub lines: Vec<ExpansionLine>,
+}
+
+impl Expansion {
+    //adds a line, returns its index for set
+    pub fn map(&mut self, line: ExpansionLine) -> usize {
+        self.lines.push(line);
+        self.lines.len() - 1
+    }
+    pub fn set(&mut self, index: usize, active: bool) {
+        if let Some(line) = self.lines.get_mut(index) {
+            line.active = active;
+        }
+    }
+    //bits at `addr` driven by a line, whether active or not
+    pub fn driven(&self, addr: u16) -> u8 {
+        self.lines.iter().filter(|line| line.addr == addr).fold(0, |mask, line| mask | line.mask)
+    }
+    //bits at `addr` set by active lines
+    pub fn read(&self, addr: u16) -> u8 {
+        self.lines.iter().filter(|line| line.addr == addr && line.active).fold(0, |value, line| value | line.mask)
+    }
+    pub fn keyboard_input(&mut self, key: KeyboardInput) {
+        for line in self.lines.iter_mut().filter(|line| line.scancode == Some(key.scancode)) {
+            line.active = key.state == ElementState::Pressed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turbo_alternates_at_the_configured_rate() {
+        let mut pad = Controller::new();
+        pad.set_turbo(Button::A, 3);
+        pad.set_button(Button::A, true);
+        pad.set_button(Button::B, true);
+        let mut seen = Vec::new();
+        for _ in 0..12 {
+            seen.push(pad.button_states() & Button::A.mask() != 0);
+            //B has no turbo and stays down
+            assert_ne!(pad.button_states() & Button::B.mask(), 0);
+            pad.next_frame();
         }
+        let (on, off) = (true, false);
+        assert_eq!(seen, [on, on, on, off, off, off, on, on, on, off, off, off]);
+
+        //releasing and pressing again starts in the pressed phase
+        pad.set_button(Button::A, false);
+        assert_eq!(pad.button_states() & Button::A.mask(), 0);
+        pad.set_button(Button::A, true);
+        assert_ne!(pad.button_states() & Button::A.mask(), 0);
+    }
+
+    #[test]
+    fn reads_past_the_eighth_button_return_one() {
+        let mut pad = Controller::new();
+        pad.set_button(Button::A, true);
+        pad.set_button(Button::Right, true);
+        pad.set_strobe();
+        pad.reset_strobe();
+        //a press after the latch waits for the next strobe
+        pad.set_button(Button::Start, true);
+        let reads: Vec<u8> = (0..11).map(|_| pad.read()).collect();
+        assert_eq!(reads, [1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1]);
+
+        pad.set_strobe();
+        pad.reset_strobe();
+        let reads: Vec<u8> = (0..9).map(|_| pad.read()).collect();
+        assert_eq!(reads, [1, 0, 0, 1, 0, 0, 0, 1, 1]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn opposing_directions_cancel_or_follow_the_last_press() {
+        let mut pad = Controller::new();
+        pad.set_button(Button::Left, true);
+        pad.set_button(Button::Right, true);
+        pad.set_button(Button::Up, true);
+        assert_eq!(pad.button_states(), Button::Up.mask());
+
+        pad.set_opposing_directions(OpposingDirections::LastPressed);
+        assert_eq!(pad.button_states(), Button::Right.mask() | Button::Up.mask());
+        pad.set_button(Button::Down, true);
+        assert_eq!(pad.button_states(), Button::Right.mask() | Button::Down.mask());
+
+        //letting go of one direction brings back the other
+        pad.set_opposing_directions(OpposingDirections::CancelBoth);
+        pad.set_button(Button::Right, false);
+        assert_eq!(pad.button_states(), Button::Left.mask());
+    }
+}
@@ -0,0 +1,25 @@
+/*************** Emulation Core Modules ***************/
+pub mod cpu;
+pub mod ppu;
+pub mod display;
+pub mod palette;
+pub mod input;
+pub mod bus;
+pub mod rom;
+pub mod mapper;
+pub mod genie;
+pub mod cheats;
+pub mod monitor;
+pub mod emu;
+pub mod movie;
+pub mod rewind;
+pub mod config;
+pub mod profile;
+pub mod pacing;
+pub mod golden;
+pub mod demo;
+#[cfg(feature = "netplay")]
+pub mod netplay;
+/******************************************************/
+
+pub use emu::Emulator;
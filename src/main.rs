@@ -1,10 +1,4 @@
 /************************************** Linking External Modules **************************************/
-mod cpu;
-mod ppu;
-mod display;
-mod input;
-mod bus;
-mod rom;
 mod shader;
 mod shader_program;
 mod vertex_array;
@@ -12,28 +6,33 @@ mod buffer;
 mod renderer;
 mod texture;
 use crate::renderer::Renderer;
-use glium::glutin::event::KeyboardInput;
+use glium::glutin::event::{KeyboardInput, VirtualKeyCode};
 use glium::glutin::event::{Event, WindowEvent, DeviceEvent, ElementState};
 use glium::glutin::event_loop::{ControlFlow, EventLoop};
 use glium::glutin::window::{WindowBuilder, Fullscreen};
 use glium::glutin::{Api, ContextBuilder, GlRequest};
 use glium::glutin::dpi::{Size, PhysicalSize};
-use rfd::FileDialog;
+use rfd::{FileDialog, MessageDialog, MessageLevel};
 use futures::executor::block_on;
-use std::time::{Duration, Instant};
-use std::fs::File;
+use log::{info, warn};
+use std::time::Duration;
+use std::env;
+use std::fs;
 use std::path::PathBuf;
-use std::io::Read;
+use std::process;
 use std::sync::mpsc;
 use std::thread;
-use cpu::*;
-use bus::Bus;
-use rom::Rom;
+use nes_emu::config::Config;
+use nes_emu::demo::{Demo, DemoProgram};
+use nes_emu::display;
+use nes_emu::input::{Button, KeyMap};
+use nes_emu::pacing::{Clock, FramePacer, WallClock};
+use nes_emu::palette;
+use nes_emu::profile::Profile;
+use nes_emu::Emulator;
 /******************************************************************************************************/
 
 /***************** Display Dimensions *****************/
-const WINDOW_WIDTH: u32 = 1024;
-const WINDOW_HEIGHT: u32 = 960;
 const SCREEN_WIDTH: u32 = 256;
 const SCREEN_HEIGHT: u32 = 240;
 /******************************************************/
@@ -43,60 +42,125 @@ const SCREEN_HEIGHT: u32 = 240;
 /*
 Startup Function:
 
-Runs once during the first frame of execution. Initializes all
-emulated hardware components
+Runs once during the first frame of execution. Prompts for a ROM
+and initializes all emulated hardware components. Also returns the
+ROM's file name for the window title.
 */
-fn startup() -> CPU {
+fn startup(config: &Config) -> (Emulator, String) {
 
     //Prompt User to select rom (via file dialog box)
     let mut filename = FileDialog::new()
     .add_filter("NES ROM", &["nes"])
     .pick_file();
 
-    let file = File::open(filename.get_or_insert(PathBuf::new()));
-    
-    //load ROM
-    let mut raw = Vec::new();
-    let _ = file.expect("REASON").read_to_end(&mut raw);
-    let rom = Rom::new(&raw);
-
-    //generate Memory Bus
-    let bus = Bus::new(rom);
-
-    //generate CPU
-    let mut cpu_6502 = CPU::new(bus);
-    cpu_6502.reset();
-
-    /*
-    output CPU as return value. CPU is already connected 
-    to all hardware peripherals via memory bus.
-    */
-    cpu_6502
+    let raw = fs::read(filename.get_or_insert(PathBuf::new())).expect("Unable to load ROM file");
+    let mut emulator = Emulator::with_mirroring(&raw, config.mirroring.clone()).expect("Unable to load ROM file");
+
+    //per-game settings, keyed by the ROM's CRC32, override the global ones below
+    let profile_path = Profile::path(&config.profile_dir, emulator.rom_hash());
+    let profile = if profile_path.exists() {
+        match Profile::load(&profile_path) {
+            Ok(profile) => {
+                info!("Loaded profile {}", profile_path.display());
+                Some(profile)
+            },
+            Err(e) => {
+                warn!("Ignoring profile {}: {}", profile_path.display(), e);
+                None
+            },
+        }
+    } else {
+        None
+    };
+    //the mirroring is fixed at boot, start over with the profile's
+    if let Some(mirroring) = profile.as_ref().and_then(|profile| profile.mirroring.clone()) {
+        emulator = Emulator::with_mirroring(&raw, Some(mirroring)).expect("Unable to load ROM file");
+    }
+
+    //optional custom colours: --palette <file.pal>
+    if let Some(path) = &config.palette {
+        emulator.set_palette(palette::load_palette(path));
+    }
+    //or a generated one: --ntsc-palette H,S,B,C
+    if let Some(params) = &config.ntsc_palette {
+        emulator.set_palette(palette::generate_palette(params));
+    }
+    for &line in &config.expansion {
+        emulator.cpu.mem_bus.expansion.map(line);
+    }
+    for code in &config.cheats {
+        match emulator.cheats.add(code) {
+            Ok(cheat) => info!("Cheat enabled: {}", cheat),
+            Err(e) => warn!("Skipping cheat: {}", e),
+        }
+    }
+    if let Some(profile) = &profile {
+        emulator.apply_profile(profile);
+    }
+    let rom_name = filename.unwrap_or_default()
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    (emulator, rom_name)
 }
 
 /*
-Update Function:
+Input Recording Hotkeys:
 
-Runs once per frame of app execution. Drives the CPU and PPU
-functionality for the duration of a single screen update.
+Recordings are kept in memory until stopped, then written to a
+file picked via a save dialog. Playback loads a file picked via
+an open dialog and restarts the console from power-on.
 */
+fn toggle_recording(emulator: &mut Emulator) {
+    if let Some(movie) = emulator.stop_recording() {
+        if let Some(path) = FileDialog::new().add_filter("Input Recording", &["txt"]).save_file() {
+            if let Err(e) = movie.save(&path) {
+                println!("Failed to save recording: {}", e);
+            }
+        }
+    } else {
+        emulator.start_recording();
+    }
+}
 
-#[inline(always)]
-fn update(cpu_6502: &mut CPU, frame: &mut display::Frame){
-    *frame = display::Frame::new(display::SYSTEM_PALLETE[cpu_6502.mem_bus.ppu.palette_table[0] as usize]);
-    let mut scanline = 0;
-    while cpu_6502.mem_bus.ppu.scanlines <= 240 {
-        scanline += 8;
-        display::render(&cpu_6502.mem_bus.ppu, frame, scanline - 8, scanline);
-        while (cpu_6502.mem_bus.ppu.scanlines as usize) < scanline{
-            cpu_6502.interpret();
+//writes OAM, the nametables and the palette to a text file picked via a save dialog
+fn dump_ppu(emulator: &Emulator) {
+    if let Some(path) = FileDialog::new().add_filter("PPU Dump", &["txt"]).save_file() {
+        if let Err(e) = fs::write(&path, emulator.cpu.mem_bus.ppu.dump_text()) {
+            println!("Failed to write PPU dump: {}", e);
         }
     }
+}
 
-    while cpu_6502.mem_bus.ppu.scanlines > 240 {
-        cpu_6502.interpret();
+fn toggle_playback(emulator: &mut Emulator) {
+    if emulator.is_playing() {
+        emulator.stop_playback();
+    } else if let Some(path) = FileDialog::new().add_filter("Input Recording", &["txt", "fm2"]).pick_file() {
+        if let Err(e) = emulator.play_file(&path) {
+            println!("Failed to load recording: {}", e);
+        }
+    }
+}
+
+//messages from the window thread to the compute thread
+enum ControlMsg {
+    //key events since the last frame, run the next one
+    Frame(Vec<KeyboardInput>),
+    //run to the next scanline, only while paused, there is no reply
+    StepScanline,
+    //the window is closing, return so the thread can be joined
+    Quit,
+}
+
+//waits for the next frame's key events, handling any StepScanline on the way, None once told to quit
+fn recv_frame(rx: &mpsc::Receiver<ControlMsg>, mut step_scanline: impl FnMut()) -> Option<Vec<KeyboardInput>> {
+    loop {
+        match rx.recv() {
+            Ok(ControlMsg::Frame(inputs)) => return Some(inputs),
+            Ok(ControlMsg::StepScanline) => step_scanline(),
+            Ok(ControlMsg::Quit) | Err(_) => return None,
+        }
     }
-    
 }
 
 /*
@@ -109,32 +173,209 @@ loop, passing data to and from the window loop as necessary.
 
 Application Loop Logic -
     Recieve/Handle Input -> Compute Frame -> Send Frame
+until ControlMsg::Quit arrives (or the window thread is gone).
 */
-fn compute_thread(tx: mpsc::SyncSender<[u8; (SCREEN_HEIGHT * SCREEN_WIDTH * 4) as usize]>, rx: mpsc::Receiver<Option<KeyboardInput>>) {
-    //Initialize Display Frame, CPU (+ Peripherals), and Input Container
-    let mut frame = display::Frame::new((0, 0, 0));
-    let mut cpu_6502 = startup();
-    tx.send(frame.data).unwrap();
-    let mut input_option = rx.recv().unwrap();
+fn compute_thread(tx: mpsc::SyncSender<Option<[u8; (SCREEN_HEIGHT * SCREEN_WIDTH * 4) as usize]>>, rx: mpsc::Receiver<ControlMsg>, title_tx: mpsc::Sender<String>, config: Config) {
+    //Initialize Emulator (CPU + Peripherals + Display Frame)
+    let (mut emulator, rom_name) = startup(&config);
+    emulator.set_frameskip(config.frameskip);
+    if config.fast_boot {
+        emulator.set_ppu_warm_up(false);
+    }
+    if config.ram_seed.is_some() {
+        emulator.set_ram_seed(config.ram_seed);
+    }
+    if let Some(path) = &config.replay {
+        if let Err(e) = emulator.play_file(path) {
+            println!("Failed to load replay: {}", e);
+        }
+    }
+    let _ = title_tx.send(rom_name);
+    let mut debug_view: Option<display::DebugView> = None;
+    let mut rewinding = false;
+    let mut overlay = false;
+    let mut paused = false;
+    let mut palette_params = config.ntsc_palette.unwrap_or_default();
+    let mut palette_param = 0;
+    //a failed send means the window thread is gone, there is nobody left to emulate for
+    if tx.send(Some(emulator.frame_buffer().try_into().unwrap())).is_err() {
+        return;
+    }
+    if recv_frame(&rx, || ()).is_none() {
+        return;
+    }
 
 
     //Application Loop
-    while true {
-        //Recieve/Parse Input From Window Thread
-        input_option = rx.recv().unwrap();
-        match input_option{
-            //if button is pressed, update emulated controller 
-            //state as needed
-            Some(input) => {
-                cpu_6502.mem_bus.port1.keyboard_input(input);
+    while let Some(inputs) = recv_frame(&rx, || if paused {
+        println!("Scanline {}", emulator.step_scanline());
+    }) {
+        //Recieve/Parse Input From Window Thread, every key event
+        //since the last frame is applied in the order it happened
+        for input in inputs {
+            //F1 cycles through the debug views
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F1) {
+                debug_view = match debug_view {
+                    None => Some(display::DebugView::PatternTables),
+                    Some(display::DebugView::PatternTables) => Some(display::DebugView::Nametables),
+                    Some(display::DebugView::Nametables) => Some(display::DebugView::BackgroundLayer),
+                    Some(display::DebugView::BackgroundLayer) => Some(display::DebugView::SpriteLayer),
+                    Some(display::DebugView::SpriteLayer) => None,
+                };
+            }
+            //F2 pauses emulation and opens the monitor on stdin
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F2) {
+                emulator.run_monitor();
+            }
+            //F3 starts/stops recording input to a file
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F3) {
+                toggle_recording(&mut emulator);
+            }
+            //F4 starts/stops playing back a recording
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F4) {
+                toggle_playback(&mut emulator);
+            }
+            //F5 toggles the 8 sprites per scanline limit
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F5) {
+                let ppu = &mut emulator.cpu.mem_bus.ppu;
+                ppu.sprite_limit = !ppu.sprite_limit;
+                println!("Sprite limit {}", if ppu.sprite_limit { "on" } else { "off" });
+            }
+            //F7 halts on the next BRK and opens the monitor
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F7) {
+                emulator.cpu.set_brk_stop(true);
+                println!("Stopping on next BRK");
+            }
+            //F8 dumps OAM, nametables and palette to a text file
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F8) {
+                dump_ppu(&emulator);
+            }
+            //F9 toggles the sprite/scroll split overlay
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F9) {
+                overlay = !overlay;
+            }
+            //Pause stops/resumes emulation, F10 steps a scanline while paused (sent by the window thread)
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::Pause) {
+                paused = !paused;
+                println!("{}", if paused { "Paused" } else { "Resumed" });
+            }
+            //F11 picks a palette control, PageUp/PageDown adjust it (switching to the generated palette)
+            if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F11) {
+                palette_param = (palette_param + 1) % palette::PALETTE_PARAMS.len();
+                println!("Adjusting palette {}", palette::PALETTE_PARAMS[palette_param]);
+            }
+            if input.state == ElementState::Pressed && matches!(input.virtual_keycode, Some(VirtualKeyCode::PageUp | VirtualKeyCode::PageDown)) {
+                let steps = if input.virtual_keycode == Some(VirtualKeyCode::PageUp) { 1 } else { -1 };
+                let value = palette_params.adjust(palette_param, steps);
+                emulator.set_palette(palette::generate_palette(&palette_params));
+                println!("Palette {} {:.2}", palette::PALETTE_PARAMS[palette_param], value);
+            }
+            //holding Backspace rewinds
+            if input.virtual_keycode == Some(VirtualKeyCode::Back) {
+                rewinding = input.state == ElementState::Pressed;
+            }
+            emulator.cpu.mem_bus.port1.keyboard_input(input);
+            emulator.cpu.mem_bus.expansion.keyboard_input(input);
+        }
+        //compute for one frame update (or step back one snapshot), a paused frame is shown as it is
+        if !paused {
+            if rewinding {
+                emulator.rewind();
+            } else {
+                emulator.step_frame();
+            }
+        }
+        //send frame (or the active debug view) to window thread, nothing for a skipped frame
+        let frame = match &debug_view {
+            Some(view) => Some(display::render_debug_view(&emulator.cpu.mem_bus.ppu, emulator.layers(), view).data),
+            None if emulator.frame_rendered() && overlay => {
+                let mut frame = display::Frame { data: emulator.frame_buffer().try_into().unwrap() };
+                display::draw_overlay(&emulator.cpu.mem_bus.ppu, &mut frame);
+                Some(frame.data)
             },
-            //otherwise, do nothing
-            _ => ()
+            None if emulator.frame_rendered() || paused => Some(emulator.frame_buffer().try_into().unwrap()),
+            None => None,
+        };
+        if tx.send(frame).is_err() {
+            return;
         }
-        //compute for one frame update
-        update(&mut cpu_6502, &mut frame);
-        //send frame to window thread
-        tx.send(frame.data).unwrap();
+    }
+}
+
+/*
+Demo Thread:
+
+Stands in for the compute thread with --demo, running one of the
+easy6502 test programs instead of a ROM. The D-pad keys are passed
+on as easy6502's w/a/s/d key codes.
+*/
+fn demo_thread(tx: mpsc::SyncSender<Option<[u8; (SCREEN_HEIGHT * SCREEN_WIDTH * 4) as usize]>>, rx: mpsc::Receiver<ControlMsg>, title_tx: mpsc::Sender<String>, program: DemoProgram, seed: u64) {
+    let mut demo = Demo::new(program, seed).expect("Unable to build demo cartridge");
+    let key_map = KeyMap::default();
+    let mut frame = display::Frame::new((0, 0, 0));
+    let _ = title_tx.send(format!("{:?} demo", program));
+    demo.render(&mut frame);
+    if tx.send(Some(frame.data)).is_err() {
+        return;
+    }
+    if recv_frame(&rx, || ()).is_none() {
+        return;
+    }
+
+    while let Some(inputs) = recv_frame(&rx, || ()) {
+        for input in inputs.iter().filter(|input| input.state == ElementState::Pressed) {
+            match key_map.button(input.scancode) {
+                Some(Button::Up) => demo.set_key(b'w'),
+                Some(Button::Left) => demo.set_key(b'a'),
+                Some(Button::Down) => demo.set_key(b's'),
+                Some(Button::Right) => demo.set_key(b'd'),
+                _ => (),
+            }
+        }
+        demo.step_frame();
+        demo.render(&mut frame);
+        if tx.send(Some(frame.data)).is_err() {
+            return;
+        }
+    }
+}
+
+//asks the compute thread to quit and waits for it, it drops its frame sender on return
+fn stop_compute_thread<T>(tx: &mpsc::SyncSender<ControlMsg>, rx: &mpsc::Receiver<T>, compute: Option<thread::JoinHandle<()>>) {
+    if tx.send(ControlMsg::Quit).is_ok() {
+        while rx.recv().is_ok() {}
+    }
+    if let Some(compute) = compute {
+        let _ = compute.join();
+    }
+}
+
+/*
+Compute Thread Failure:
+
+A panic in the compute thread (unknown opcode, unloadable ROM)
+closes its ends of the channels. The window thread notices on its
+next send/recv, collects the panic message through the join
+handle, and reports it before exiting instead of hanging or
+panicking itself.
+*/
+fn report_compute_failure(compute: Option<thread::JoinHandle<()>>) {
+    let message = failure_message(compute);
+    eprintln!("Emulation stopped: {}", message);
+    MessageDialog::new()
+        .set_level(MessageLevel::Error)
+        .set_title("NES Emu")
+        .set_description(&format!("Emulation stopped: {}", message))
+        .show();
+}
+
+//waits for the stopped compute thread, returning its panic message
+fn failure_message(compute: Option<thread::JoinHandle<()>>) -> String {
+    match compute.map(|compute| compute.join()) {
+        Some(Err(payload)) => payload.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_string()),
+        _ => "the emulation thread stopped unexpectedly".to_string(),
     }
 }
 
@@ -147,19 +388,19 @@ runs event handling and screen rendering through an application
 loop, passing data to and from the compute thread as necessary.
 
 Application Loop Logic - 
-    Calculate Framerate -> Poll User Input -> Send Input to
-    Compute Thread -> Recieve Display Frame from Compute Thread
-    -> Render Display Frame
+    Collect User Input -> (once all pending events are handled)
+    Calculate Framerate -> Send Input to Compute Thread ->
+    Recieve Display Frame from Compute Thread -> Render Display Frame
 */
-fn window_thread(tx: mpsc::SyncSender<Option<KeyboardInput>>, rx: mpsc::Receiver<[u8; (SCREEN_HEIGHT * SCREEN_WIDTH * 4) as usize]>) {
+fn window_thread(tx: mpsc::SyncSender<ControlMsg>, rx: mpsc::Receiver<Option<[u8; (SCREEN_HEIGHT * SCREEN_WIDTH * 4) as usize]>>, title_rx: mpsc::Receiver<String>, compute: thread::JoinHandle<()>, window_size: (u32, u32), mut overscan: bool, vsync: bool) {
     //Initialize OpenGL Context, Window, and Event Handler
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_inner_size(
             Size::from(
                 PhysicalSize::new(
-                    WINDOW_WIDTH, 
-                    WINDOW_HEIGHT
+                    window_size.0, 
+                    window_size.1
                 )
             )
         )
@@ -167,6 +408,7 @@ fn window_thread(tx: mpsc::SyncSender<Option<KeyboardInput>>, rx: mpsc::Receiver
 
     let gl_context = ContextBuilder::new()
         .with_gl(GlRequest::Specific(Api::OpenGl, (3, 3)))
+        .with_vsync(vsync)
         .build_windowed(window, &event_loop)
         .expect("Cannot create windowed context");
 
@@ -180,62 +422,185 @@ fn window_thread(tx: mpsc::SyncSender<Option<KeyboardInput>>, rx: mpsc::Receiver
 
     //Initialize renderer and framerate calculation variables
     let renderer = Renderer::new().expect("Cannot create renderer");
-    let mut now = Instant::now();
+    let mut pacer = FramePacer::new(WallClock::new(), vsync);
+    let mut now = pacer.clock.now();
     let mut frames = 0;
-    let mut frame_time = Instant::now();
-    let mut input_option: Option<KeyboardInput> = None;
+    //frames that took longer than their 1/60s slot, the compute thread isn't keeping up
+    let mut dropped = 0;
+    info!("Frame pacing: {}", if vsync { "vsync" } else { "timer" });
+    let mut inputs: Vec<KeyboardInput> = Vec::new();
+    let mut rom_name = String::new();
+    let mut compute = Some(compute);
     
     //Application Loop
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
 
-        //Framerate Limiter
-        while frame_time.elapsed() < Duration::new(0,16666666) {}
-        frame_time = Instant::now();
-    
-        //Calculate FPS
-        if now.elapsed() < Duration::new(1, 0) {
-            frames += 1;
-        }
-        else {
-            println!("Framerate: {} fps", frames);
-            frames = 0;
-            now = Instant::now();
-        }
-
-        //Input Handling
-        input_option = None;
         match event {
-            Event::LoopDestroyed => (),
+            Event::LoopDestroyed => stop_compute_thread(&tx, &rx, compute.take()),
+            //Input Handling, queued until the end of the frame
             Event::WindowEvent { event, .. } => {
                 match event {
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                     WindowEvent::Resized(physical_size) => gl_context.resize(physical_size),
-                    WindowEvent::KeyboardInput { device_id, input, .. } => {
-                        input_option = Some(input);
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        //F6 toggles overscan cropping, which only affects drawing
+                        if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F6) {
+                            overscan = !overscan;
+                        }
+                        //F10 steps a scanline, ahead of the frame's other key events
+                        if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::F10) {
+                            let _ = tx.send(ControlMsg::StepScanline);
+                        }
+                        inputs.push(input);
                     },
                     _ => (),
                 }
             },
+            //every pending event has been handled, run one frame
+            Event::MainEventsCleared => {
+                //Calculate FPS, shown in the title once a second along with the speed relative to 60 fps
+                dropped += pacer.take_dropped();
+                if pacer.clock.now() - now < Duration::new(1, 0) {
+                    frames += 1;
+                }
+                else {
+                    if let Ok(name) = title_rx.try_recv() {
+                        rom_name = name;
+                    }
+                    gl_context.window().set_title(&format!("NES Emu - {} - {} fps ({}%) - {} dropped", rom_name, frames, frames * 100 / 60, dropped));
+                    frames = 0;
+                    dropped = 0;
+                    now = pacer.clock.now();
+                }
+
+                //Send Input, then Recieve Frame (skipped frames leave the screen as it is)
+                let frame = match tx.send(ControlMsg::Frame(std::mem::take(&mut inputs))) {
+                    Ok(()) => rx.recv().ok(),
+                    Err(_) => None,
+                };
+                let shown = match frame {
+                    Some(Some(frame)) => {
+                        renderer.draw(if overscan { display::crop_overscan(&frame) } else { &frame });
+                        gl_context.swap_buffers();              //Update Screen with Current Frame
+                        true
+                    },
+                    Some(None) => false,
+                    //the compute thread is gone
+                    None => {
+                        report_compute_failure(compute.take());
+                        *control_flow = ControlFlow::ExitWithCode(1);
+                        false
+                    },
+                };
+
+                //Framerate Limiter, see pacing.rs
+                pacer.end_frame(shown);
+            },
             _ => (),
         }
-
-        tx.send(input_option).unwrap();     //Send Input
-        renderer.draw(&rx.recv().unwrap()); //Recieve Frame
-        gl_context.swap_buffers();          //Update Screen with Current Frame
     });
 }
 
 /***** Main Function *****/
 fn main() {
+    //diagnostics go through the log crate, filtered with RUST_LOG (e.g. RUST_LOG=info)
+    env_logger::init();
+
+    let config = Config::from_args(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        eprintln!("usage: NES_Emu [--scale N] [--palette FILE] [--overscan] [--frameskip N] [--ram-seed N] [--replay FILE] [--vsync] [--profiles DIR] [--mirroring MODE] [--demo NAME] [--fast-boot] [--ntsc-palette H,S,B,C] [--expansion AAAA:B=KEY]... [--cheat AAAA:VV]...");
+        process::exit(2);
+    });
+    let window_size = config.window_size();
+    let overscan = config.overscan;
+    let vsync = config.vsync;
+
     //Initialize Message Passing Channels
     let (frame_send, frame_recv) = mpsc::sync_channel(1);
     let (input_send, input_recv) = mpsc::sync_channel(1);
+    let (title_send, title_recv) = mpsc::channel();
     //Run Compute on Separate Thread
-    thread::spawn( move || {
-        compute_thread(frame_send, input_recv);
+    let compute = thread::spawn( move || {
+        match config.demo {
+            Some(program) => demo_thread(frame_send, input_recv, title_send, program, rand::random()),
+            None => compute_thread(frame_send, input_recv, title_send, config),
+        }
     });
     //Run Graphics Pipeline on Main Thread (Cannot Run on Sub-thread)
-    window_thread(input_send, frame_recv);
+    window_thread(input_send, frame_recv, title_recv, compute, window_size, overscan, vsync);
+}
+/*************************/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glium::glutin::event::ModifiersState;
+    use nes_emu::input::Controller;
+
+    #[allow(deprecated)]
+    fn key(scancode: u32, state: ElementState) -> KeyboardInput {
+        KeyboardInput { scancode, state, virtual_keycode: None, modifiers: ModifiersState::empty() }
+    }
+
+    #[test]
+    fn every_key_event_of_a_frame_reaches_the_controller() {
+        let keys = KeyMap::default();
+        let (a, b) = (keys.scancodes[7], keys.scancodes[6]);
+        let (tx, rx) = mpsc::channel();
+        //A and B pressed, then A let go again, all within one frame
+        tx.send(ControlMsg::Frame(vec![key(a, ElementState::Pressed), key(b, ElementState::Pressed)])).unwrap();
+        tx.send(ControlMsg::Frame(vec![key(b, ElementState::Released), key(a, ElementState::Released), key(a, ElementState::Pressed)])).unwrap();
+        tx.send(ControlMsg::Quit).unwrap();
+
+        let mut pad = Controller::new();
+        let mut states = Vec::new();
+        while let Some(inputs) = recv_frame(&rx, || ()) {
+            for input in inputs {
+                pad.keyboard_input(input);
+            }
+            states.push(pad.button_states());
+        }
+        assert_eq!(states, [Button::A.mask() | Button::B.mask(), Button::A.mask()]);
+    }
+
+    //frames are passed by value, more than a test thread's stack holds
+    fn on_big_stack(test: impl FnOnce() + Send + 'static) {
+        thread::Builder::new().stack_size(16 << 20).spawn(test).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn quit_makes_the_frame_loop_return() {
+        on_big_stack(|| {
+            let (input_send, input_recv) = mpsc::sync_channel(1);
+            let (frame_send, frame_recv) = mpsc::sync_channel(1);
+            let (title_send, _title_recv) = mpsc::channel();
+            let demo = thread::Builder::new().stack_size(16 << 20)
+                .spawn(move || demo_thread(frame_send, input_recv, title_send, DemoProgram::Bmp, 0))
+                .unwrap();
+            assert!(frame_recv.recv().unwrap().is_some());
+            //the first frame message only starts the loop, the second runs a frame
+            input_send.send(ControlMsg::Frame(Vec::new())).unwrap();
+            input_send.send(ControlMsg::Frame(Vec::new())).unwrap();
+            assert!(frame_recv.recv().unwrap().is_some());
+            //returns only once the thread has exited
+            stop_compute_thread(&input_send, &frame_recv, Some(demo));
+            assert!(input_send.send(ControlMsg::Quit).is_err());
+        });
+    }
+
+    #[test]
+    fn a_compute_thread_panic_is_reported() {
+        let (frame_send, frame_recv) = mpsc::sync_channel::<Option<u8>>(1);
+        let compute = thread::spawn(move || {
+            let _frame_send = frame_send;
+            panic!("Unknown opcode $02 at $8000");
+        });
+        //the window thread sees the channel close instead of a frame
+        assert!(frame_recv.recv().is_err());
+        assert_eq!(failure_message(Some(compute)), "Unknown opcode $02 at $8000");
+
+        let compute = thread::spawn(|| panic!("{} is not a NES ROM", "game.txt"));
+        assert_eq!(failure_message(Some(compute)), "game.txt is not a NES ROM");
+        assert_eq!(failure_message(Some(thread::spawn(|| ()))), "the emulation thread stopped unexpectedly");
+    }
 }
-/*************************/
\ No newline at end of file
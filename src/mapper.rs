@@ -0,0 +1,535 @@
+/****************** Linking External Modules ******************/
+use crate::rom::{Mirroring, RomError};
+use log::warn;
+use std::sync::Arc;
+/**************************************************************/
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const MMC3_PRG_BANK_SIZE: usize = 0x2000;
+const MMC3_CHR_BANK_SIZE: usize = 0x0400;
+
+/*
+Mapper:
+
+The cartridge hardware sitting between the console's buses and
+the ROM chips. The CPU side ($8000-$FFFF) is served entirely by
+the mapper. CHR data stays with the PPU, the mapper only decides
+which part of it is visible through the eight 1KB windows at PPU
+$0000-$1FFF (and, for some boards, the nametable mirroring).
+*/
+pub trait Mapper: Send {
+    //iNES mapper number of the board
+    fn id(&self) -> u8;
+    //CPU read from $8000-$FFFF
+    fn read_prg(&self, addr: u16) -> u8;
+    //CPU write to $8000-$FFFF, usually a bank select
+    fn write_prg(&mut self, addr: u16, data: u8);
+    //CHR offsets of the 1KB windows at PPU $0000, $0400, ... $1C00
+    fn chr_banks(&self) -> [usize; 8] {
+        chr_banks_8k(0)
+    }
+    //mirroring selected by the mapper, None if it is hardwired on the board
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+    //puts the bank registers back into their power-on state
+    fn power_on(&mut self) {}
+    //ROM keeps driving the data bus during writes, so a write lands as data & ROM byte
+    fn has_bus_conflicts(&self) -> bool {
+        false
+    }
+    //the PRG-ROM, clones of the mapper share it rather than copying it
+    fn prg_rom(&self) -> &Arc<[u8]>;
+    fn box_clone(&self) -> Box<dyn Mapper>;
+    //the mapper's full state, for save states
+    #[cfg(feature = "serde")]
+    fn snapshot(&self) -> MapperSnapshot;
+}
+
+impl Clone for Box<dyn Mapper> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/*
+Mapper Registry:
+
+The one place iNES mapper numbers are turned into mapper
+implementations. CHR data is only needed for its size, the PPU
+keeps the data itself. Mirroring is the board's power-on mirroring,
+for mappers that can switch it.
+*/
+pub fn create_mapper(id: u8, prg_rom: Vec<u8>, chr_rom: &[u8], mirroring: Mirroring) -> Result<Box<dyn Mapper>, RomError> {
+    match id {
+        0 => Ok(Box::new(Nrom::new(prg_rom))),
+        2 => Ok(Box::new(Uxrom::new(prg_rom))),
+        3 => Ok(Box::new(Cnrom::new(prg_rom, chr_rom.len()))),
+        4 => {
+            //games still run, but raster splits timed by the IRQ land in the wrong place or not at all
+            warn!("MMC3 scanline IRQs are not emulated yet, split-screen effects will be broken");
+            Ok(Box::new(Mmc3::new(prg_rom, chr_rom.len(), mirroring)))
+        },
+        _ => Err(RomError::UnsupportedMapper(id)),
+    }
+}
+
+/*
+Mapper Snapshot:
+
+Trait objects can't be (de)serialized directly, so save states
+go through this enum of every concrete mapper instead. A new
+mapper needs a variant here as well as in create_mapper.
+*/
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum MapperSnapshot {
+    Nrom(Nrom),
+    Uxrom(Uxrom),
+    Cnrom(Cnrom),
+    Mmc3(Mmc3),
+}
+
+#[cfg(feature = "serde")]
+impl From<MapperSnapshot> for Box<dyn Mapper> {
+    fn from(snapshot: MapperSnapshot) -> Self {
+        match snapshot {
+            MapperSnapshot::Nrom(mapper) => Box::new(mapper),
+            MapperSnapshot::Uxrom(mapper) => Box::new(mapper),
+            MapperSnapshot::Cnrom(mapper) => Box::new(mapper),
+            MapperSnapshot::Mmc3(mapper) => Box::new(mapper),
+        }
+    }
+}
+
+//serde `with` module for Box<dyn Mapper> fields
+#[cfg(feature = "serde")]
+pub mod snapshot {
+    use super::{Mapper, MapperSnapshot};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    //serde hands over a reference to the field itself
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S: Serializer>(mapper: &Box<dyn Mapper>, serializer: S) -> Result<S::Ok, S::Error> {
+        mapper.snapshot().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Box<dyn Mapper>, D::Error> {
+        MapperSnapshot::deserialize(deserializer).map(Into::into)
+    }
+}
+
+//window offsets for one contiguous 8KB CHR bank
+fn chr_banks_8k(base: usize) -> [usize; 8] {
+    let mut banks = [0; 8];
+    for (i, bank) in banks.iter_mut().enumerate() {
+        *bank = base + i * 0x400;
+    }
+    banks
+}
+
+/*
+NROM (mapper 0):
+
+No bank switching. 16KB of PRG-ROM is mirrored into both halves
+of $8000-$FFFF, 32KB fills it.
+*/
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nrom {
+    prg_rom: Arc<[u8]>,
+}
+
+impl Nrom {
+    pub fn new(prg_rom: Vec<u8>) -> Self {
+        Nrom { prg_rom: prg_rom.into() }
+    }
+}
+
+impl Mapper for Nrom {
+    fn id(&self) -> u8 {
+        0
+    }
+    fn read_prg(&self, addr: u16) -> u8 {
+        let addr = (addr - 0x8000) as usize;
+        self.prg_rom[addr % self.prg_rom.len()]
+    }
+    fn write_prg(&mut self, _addr: u16, _data: u8) {}
+    fn prg_rom(&self) -> &Arc<[u8]> {
+        &self.prg_rom
+    }
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    #[cfg(feature = "serde")]
+    fn snapshot(&self) -> MapperSnapshot {
+        MapperSnapshot::Nrom(self.clone())
+    }
+}
+
+/*
+UxROM (mapper 2):
+
+A switchable 16KB PRG bank at $8000-$BFFF and the last bank fixed
+at $C000-$FFFF. Any write to $8000-$FFFF selects the bank.
+CHR is a single unbanked 8KB (usually CHR-RAM).
+The discrete logic boards have bus conflicts, games write the bank
+number over a ROM byte holding the same value.
+*/
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Uxrom {
+    prg_rom: Arc<[u8]>,
+    prg_bank: usize,
+}
+
+impl Uxrom {
+    pub fn new(prg_rom: Vec<u8>) -> Self {
+        Uxrom {
+            prg_rom: prg_rom.into(),
+            prg_bank: 0,
+        }
+    }
+    fn prg_banks(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+}
+
+impl Mapper for Uxrom {
+    fn id(&self) -> u8 {
+        2
+    }
+    fn read_prg(&self, addr: u16) -> u8 {
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank,
+            _ => self.prg_banks() - 1,
+        };
+        self.prg_rom[bank * PRG_BANK_SIZE + (addr as usize & 0x3FFF)]
+    }
+    fn write_prg(&mut self, _addr: u16, data: u8) {
+        self.prg_bank = data as usize % self.prg_banks();
+    }
+    fn power_on(&mut self) {
+        self.prg_bank = 0;
+    }
+    fn has_bus_conflicts(&self) -> bool {
+        true
+    }
+    fn prg_rom(&self) -> &Arc<[u8]> {
+        &self.prg_rom
+    }
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    #[cfg(feature = "serde")]
+    fn snapshot(&self) -> MapperSnapshot {
+        MapperSnapshot::Uxrom(self.clone())
+    }
+}
+
+/*
+CNROM (mapper 3):
+
+PRG is laid out like NROM. Any write to $8000-$FFFF selects which
+8KB CHR bank the PPU sees. Has bus conflicts like UxROM.
+*/
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cnrom {
+    prg_rom: Arc<[u8]>,
+    chr_banks: usize,
+    chr_bank: usize,
+}
+
+impl Cnrom {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize) -> Self {
+        Cnrom {
+            prg_rom: prg_rom.into(),
+            chr_banks: (chr_size / CHR_BANK_SIZE).max(1),
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for Cnrom {
+    fn id(&self) -> u8 {
+        3
+    }
+    fn read_prg(&self, addr: u16) -> u8 {
+        let addr = (addr - 0x8000) as usize;
+        self.prg_rom[addr % self.prg_rom.len()]
+    }
+    fn write_prg(&mut self, _addr: u16, data: u8) {
+        self.chr_bank = data as usize % self.chr_banks;
+    }
+    fn chr_banks(&self) -> [usize; 8] {
+        chr_banks_8k(self.chr_bank * CHR_BANK_SIZE)
+    }
+    fn power_on(&mut self) {
+        self.chr_bank = 0;
+    }
+    fn has_bus_conflicts(&self) -> bool {
+        true
+    }
+    fn prg_rom(&self) -> &Arc<[u8]> {
+        &self.prg_rom
+    }
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    #[cfg(feature = "serde")]
+    fn snapshot(&self) -> MapperSnapshot {
+        MapperSnapshot::Cnrom(self.clone())
+    }
+}
+
+/*
+MMC3 (mapper 4):
+
+Eight bank registers R0-R7, picked by writing $8000 (even) and
+loaded by writing $8001 (odd):
+    R0/R1   2KB CHR banks (low bit ignored)
+    R2-R5   1KB CHR banks
+    R6/R7   8KB PRG banks
+
+Bank select bit 7 (CHR A12 inversion) swaps which half of the
+pattern tables gets the two 2KB banks:
+    inversion 0 - $0000 R0, $0800 R1, $1000-$1C00 R2-R5
+    inversion 1 - $0000-$0C00 R2-R5, $1000 R0, $1800 R1
+Bank select bit 6 (PRG mode) swaps R6 with the fixed second-last
+bank:
+    mode 0 - $8000 R6, $A000 R7, $C000 second-last, $E000 last
+    mode 1 - $8000 second-last, $A000 R7, $C000 R6, $E000 last
+$A000 (even) selects vertical/horizontal mirroring, ignored on
+four-screen boards. Power-on goes back to the header's mirroring.
+
+Not emulated yet: the scanline IRQ counter ($C000-$E001), which
+needs the PPU's A12 rises and a CPU IRQ line, and the $A001 PRG-RAM
+protect, the bus's PRG-RAM at $6000 is always enabled.
+*/
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmc3 {
+    prg_rom: Arc<[u8]>,
+    chr_banks: usize,
+    bank_select: u8,
+    registers: [u8; 8],
+    //the header's mirroring, what the board powers up with
+    header_mirroring: Mirroring,
+    mirroring: Mirroring,
+}
+
+impl Mmc3 {
+    pub fn new(prg_rom: Vec<u8>, chr_size: usize, mirroring: Mirroring) -> Self {
+        Mmc3 {
+            prg_rom: prg_rom.into(),
+            chr_banks: (chr_size / MMC3_CHR_BANK_SIZE).max(1),
+            bank_select: 0,
+            registers: [0; 8],
+            header_mirroring: mirroring.clone(),
+            mirroring,
+        }
+    }
+    fn prg_banks(&self) -> usize {
+        (self.prg_rom.len() / MMC3_PRG_BANK_SIZE).max(1)
+    }
+    //8KB PRG bank mapped at the slot holding addr
+    fn prg_bank(&self, addr: u16) -> usize {
+        let banks = self.prg_banks();
+        let second_last = banks.saturating_sub(2);
+        let r6 = (self.registers[6] & 0x3F) as usize % banks;
+        let r7 = (self.registers[7] & 0x3F) as usize % banks;
+        let prg_mode = self.bank_select & 0x40 != 0;
+        match addr {
+            0x8000..=0x9FFF => if prg_mode { second_last } else { r6 },
+            0xA000..=0xBFFF => r7,
+            0xC000..=0xDFFF => if prg_mode { r6 } else { second_last },
+            _ => banks - 1,
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn id(&self) -> u8 {
+        4
+    }
+    fn read_prg(&self, addr: u16) -> u8 {
+        self.prg_rom[self.prg_bank(addr) * MMC3_PRG_BANK_SIZE + (addr as usize & 0x1FFF)]
+    }
+    fn write_prg(&mut self, addr: u16, data: u8) {
+        match (addr, addr & 1) {
+            (0x8000..=0x9FFF, 0) => self.bank_select = data,
+            (0x8000..=0x9FFF, _) => self.registers[(self.bank_select & 0x07) as usize] = data,
+            (0xA000..=0xBFFF, 0) if self.header_mirroring != Mirroring::FOUR_SCREEN => {
+                self.mirroring = if data & 1 == 0 { Mirroring::VERTICAL } else { Mirroring::HORIZONTAL };
+            },
+            _ => {},
+        }
+    }
+    fn chr_banks(&self) -> [usize; 8] {
+        let r = |i: usize| self.registers[i] as usize;
+        //1KB bank numbers for $0000-$0FFF and $1000-$1FFF before inversion
+        let two_kb = [r(0) & !1, r(0) | 1, r(1) & !1, r(1) | 1];
+        let one_kb = [r(2), r(3), r(4), r(5)];
+        let (low, high) = if self.bank_select & 0x80 == 0 { (two_kb, one_kb) } else { (one_kb, two_kb) };
+        let mut banks = [0; 8];
+        for (window, bank) in banks.iter_mut().zip(low.iter().chain(high.iter())) {
+            *window = (bank % self.chr_banks) * MMC3_CHR_BANK_SIZE;
+        }
+        banks
+    }
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring.clone())
+    }
+    fn power_on(&mut self) {
+        self.bank_select = 0;
+        self.registers = [0; 8];
+        self.mirroring = self.header_mirroring.clone();
+    }
+    fn prg_rom(&self) -> &Arc<[u8]> {
+        &self.prg_rom
+    }
+    fn box_clone(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+    #[cfg(feature = "serde")]
+    fn snapshot(&self) -> MapperSnapshot {
+        MapperSnapshot::Mmc3(self.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ppu::PPU;
+    use crate::rom::Rom;
+
+    /*
+    A minimal iNES image for a mapper, shared by the mapper tests.
+    Banks are easy to tell apart: every PRG byte holds the number of
+    its 8KB bank, every CHR byte the number of its 1KB bank.
+    */
+    pub(crate) fn ines(id: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+        let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, prg_banks, chr_banks, id << 4, id & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0];
+        raw.extend((0..prg_banks as usize * PRG_BANK_SIZE).map(|i| (i / MMC3_PRG_BANK_SIZE) as u8));
+        raw.extend((0..chr_banks as usize * CHR_BANK_SIZE).map(|i| (i / MMC3_CHR_BANK_SIZE) as u8));
+        raw
+    }
+
+    //the mapper create_mapper builds for an ines image
+    pub(crate) fn mapper(id: u8, prg_banks: u8, chr_banks: u8) -> Box<dyn Mapper> {
+        let rom = Rom::new(&ines(id, prg_banks, chr_banks)).unwrap();
+        create_mapper(rom.mapper, rom.prg_rom, &rom.chr_rom, rom.screen_mirroring).unwrap()
+    }
+
+    #[test]
+    fn registry_builds_the_mapper_for_each_id() {
+        for id in [0, 2, 3, 4] {
+            assert_eq!(mapper(id, 2, 1).id(), id);
+        }
+    }
+
+    #[test]
+    fn registry_rejects_unsupported_mappers() {
+        let rom = Rom::new(&ines(1, 2, 1)).unwrap();
+        assert!(matches!(create_mapper(rom.mapper, rom.prg_rom, &rom.chr_rom, rom.screen_mirroring), Err(RomError::UnsupportedMapper(1))));
+    }
+
+    #[test]
+    fn uxrom_switches_the_bank_at_8000() {
+        //four 16KB banks, PRG bytes hold their 8KB bank number
+        let mut uxrom = mapper(2, 4, 0);
+        assert_eq!((uxrom.read_prg(0x8000), uxrom.read_prg(0xC000)), (0, 6));
+        uxrom.write_prg(0x8000, 2);
+        assert_eq!((uxrom.read_prg(0x8000), uxrom.read_prg(0xBFFF)), (4, 5));
+        assert_eq!((uxrom.read_prg(0xC000), uxrom.read_prg(0xFFFF)), (6, 7));
+        uxrom.power_on();
+        assert_eq!(uxrom.read_prg(0x8000), 0);
+    }
+
+    #[test]
+    fn cnrom_switches_the_8kb_chr_bank() {
+        let rom = Rom::new(&ines(3, 1, 4)).unwrap();
+        let mut cnrom = create_mapper(rom.mapper, rom.prg_rom, &rom.chr_rom, rom.screen_mirroring.clone()).unwrap();
+        let mut ppu = PPU::new(rom.chr_rom, rom.chr_ram, rom.screen_mirroring);
+        cnrom.write_prg(0xFFFF, 2);
+        ppu.set_chr_banks(cnrom.chr_banks());
+        //CHR bytes hold their 1KB bank number, 8KB bank 2 starts at 1KB bank 16
+        assert_eq!(ppu.peek_vram(0x0000), 16);
+        assert_eq!(ppu.peek_vram(0x1FFF), 23);
+        assert_eq!(cnrom.read_prg(0x8000), 0);
+    }
+
+    //1KB CHR bank numbers in MMC3's eight windows after selecting each of R0-R5
+    fn mmc3_chr_windows(inversion: u8) -> [usize; 8] {
+        //eight 8KB PRG banks, thirty-two 1KB CHR banks
+        let mut mmc3 = mapper(4, 4, 4);
+        for (r, value) in [9, 14, 20, 21, 22, 23].into_iter().enumerate() {
+            mmc3.write_prg(0x8000, inversion | r as u8);
+            mmc3.write_prg(0x8001, value);
+        }
+        mmc3.chr_banks().map(|offset| offset / MMC3_CHR_BANK_SIZE)
+    }
+
+    #[test]
+    fn mmc3_maps_each_chr_register_to_its_windows() {
+        //R0/R1 are 2KB banks and ignore their low bit, R2-R5 are 1KB banks
+        assert_eq!(mmc3_chr_windows(0x00), [8, 9, 14, 15, 20, 21, 22, 23]);
+        //inversion swaps the 2KB pair into $1000-$1FFF
+        assert_eq!(mmc3_chr_windows(0x80), [20, 21, 22, 23, 8, 9, 14, 15]);
+    }
+
+    #[test]
+    fn mmc3_chr_windows_reach_the_ppu() {
+        let rom = Rom::new(&ines(4, 2, 4)).unwrap();
+        let mut mmc3 = create_mapper(rom.mapper, rom.prg_rom, &rom.chr_rom, rom.screen_mirroring.clone()).unwrap();
+        let mut ppu = PPU::new(rom.chr_rom, rom.chr_ram, rom.screen_mirroring);
+        for (r, value) in [(0, 4), (1, 10), (2, 16), (5, 31)] {
+            mmc3.write_prg(0x8000, 0x80 | r);
+            mmc3.write_prg(0x8001, value);
+        }
+        ppu.set_chr_banks(mmc3.chr_banks());
+        for (addr, bank) in [(0x0000, 16), (0x0C00, 31), (0x1000, 4), (0x17FF, 5), (0x1800, 10), (0x1FFF, 11)] {
+            assert_eq!(ppu.peek_vram(addr), bank, "PPU ${:04X}", addr);
+        }
+        //bank numbers wrap around the CHR size
+        mmc3.write_prg(0x8000, 0x82);
+        mmc3.write_prg(0x8001, 32 + 3);
+        ppu.set_chr_banks(mmc3.chr_banks());
+        assert_eq!(ppu.peek_vram(0x0000), 3);
+    }
+
+    #[test]
+    fn mmc3_prg_mode_swaps_r6_with_the_fixed_bank() {
+        //eight 8KB PRG banks, PRG bytes hold their bank number
+        let mut mmc3 = mapper(4, 4, 1);
+        mmc3.write_prg(0x8000, 6);
+        mmc3.write_prg(0x8001, 0x43); //only the low six bits select
+        mmc3.write_prg(0x8000, 7);
+        mmc3.write_prg(0x8001, 5);
+        let slots = |mmc3: &dyn Mapper| {
+            [0x8000, 0x9FFF, 0xA000, 0xBFFF, 0xC000, 0xDFFF, 0xE000, 0xFFFF].map(|addr| mmc3.read_prg(addr))
+        };
+        assert_eq!(slots(mmc3.as_ref()), [3, 3, 5, 5, 6, 6, 7, 7]);
+        //the mode bit lives in bank select, switching it keeps the registers
+        mmc3.write_prg(0x8000, 0x40);
+        assert_eq!(slots(mmc3.as_ref()), [6, 6, 5, 5, 3, 3, 7, 7]);
+        mmc3.power_on();
+        assert_eq!(slots(mmc3.as_ref()), [0, 0, 0, 0, 6, 6, 7, 7]);
+    }
+
+    #[test]
+    fn mmc3_power_on_restores_the_header_mirroring() {
+        let mut mmc3 = Mmc3::new(vec![0; 0x8000], 0x2000, Mirroring::HORIZONTAL);
+        assert_eq!(mmc3.mirroring(), Some(Mirroring::HORIZONTAL));
+        mmc3.write_prg(0xA000, 0);
+        assert_eq!(mmc3.mirroring(), Some(Mirroring::VERTICAL));
+        mmc3.power_on();
+        assert_eq!(mmc3.mirroring(), Some(Mirroring::HORIZONTAL));
+        //four-screen boards have their own nametable RAM and ignore $A000
+        let mut mmc3 = Mmc3::new(vec![0; 0x8000], 0x2000, Mirroring::FOUR_SCREEN);
+        mmc3.write_prg(0xA000, 1);
+        assert_eq!(mmc3.mirroring(), Some(Mirroring::FOUR_SCREEN));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::bus::Bus;
+use crate::cpu::{CPU, StepResult, WatchKind};
+use std::io::{self, BufRead, Write};
+
+/*
+Monitor:
+
+A small interactive debugger driven from stdin. While the monitor
+is active, emulation is paused and commands are read one line at
+a time until execution is resumed.
+
+Commands (all numbers are hex) -
+    mem START [END]     hex dump of CPU memory from START to END
+    reg                 dump A/X/Y/P/SP/PC
+    step [N]            execute N instructions (default 1)
+    bp ADDR             set a breakpoint on PC == ADDR
+    watch ADDR [r|w]    halt after an instruction reads/writes ADDR
+    del ADDR            remove a breakpoint or watchpoint
+    c                   continue execution
+
+An empty line repeats the previous command.
+*/
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command {
+    Mem(u16, u16),
+    Reg,
+    Step(u32),
+    Break(u16),
+    Watch(u16, WatchKind),
+    Delete(u16),
+    Continue,
+}
+
+pub struct Monitor {
+    last_command: Option<Command>,
+}
+
+fn parse_hex(arg: &str) -> Result<u16, String> {
+    u16::from_str_radix(arg.trim_start_matches('$'), 16)
+        .map_err(|_| format!("invalid hex value '{}'", arg))
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor {
+            last_command: None,
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.as_slice() {
+            ["mem", start] => {
+                let start = parse_hex(start)?;
+                Ok(Command::Mem(start, start.saturating_add(0x0F)))
+            },
+            ["mem", start, end] => Ok(Command::Mem(parse_hex(start)?, parse_hex(end)?)),
+            ["reg"] => Ok(Command::Reg),
+            ["step"] => Ok(Command::Step(1)),
+            ["step", n] => n.parse::<u32>()
+                .map(Command::Step)
+                .map_err(|_| format!("invalid step count '{}'", n)),
+            ["bp", addr] => Ok(Command::Break(parse_hex(addr)?)),
+            ["watch", addr] => Ok(Command::Watch(parse_hex(addr)?, WatchKind::ReadWrite)),
+            ["watch", addr, "r"] => Ok(Command::Watch(parse_hex(addr)?, WatchKind::Read)),
+            ["watch", addr, "w"] => Ok(Command::Watch(parse_hex(addr)?, WatchKind::Write)),
+            ["del", addr] => Ok(Command::Delete(parse_hex(addr)?)),
+            ["c"] | ["continue"] => Ok(Command::Continue),
+            _ => Err(format!("unknown command '{}'", line.trim())),
+        }
+    }
+
+    fn dump_registers(cpu: &CPU) {
+        println!(
+            "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X}",
+            cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.reg_stat, cpu.reg_sp, cpu.reg_pc
+        );
+    }
+
+    //peeks rather than reads, so dumping registers doesn't disturb them (or trip watchpoints)
+    fn dump_memory(bus: &Bus, start: u16, end: u16) -> String {
+        let mut text = String::new();
+        let mut line_addr = start & 0xFFF0;
+        while line_addr <= end {
+            text.push_str(&format!("{:04X}:", line_addr));
+            for offset in 0..16 {
+                let addr = line_addr + offset;
+                if addr < start || addr > end {
+                    text.push_str("   ");
+                } else {
+                    text.push_str(&format!(" {:02X}", bus.peek(addr)));
+                }
+            }
+            text.push('\n');
+            match line_addr.checked_add(16) {
+                Some(next) => line_addr = next,
+                None => break,
+            }
+        }
+        text
+    }
+
+    /*
+    Steps through the CPU's debugger hooks so a watchpoint hit is
+    consumed rather than left for the next step. Stops early on a
+    breakpoint, BRK or watchpoint.
+    */
+    fn step(cpu: &mut CPU, n: u32) {
+        for _ in 0..n {
+            match cpu.step() {
+                StepResult::Completed => (),
+                StepResult::Breakpoint(pc) => {
+                    println!("Breakpoint hit at ${:04X}", pc);
+                    break;
+                },
+                StepResult::Watchpoint(addr, access) => {
+                    println!("Watchpoint hit: {:?} ${:04X} (PC = ${:04X})", access, addr, cpu.reg_pc);
+                    break;
+                },
+                StepResult::Brk(pc) => {
+                    println!("BRK hit at ${:04X}", pc);
+                    break;
+                },
+            }
+        }
+        Self::dump_registers(cpu);
+    }
+
+    /*
+    Runs the command prompt until the user continues execution
+    (or stdin is closed).
+    */
+    pub fn run(&mut self, cpu: &mut CPU) {
+        Self::dump_registers(cpu);
+        let stdin = io::stdin();
+        loop {
+            print!("> ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => (),
+            }
+            let command = if line.trim().is_empty() {
+                match &self.last_command {
+                    Some(command) => Ok(command.clone()),
+                    None => continue,
+                }
+            } else {
+                Self::parse(&line)
+            };
+            if let Ok(command) = &command {
+                self.last_command = Some(command.clone());
+            }
+            match command {
+                Ok(Command::Mem(start, end)) => print!("{}", Self::dump_memory(&cpu.mem_bus, start, end)),
+                Ok(Command::Reg) => Self::dump_registers(cpu),
+                Ok(Command::Step(n)) => Self::step(cpu, n),
+                Ok(Command::Break(addr)) => cpu.add_breakpoint(addr),
+                Ok(Command::Watch(addr, kind)) => cpu.add_watchpoint(addr, kind),
+                Ok(Command::Delete(addr)) => {
+                    cpu.remove_breakpoint(addr);
+                    cpu.remove_watchpoint(addr);
+                },
+                Ok(Command::Continue) => return,
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::cpu;
+
+    #[test]
+    fn dumping_memory_has_no_side_effects() {
+        //NOP
+        let mut cpu = cpu(&[0xEA]);
+        cpu.mem_bus.ppu.stat = 0x80;
+        cpu.mem_bus.poke(0x0010, 0xAB);
+        cpu.add_watchpoint(0x0010, WatchKind::Read);
+        let text = Monitor::dump_memory(&cpu.mem_bus, 0x0010, 0x0011);
+        assert_eq!(text, "0010: AB 00                                          \n");
+        Monitor::dump_memory(&cpu.mem_bus, 0x2000, 0x2007);
+        assert_eq!(cpu.mem_bus.ppu.stat, 0x80);
+        assert_eq!(cpu.step(), StepResult::Completed);
+    }
+
+    #[test]
+    fn stepping_consumes_watchpoint_hits() {
+        //LDA $10, NOP
+        let mut cpu = cpu(&[0xA5, 0x10, 0xEA]);
+        cpu.add_watchpoint(0x0010, WatchKind::Read);
+        Monitor::step(&mut cpu, 1);
+        assert_eq!(cpu.reg_pc, 0x8002);
+        assert_eq!(cpu.step(), StepResult::Completed);
+    }
+}
@@ -0,0 +1,226 @@
+use base64::Engine;
+use log::warn;
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+/*
+Movie:
+
+A per-frame log of controller input for deterministic replay
+(TAS-style recording and playback). Each frame stores the button
+state byte of both controller ports, using the same bit layout
+as the Controller (A in bit 7 down to Right in bit 0).
+
+File format is plain text, one frame per line with the port 1
+and port 2 states as hex bytes. Blank lines and lines starting
+with '#' are ignored.
+
+    # NES_Emu input recording
+    00 00
+    10 00
+    80 00
+*/
+
+#[derive(Debug, Error)]
+pub enum MovieError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("line {line}: {msg}")]
+    Parse { line: usize, msg: String },
+    #[error("unsupported movie: {0}")]
+    Unsupported(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Movie {
+    pub frames: Vec<[u8; 2]>,
+}
+
+impl Movie {
+    pub fn new() -> Self {
+        Movie { frames: Vec::new() }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut frames = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse_err = |msg: &str| MovieError::Parse { line: i + 1, msg: msg.to_string() };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return Err(parse_err("expected two controller states"));
+            }
+            let port1 = u8::from_str_radix(fields[0], 16).map_err(|_| parse_err("invalid port 1 state"))?;
+            let port2 = u8::from_str_radix(fields[1], 16).map_err(|_| parse_err("invalid port 2 state"))?;
+            frames.push([port1, port2]);
+        }
+        Ok(Movie { frames })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MovieError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# NES_Emu input recording\n");
+        for frame in &self.frames {
+            text.push_str(&format!("{:02X} {:02X}\n", frame[0], frame[1]));
+        }
+        text
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MovieError> {
+        fs::write(path, self.to_text())?;
+        Ok(())
+    }
+}
+
+/*
+FM2 Movies:
+
+The FCEUX text movie format. A header of "key value" lines is
+followed by one input line per frame:
+
+    |commands|port0|port1|port2|
+
+commands is a bitfield (1 = soft reset, 2 = power), each gamepad
+field holds the buttons RLDUTSBA in that order with '.' or ' ' for
+released, which puts Right at bit 0 up to A at bit 7 just like
+the Controller. Only power-on movies with standard gamepads are
+supported, movies starting from a savestate cannot be replayed.
+*/
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Fm2 {
+    pub rerecord_count: u32,
+    pub rom_filename: String,
+    //MD5 of the PRG and CHR data, see Rom::md5
+    pub rom_checksum: Option<[u8; 16]>,
+    pub movie: Movie,
+}
+
+impl Fm2 {
+    pub fn parse(text: &str) -> Result<Self, MovieError> {
+        let mut fm2 = Fm2::default();
+        for (i, line) in text.lines().enumerate() {
+            let parse_err = |msg: &str| MovieError::Parse { line: i + 1, msg: msg.to_string() };
+            if let Some(fields) = line.strip_prefix('|') {
+                let fields: Vec<&str> = fields.split('|').collect();
+                if fields.len() < 3 {
+                    return Err(parse_err("expected commands and two controller fields"));
+                }
+                let commands = fields[0].trim().parse::<u8>().map_err(|_| parse_err("invalid commands"))?;
+                //the console is powered on when playback starts, so only later resets are lost
+                if commands & 0x01 != 0 || (commands & 0x02 != 0 && !fm2.movie.frames.is_empty()) {
+                    warn!("FM2 line {}: resets are not supported, ignoring", i + 1);
+                }
+                let port1 = Self::parse_gamepad(fields[1]).ok_or_else(|| parse_err("invalid port 0 input"))?;
+                let port2 = Self::parse_gamepad(fields[2]).ok_or_else(|| parse_err("invalid port 1 input"))?;
+                fm2.movie.frames.push([port1, port2]);
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "rerecordCount" => fm2.rerecord_count = value.parse().map_err(|_| parse_err("invalid rerecord count"))?,
+                "romFilename" => fm2.rom_filename = value.to_string(),
+                "romChecksum" => fm2.rom_checksum = Self::parse_checksum(value),
+                "binary" if value == "1" => return Err(MovieError::Unsupported("binary input log".to_string())),
+                "fourscore" if value == "1" => return Err(MovieError::Unsupported("four score input".to_string())),
+                "port0" | "port1" if value != "0" && value != "1" => {
+                    return Err(MovieError::Unsupported(format!("{} device {}", key, value)));
+                },
+                "savestate" => return Err(MovieError::Unsupported("movie starts from a savestate".to_string())),
+                _ => {},
+            }
+        }
+        Ok(fm2)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MovieError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    //"RLDUTSBA" with '.' or ' ' for released buttons, empty when no device is attached
+    fn parse_gamepad(field: &str) -> Option<u8> {
+        if field.is_empty() {
+            return Some(0);
+        }
+        if field.len() != 8 {
+            return None;
+        }
+        Some(field.chars().enumerate().fold(0, |state, (bit, c)| {
+            if c == '.' || c == ' ' { state } else { state | (1 << bit) }
+        }))
+    }
+
+    //"base64:..." holding the 16 byte MD5
+    fn parse_checksum(value: &str) -> Option<[u8; 16]> {
+        let encoded = value.strip_prefix("base64:")?;
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).ok()?;
+        decoded.try_into().ok()
+    }
+}
+
+//feeds a movie back one frame at a time
+pub struct Player {
+    movie: Movie,
+    position: usize,
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Self {
+        Player { movie, position: 0 }
+    }
+
+    //input for the next frame, or None once the movie has ended
+    pub fn next_frame(&mut self) -> Option<[u8; 2]> {
+        let frame = self.movie.frames.get(self.position).copied();
+        self.position += 1;
+        frame
+    }
+
+    //frames left to play
+    pub fn remaining(&self) -> usize {
+        self.movie.frames.len().saturating_sub(self.position)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "version 3\n\
+        emuVersion 22020\n\
+        rerecordCount 42\n\
+        romFilename smb\n\
+        romChecksum base64:AAECAwQFBgcICQoLDA0ODw==\n\
+        port0 1\n\
+        port1 1\n\
+        port2 0\n\
+        |2|........|........||\n\
+        |0|....T...|........||\n\
+        |0|R......A|.L......||\n\
+        |0|RLDUTSBA|        ||\n";
+
+    #[test]
+    fn fm2_sample_decodes_its_first_frames() {
+        let fm2 = Fm2::parse(SAMPLE).unwrap();
+        assert_eq!(fm2.rerecord_count, 42);
+        assert_eq!(fm2.rom_filename, "smb");
+        assert_eq!(fm2.rom_checksum, Some(std::array::from_fn(|i| i as u8)));
+        //bit 0 is the first letter, R, matching the controller's Right bit
+        assert_eq!(fm2.movie.frames, vec![[0x00, 0x00], [0x10, 0x00], [0x81, 0x02], [0xFF, 0x00]]);
+    }
+
+    #[test]
+    fn fm2_rejects_what_it_cannot_replay() {
+        assert!(matches!(Fm2::parse("|0|RLDU|........||"), Err(MovieError::Parse { line: 1, .. })));
+        assert!(matches!(Fm2::parse("binary 1"), Err(MovieError::Unsupported(_))));
+        assert!(matches!(Fm2::parse("fourscore 1"), Err(MovieError::Unsupported(_))));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::Emulator;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use thiserror::Error;
+
+/*
+Netplay:
+
+Keeps two consoles in lock-step over TCP. Every frame both sides
+swap their controller byte along with a hash of their machine
+state, then apply the same pair of inputs (host on port 1, guest
+on port 2) before stepping. When the hashes differ the host sends
+its full save state and the guest loads it.
+
+This is plumbing only - there is no input delay or rollback yet,
+each frame waits for the peer's input.
+
+Messages -
+    0x01 frame:u32 buttons:u8 hash:u32      per-frame input
+    0x02 len:u32 state:[u8; len]            host save state (resync)
+(all integers little-endian)
+*/
+
+const MSG_INPUT: u8 = 0x01;
+const MSG_STATE: u8 = 0x02;
+
+#[derive(Debug, Error)]
+pub enum NetError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    State(#[from] bincode::Error),
+    #[error("protocol error: {0}")]
+    Protocol(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    //player 1, the source of truth on desync
+    Host,
+    //player 2
+    Guest,
+}
+
+pub struct NetSession {
+    stream: TcpStream,
+    role: Role,
+    frame: u32,
+    //number of times the guest had to be resynced
+    pub resyncs: u32,
+}
+
+impl NetSession {
+    //waits for a guest to connect
+    pub fn host<A: ToSocketAddrs>(addr: A) -> Result<Self, NetError> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        Self::new(stream, Role::Host)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, NetError> {
+        Self::new(TcpStream::connect(addr)?, Role::Guest)
+    }
+
+    pub fn new(stream: TcpStream, role: Role) -> Result<Self, NetError> {
+        stream.set_nodelay(true)?;
+        Ok(NetSession {
+            stream,
+            role,
+            frame: 0,
+            resyncs: 0,
+        })
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /*
+    Swaps this frame's input with the peer, resyncs on a state hash
+    mismatch, and sets both controllers. Call once per frame right
+    before Emulator::step_frame.
+    */
+    pub fn sync_frame(&mut self, emulator: &mut Emulator, local_buttons: u8) -> Result<(), NetError> {
+        let state = emulator.save_state()?;
+        let hash = crc32fast::hash(&state);
+
+        let mut msg = vec![MSG_INPUT];
+        msg.extend(self.frame.to_le_bytes());
+        msg.push(local_buttons);
+        msg.extend(hash.to_le_bytes());
+        self.stream.write_all(&msg)?;
+
+        let (frame, remote_buttons, remote_hash) = self.read_input()?;
+        if frame != self.frame {
+            return Err(NetError::Protocol(format!("expected frame {}, peer sent {}", self.frame, frame)));
+        }
+
+        if hash != remote_hash {
+            match self.role {
+                Role::Host => {
+                    let mut msg = vec![MSG_STATE];
+                    msg.extend((state.len() as u32).to_le_bytes());
+                    msg.extend(&state);
+                    self.stream.write_all(&msg)?;
+                },
+                Role::Guest => {
+                    let state = self.read_state()?;
+                    emulator.load_state(&state)?;
+                },
+            }
+            self.resyncs += 1;
+        }
+
+        let (port1, port2) = match self.role {
+            Role::Host => (local_buttons, remote_buttons),
+            Role::Guest => (remote_buttons, local_buttons),
+        };
+        emulator.cpu.mem_bus.port1.set_button_states(port1);
+        emulator.cpu.mem_bus.port2.set_button_states(port2);
+        self.frame = self.frame.wrapping_add(1);
+        Ok(())
+    }
+
+    fn read_tag(&mut self, expected: u8) -> Result<(), NetError> {
+        let mut tag = [0; 1];
+        self.stream.read_exact(&mut tag)?;
+        if tag[0] != expected {
+            return Err(NetError::Protocol(format!("unexpected message type {:#04X}", tag[0])));
+        }
+        Ok(())
+    }
+
+    fn read_u32(&mut self) -> Result<u32, NetError> {
+        let mut bytes = [0; 4];
+        self.stream.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_input(&mut self) -> Result<(u32, u8, u32), NetError> {
+        self.read_tag(MSG_INPUT)?;
+        let frame = self.read_u32()?;
+        let mut buttons = [0; 1];
+        self.stream.read_exact(&mut buttons)?;
+        let hash = self.read_u32()?;
+        Ok((frame, buttons[0], hash))
+    }
+
+    fn read_state(&mut self) -> Result<Vec<u8>, NetError> {
+        self.read_tag(MSG_STATE)?;
+        let len = self.read_u32()? as usize;
+        let mut state = vec![0; len];
+        self.stream.read_exact(&mut state)?;
+        Ok(state)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::tests::rom;
+    use crate::emu::tests::{frame_hash, on_big_stack, SHOW_PAD};
+    use std::thread;
+
+    const FRAMES: u8 = 40;
+
+    //steps `emulator` through the session, returning the frame hash after each frame
+    fn play(emulator: &mut Emulator, session: &mut NetSession) -> Vec<u32> {
+        (0..FRAMES).map(|frame| {
+            let buttons = match session.role() {
+                Role::Host => frame.wrapping_mul(37),
+                Role::Guest => !frame,
+            };
+            session.sync_frame(emulator, buttons).unwrap();
+            emulator.step_frame();
+            frame_hash(emulator)
+        }).collect()
+    }
+
+    #[test]
+    fn two_machines_stay_in_lock_step() {
+        on_big_stack(|| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let guest = thread::Builder::new().stack_size(8 << 20).spawn(move || {
+                let mut emulator = Emulator::new(&rom(&SHOW_PAD)).unwrap();
+                //started late, the guest is a few frames ahead
+                for _ in 0..3 {
+                    emulator.step_frame();
+                }
+                let mut session = NetSession::connect(addr).unwrap();
+                (play(&mut emulator, &mut session), session.resyncs)
+            }).unwrap();
+
+            let mut emulator = Emulator::new(&rom(&SHOW_PAD)).unwrap();
+            let mut session = NetSession::new(listener.accept().unwrap().0, Role::Host).unwrap();
+            let hashes = play(&mut emulator, &mut session);
+            let (guest_hashes, guest_resyncs) = guest.join().unwrap();
+
+            assert_eq!(guest_hashes, hashes);
+            assert_eq!((session.resyncs, guest_resyncs), (1, 1));
+        });
+    }
+}
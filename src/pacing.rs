@@ -0,0 +1,203 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+Frame Pacing:
+
+Keeps the frontend at 60 frames a second. The pacer only sees time
+through a Clock, the window thread uses the wall clock while a
+VirtualClock lets the schedule be driven deterministically without
+sleeping.
+*/
+
+//one 1/60s frame slot
+pub const FRAME_DURATION: Duration = Duration::new(0, 16666666);
+
+pub trait Clock {
+    //time since some fixed starting point
+    fn now(&self) -> Duration;
+    fn sleep(&mut self, duration: Duration);
+}
+
+pub struct WallClock {
+    start: Instant,
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WallClock {
+    pub fn new() -> Self {
+        WallClock { start: Instant::now() }
+    }
+}
+
+impl Clock for WallClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+//time only moves when it is slept through or advanced by hand (to stand in for work done)
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Duration,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock { now: Duration::ZERO }
+    }
+    pub fn advance(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+    fn sleep(&mut self, duration: Duration) {
+        self.now += duration;
+    }
+}
+
+/*
+Frame Pacer:
+
+Without vsync (and for skipped frames, which have no buffer swap
+to wait on) end_frame sleeps until the next 1/60s slot. The slots
+are accumulated rather than restarted after the sleep so
+oversleeping doesn't drift, a frame that misses its slot entirely
+counts as dropped and restarts the schedule.
+
+With vsync the swap has already blocked until the display
+refreshed, so a shown frame only restarts the schedule from now,
+counting as dropped if it took more than one and a half slots.
+*/
+pub struct FramePacer<C: Clock> {
+    pub clock: C,
+    vsync: bool,
+    //start of the next slot
+    next_frame: Duration,
+    //frames that missed their slot since the last take_dropped
+    dropped: u32,
+}
+
+impl<C: Clock> FramePacer<C> {
+    pub fn new(clock: C, vsync: bool) -> Self {
+        let next_frame = clock.now() + FRAME_DURATION;
+        FramePacer { clock, vsync, next_frame, dropped: 0 }
+    }
+
+    //call once a frame has been handled, `shown` if it was drawn and swapped to the screen
+    pub fn end_frame(&mut self, shown: bool) {
+        let now = self.clock.now();
+        if self.vsync && shown {
+            if now > self.next_frame + FRAME_DURATION / 2 {
+                self.dropped += 1;
+            }
+            self.next_frame = now + FRAME_DURATION;
+            return;
+        }
+        if now < self.next_frame {
+            self.clock.sleep(self.next_frame - now);
+        } else {
+            self.dropped += 1;
+            self.next_frame = now;
+        }
+        self.next_frame += FRAME_DURATION;
+    }
+
+    //dropped frames since the last call
+    pub fn take_dropped(&mut self) -> u32 {
+        std::mem::take(&mut self.dropped)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a VirtualClock that counts how often it is asked the time and slept on
+    #[derive(Default)]
+    struct CountingClock {
+        clock: VirtualClock,
+        reads: std::cell::Cell<u32>,
+        sleeps: Vec<Duration>,
+    }
+
+    impl Clock for CountingClock {
+        fn now(&self) -> Duration {
+            self.reads.set(self.reads.get() + 1);
+            self.clock.now()
+        }
+        fn sleep(&mut self, duration: Duration) {
+            self.sleeps.push(duration);
+            self.clock.sleep(duration);
+        }
+    }
+
+    #[test]
+    fn neither_mode_spins_waiting_for_the_slot() {
+        //the timer sleeps out each slot in one go instead of polling the clock
+        let mut timer = FramePacer::new(CountingClock::default(), false);
+        for _ in 0..10 {
+            timer.clock.clock.advance(Duration::from_millis(5));
+            timer.end_frame(true);
+        }
+        assert_eq!(timer.clock.reads.get(), 11);
+        assert_eq!(timer.clock.sleeps.len(), 10);
+        assert!(timer.clock.sleeps.iter().all(|&slept| slept == FRAME_DURATION - Duration::from_millis(5)));
+
+        //with vsync the swap did the waiting, only skipped frames sleep
+        let mut vsync = FramePacer::new(CountingClock::default(), true);
+        for (shown, work) in [(true, FRAME_DURATION), (true, FRAME_DURATION), (false, Duration::from_millis(5)), (true, FRAME_DURATION)] {
+            vsync.clock.clock.advance(work);
+            vsync.end_frame(shown);
+        }
+        assert_eq!(vsync.clock.reads.get(), 5);
+        assert_eq!(vsync.clock.sleeps, [FRAME_DURATION - Duration::from_millis(5)]);
+        assert_eq!(vsync.take_dropped(), 0);
+    }
+
+    //frames the pacer lets through in `span` of simulated time when each takes `work`
+    fn frames_in(span: Duration, work: Duration) -> (u32, u32) {
+        let mut pacer = FramePacer::new(VirtualClock::new(), false);
+        let mut frames = 0;
+        while pacer.clock.now() < span {
+            pacer.clock.advance(work);
+            pacer.end_frame(true);
+            frames += 1;
+        }
+        (frames, pacer.take_dropped())
+    }
+
+    #[test]
+    fn virtual_time_paces_sixty_frames_a_second() {
+        let two_seconds = FRAME_DURATION * 120;
+        assert_eq!(frames_in(two_seconds, Duration::from_millis(5)), (120, 0));
+        assert_eq!(frames_in(two_seconds, Duration::ZERO), (120, 0));
+        //too slow to keep up, every frame misses its slot and none are slept through
+        assert_eq!(frames_in(two_seconds, Duration::from_millis(20)), (100, 100));
+    }
+
+    #[test]
+    fn a_late_frame_restarts_the_schedule() {
+        let mut pacer = FramePacer::new(VirtualClock::new(), false);
+        pacer.clock.advance(Duration::from_millis(40));
+        pacer.end_frame(true);
+        assert_eq!(pacer.take_dropped(), 1);
+        //the next slot is a full frame after the late one, not two slots of catching up
+        pacer.end_frame(true);
+        assert_eq!(pacer.clock.now(), Duration::from_millis(40) + FRAME_DURATION);
+        assert_eq!(pacer.take_dropped(), 0);
+    }
+}
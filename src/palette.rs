@@ -0,0 +1,229 @@
+use log::warn;
+use std::fs;
+use std::path::Path;
+
+//RGB colour for each of the 64 values the PPU can output
+pub type Palette = [(u8, u8, u8); 64];
+
+/*
+Palette Files:
+
+A .pal file is 64 RGB triplets (192 bytes). Files that also carry
+the 7 colour emphasis variants (8 * 192 bytes) are accepted, only
+the first, unemphasized set is used.
+*/
+pub fn parse_palette(raw: &[u8]) -> Option<Palette> {
+    if raw.len() != 192 && raw.len() != 192 * 8 {
+        return None;
+    }
+    let mut palette = SYSTEM_PALLETE;
+    for (i, rgb) in raw[..192].chunks(3).enumerate() {
+        palette[i] = (rgb[0], rgb[1], rgb[2]);
+    }
+    Some(palette)
+}
+
+//loads a .pal file, falling back to the built-in palette if it is missing or malformed
+pub fn load_palette(path: &Path) -> Palette {
+    match fs::read(path).ok().and_then(|raw| parse_palette(&raw)) {
+        Some(palette) => palette,
+        None => {
+            warn!("Unable to load palette {}, using the built-in one", path.display());
+            SYSTEM_PALLETE
+        }
+    }
+}
+
+/*
+Palette Generation:
+
+Synthesizes the palette the way a TV decodes the PPU's composite
+signal, so colours can be tuned like a TV's picture controls
+instead of only through .pal files. Each colour is a square wave
+between a low and a high voltage (picked by the luma row), high for
+6 of the 12 phases of the colour subcarrier starting at the hue
+column's phase. Column 0 stays high, column $D stays low, $E/$F are
+black. Averaging the wave over a subcarrier cycle gives Y, mixing
+it with the subcarrier gives I and Q, then YIQ is converted to RGB.
+
+The default parameters are calibrated against SYSTEM_PALLETE, which
+is more saturated than a plain decode, to a mean difference of
+about 16 per channel.
+*/
+
+//signal voltages for luma rows 0-3, low then high
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+//calibration against SYSTEM_PALLETE, see above
+const HUE_OFFSET: f32 = 102.0;
+const SATURATION_SCALE: f32 = 1.8;
+const BRIGHTNESS_OFFSET: f32 = 0.04;
+
+//the controls in the order the frontend's hotkey cycles through them, see PaletteParams::adjust
+pub const PALETTE_PARAMS: [&str; 4] = ["hue", "saturation", "brightness", "contrast"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaletteParams {
+    //degrees added to every hue
+    pub hue: f32,
+    //colour multiplier, 0 is greyscale
+    pub saturation: f32,
+    //added to the luma, -1 to 1
+    pub brightness: f32,
+    //luma and colour multiplier around mid grey
+    pub contrast: f32,
+}
+
+impl Default for PaletteParams {
+    fn default() -> Self {
+        PaletteParams { hue: 0.0, saturation: 1.0, brightness: 0.0, contrast: 1.0 }
+    }
+}
+
+impl PaletteParams {
+    //nudges one control (an index into PALETTE_PARAMS) by `steps` hotkey steps, returns its new value
+    pub fn adjust(&mut self, param: usize, steps: i32) -> f32 {
+        let steps = steps as f32;
+        match param {
+            0 => {
+                self.hue = (self.hue + steps * 5.0).rem_euclid(360.0);
+                self.hue
+            },
+            1 => {
+                self.saturation = (self.saturation + steps * 0.05).max(0.0);
+                self.saturation
+            },
+            2 => {
+                self.brightness = (self.brightness + steps * 0.02).clamp(-1.0, 1.0);
+                self.brightness
+            },
+            _ => {
+                self.contrast = (self.contrast + steps * 0.05).max(0.0);
+                self.contrast
+            },
+        }
+    }
+}
+
+//builds the 64 colour table from the picture controls, see Palette Generation
+pub fn generate_palette(params: &PaletteParams) -> Palette {
+    let mut palette = [(0, 0, 0); 64];
+    for (i, rgb) in palette.iter_mut().enumerate() {
+        let (column, row) = (i & 0x0F, (i >> 4) & 0x03);
+        let (low, high) = match column {
+            0x00 => (SIGNAL_HIGH[row], SIGNAL_HIGH[row]),
+            0x0D => (SIGNAL_LOW[row], SIGNAL_LOW[row]),
+            0x0E | 0x0F => (SIGNAL_BLACK, SIGNAL_BLACK),
+            _ => (SIGNAL_LOW[row], SIGNAL_HIGH[row]),
+        };
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for phase in 0..12 {
+            let signal = if (column + phase) % 12 < 6 { high } else { low };
+            let level = (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK);
+            let angle = std::f32::consts::PI * (phase as f32 + 0.5) / 6.0 + (HUE_OFFSET + params.hue).to_radians();
+            y += level / 12.0;
+            i += level * angle.cos() / 12.0;
+            q += level * angle.sin() / 12.0;
+        }
+        let chroma = SATURATION_SCALE * params.saturation * params.contrast;
+        let (i, q) = (i * chroma, q * chroma);
+        let y = (y - 0.5) * params.contrast + 0.5 + BRIGHTNESS_OFFSET + params.brightness;
+        let channel = |value: f32| (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        *rgb = (
+            channel(y + 0.946882 * i + 0.623557 * q),
+            channel(y - 0.274788 * i - 0.635691 * q),
+            channel(y - 1.108545 * i + 1.709007 * q),
+        );
+    }
+    palette
+}
+
+#[rustfmt::skip]
+pub static SYSTEM_PALLETE: Palette = [
+   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
+];
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn pal_files_replace_the_palette() {
+        let path = env::temp_dir().join(format!("nes_emu_palette_{}.pal", std::process::id()));
+        let raw: Vec<u8> = (0..192).map(|i| i as u8).collect();
+        fs::write(&path, &raw).unwrap();
+        let palette = load_palette(&path);
+        assert_eq!(palette[0x00], (0, 1, 2));
+        assert_eq!(palette[0x16], (66, 67, 68));
+        assert_eq!(palette[0x3F], (189, 190, 191));
+
+        //emphasis sets after the first are ignored, other sizes are rejected
+        let emphasis: Vec<u8> = raw.iter().cycle().take(192 * 8).copied().collect();
+        assert_eq!(parse_palette(&emphasis), Some(palette));
+        fs::write(&path, &raw[..191]).unwrap();
+        assert_eq!(load_palette(&path), SYSTEM_PALLETE);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load_palette(&path), SYSTEM_PALLETE);
+    }
+
+    #[test]
+    fn system_palette_matches_the_reference_colours() {
+        assert_eq!(SYSTEM_PALLETE.len(), 64);
+        //a few fixed points of the NTSC palette the rest of the table was built around
+        let reference = [
+            (0x00, (0x80, 0x80, 0x80)),
+            (0x01, (0x00, 0x3D, 0xA6)),
+            (0x0D, (0x00, 0x00, 0x00)),
+            (0x16, (0xFF, 0x22, 0x00)),
+            (0x1A, (0x05, 0x8F, 0x00)),
+            (0x20, (0xFF, 0xFF, 0xFF)),
+            (0x2C, (0x05, 0xFB, 0xFF)),
+            (0x30, (0xFF, 0xFF, 0xFF)),
+            (0x3D, (0xDD, 0xDD, 0xDD)),
+        ];
+        for (value, rgb) in reference {
+            assert_eq!(SYSTEM_PALLETE[value], rgb, "${:02X}", value);
+        }
+        //columns $E/$F are black (near black) in every row, column 0 gets brighter row by row
+        for row in 0..4 {
+            for column in [0x0E, 0x0F] {
+                let (r, g, b) = SYSTEM_PALLETE[row * 16 + column];
+                assert!(r == g && g == b && r < 0x20);
+            }
+        }
+        assert!((1..4).all(|row| SYSTEM_PALLETE[row * 16].0 >= SYSTEM_PALLETE[(row - 1) * 16].0));
+    }
+
+    #[test]
+    fn default_params_come_close_to_the_built_in_palette() {
+        let generated = generate_palette(&PaletteParams::default());
+        let differences: Vec<[u8; 3]> = generated.iter().zip(SYSTEM_PALLETE.iter())
+            .map(|(a, b)| [a.0.abs_diff(b.0), a.1.abs_diff(b.1), a.2.abs_diff(b.2)])
+            .collect();
+        //the mean the calibration was tuned to, with no single channel far out
+        let mean = differences.iter().flatten().map(|&d| d as f32).sum::<f32>() / (64.0 * 3.0);
+        assert!(mean < 20.0, "mean difference {}", mean);
+        for (i, difference) in differences.iter().enumerate() {
+            assert!(difference.iter().all(|&d| d < 96), "${:02X} differs by {:?}", i, difference);
+        }
+        //the blacks stay black
+        for i in [0x0D, 0x0E, 0x0F, 0x1E, 0x1F, 0x2E, 0x2F, 0x3E, 0x3F] {
+            let (r, g, b) = generated[i];
+            assert!(r.max(g).max(b) < 0x20, "${:02X} is {:?}", i, generated[i]);
+        }
+    }
+}
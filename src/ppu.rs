@@ -1,12 +1,205 @@
 /****************** Linking External Modules ******************/
+use crate::palette::{Palette, SYSTEM_PALLETE};
 use crate::rom::Mirroring;
+use log::warn;
+#[cfg(feature = "serde")]
+use serde_big_array::BigArray;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 /**************************************************************/
 
+//drawn in place of tiles past the end of CHR (odd CHR sizes, misconfigured banks)
+const BLANK_TILE: [u8; 16] = [0; 16];
+//the bad tile is usually drawn every frame, only report the first one
+static CHR_OVERRUN_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/*
+Power-On State:
+
+Real hardware powers up with semi-random VRAM/OAM/palette contents.
+For reproducible runs (replays, regression hashes) everything is
+instead cleared to a fixed value.
+
+The PPU starts at dot 21 of scanline 0 because the CPU reset
+sequence takes 7 CPU cycles (3 PPU dots each) before the first
+instruction is fetched.
+*/
+pub const POWER_ON_VRAM: u8 = 0x00;
+pub const POWER_ON_OAM: u8 = 0x00;
+pub const POWER_ON_PALETTE: u8 = 0x00;
+pub const POWER_ON_DOT: u16 = 21;
+pub const POWER_ON_SCANLINE: u16 = 0;
+
+//sprites the PPU can fetch for a single scanline
+pub const SPRITES_PER_LINE: usize = 8;
+
+/*
+Sprite 0 Hit Delay:
+
+Sprite 0 hit is only checked once per scanline, at its end, rather
+than per dot. Hardware flags it on the first line the sprite is
+drawn (OAM Y + 1), so a delay of 1 is exact to the line. The default
+of 4 is the fudge the renderer was tuned with, it goes away once
+hit detection is dot based.
+*/
+pub const SPRITE_0_HIT_DELAY: u16 = 4;
+
+/*
+Warm-Up:
+
+For the first frame after power-on (until the pre-render line,
+about 29658 CPU cycles) the PPU ignores writes to PPUCTRL, PPUMASK,
+PPUSCROLL and PPUADDR, which is why games wait for two vblanks
+before setting it up. Some test ROMs check for this. It can be
+switched off so that code writing the PPU straight away (quick
+test carts, the benchmark) sees its writes take effect.
+*/
+pub const PPU_WARM_UP: bool = true;
+
+#[cfg(feature = "serde")]
+fn default_palette() -> Palette {
+    SYSTEM_PALLETE
+}
+
+#[cfg(feature = "serde")]
+fn default_sprite_limit() -> bool {
+    true
+}
+
+#[cfg(feature = "serde")]
+fn default_sprite_0_hit_delay() -> u16 {
+    SPRITE_0_HIT_DELAY
+}
+
+#[cfg(feature = "serde")]
+fn default_warm_up() -> bool {
+    PPU_WARM_UP
+}
+
+//one OAM entry with its attribute byte decoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpriteInfo {
+    //OAM Y, the first scanline the sprite is drawn on
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    //sprite palette 0-3
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl SpriteInfo {
+    //decodes a 4 byte OAM entry (y, tile, attributes, x)
+    pub fn from_oam(entry: &[u8]) -> Self {
+        SpriteInfo {
+            y: entry[0],
+            tile: entry[1],
+            palette: entry[2] & 0x03,
+            behind_background: entry[2] & 0x20 != 0,
+            flip_horizontal: entry[2] & 0x40 != 0,
+            flip_vertical: entry[2] & 0x80 != 0,
+            x: entry[3],
+        }
+    }
+}
+
+//notable things that can happen on a single dot
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PpuEvent {
+    //vblank flag was set (with NMI output disabled)
+    VBlankStart,
+    //vblank flag was set and an NMI was raised
+    Nmi,
+    Sprite0Hit,
+    //wrapped from the pre-render line back to scanline 0
+    FrameComplete,
+}
+
+/*
+Sprite Evaluation:
+
+The sprites found on one scanline, in OAM order. Hardware only has
+room for eight, a ninth sets the sprite overflow flag and anything
+past it is not drawn (unless the limit is switched off). The real
+overflow check is buggy and gives false positives/negatives, this
+implements the intended behaviour.
+*/
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ScanlineSprites {
+    //OAM offsets (multiples of 4), only the first `count` are used
+    pub oam: [usize; 64],
+    pub count: usize,
+    pub overflow: bool,
+}
+
+impl ScanlineSprites {
+    pub fn iter(&self) -> std::slice::Iter<'_, usize> {
+        self.oam[..self.count].iter()
+    }
+}
+
+/*
+Mid-Frame Register Changes:
+
+Games change PPUCTRL/PPUMASK and the scroll partway down the screen
+(status bars, pattern table swaps, hiding a line of sprites). The
+renderer works after the CPU has run a block of scanlines, so the
+values live at the start of each visible scanline (dot 0, after the
+horizontal scroll copy at dot 257 of the line before) are latched
+here and used to draw that line. A write lands on the first line
+that starts after it.
+*/
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineRegisters {
+    pub ctrl: u8,
+    pub mask: u8,
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+    //nametable the scroll is relative to (0-3)
+    pub name_table: u8,
+    //palette value shown where nothing else is drawn
+    pub backdrop: u8,
+}
+
+/*
+Every field, including the internal latches (v/t/x, the write
+toggle and the read buffer), is part of a save state. Leaving any
+of them out renders garbage for a frame after restoring.
+*/
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PPU {
-    pub chr_rom: Vec<u8>,
+    //pattern table memory, writable when the cartridge uses CHR-RAM, shared by clones until written (see chr_mut)
+    pub chr_rom: Arc<[u8]>,
+    pub chr_ram: bool,
+    //offsets into chr_rom of the 1KB windows at $0000-$1FFF, set by the mapper
+    chr_banks: [usize; 8],
     pub palette_table: [u8; 0x20],
-    pub vram: [u8; 0x0800],
+    //colours the renderer uses for palette values, a preference rather than console state
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_palette"))]
+    pub system_palette: Palette,
+    //drop sprites past the eighth on a line like hardware, off shows them all (less flicker)
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_sprite_limit"))]
+    pub sprite_limit: bool,
+    //lines after OAM Y that sprite 0 hit is reported, see SPRITE_0_HIT_DELAY
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_sprite_0_hit_delay"))]
+    pub sprite_0_hit_delay: u16,
+    //model the power-on warm-up, see PPU_WARM_UP
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_warm_up"))]
+    warm_up: bool,
+    //register writes are still being ignored
+    warming_up: bool,
+    /*
+    Nametable RAM: the lower 2KB is the console's own VRAM, the upper
+    2KB is the extra RAM supplied by four-screen cartridges and is only
+    addressed when mirroring is FOUR_SCREEN.
+    */
+    #[cfg_attr(feature = "serde", serde(with = "BigArray"))]
+    pub vram: [u8; 0x1000],
+    #[cfg_attr(feature = "serde", serde(with = "BigArray"))]
     pub oam_data: [u8; 0x0100],
 
     pub mirroring: Mirroring,
@@ -16,7 +209,6 @@ pub struct PPU {
     mask: u8,
     pub stat: u8,
     oam_addr: u8,
-    pub fetch_scroll_x: u8,
     pub fetch_scroll_y: u8,
     pub scroll_x: u8,
     pub scroll_y: u8,
@@ -25,21 +217,46 @@ pub struct PPU {
     pub v: u16,
     pub t: u16,
     pub x: u8,
+    //nametable X bit (t bit 10) as of dot 257, v's copy moves with the prefetch increments
+    scroll_name_table_x: u8,
 
     addr_latch: bool,
 
     pub cycles: u16,
     pub scanlines: u16,
+    pub frame_is_odd: bool,
+    //registers as they were at the start of each visible scanline this frame
+    #[cfg_attr(feature = "serde", serde(with = "BigArray"))]
+    pub line_registers: [LineRegisters; 240],
+
+    /*
+    NMI Latch:
 
+    The CPU's NMI input is edge triggered. Whenever the PPU's NMI
+    output (PPUCTRL bit 7 and the vblank flag) turns on, a pending
+    NMI is latched here. The CPU clears it when its interrupt poll
+    takes it (see Bus::take_nmi), so every edge is serviced exactly
+    once, however long the output stays on and wherever the
+    instruction boundaries fall.
+    */
     pub nmi_interrupt: bool,
+    //$2002 was read just before vblank, so the flag is not set this frame
+    vblank_suppressed: bool,
 }
 
 impl PPU {
-    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
-        PPU {
-            chr_rom,
+    pub fn new(chr_rom: Vec<u8>, chr_ram: bool, mirroring: Mirroring) -> Self {
+        let mut ppu = PPU {
+            chr_rom: chr_rom.into(),
+            chr_ram,
+            chr_banks: [0x0000, 0x0400, 0x0800, 0x0C00, 0x1000, 0x1400, 0x1800, 0x1C00],
             palette_table: [0; 0x20],
-            vram: [0; 0x0800],
+            system_palette: SYSTEM_PALLETE,
+            sprite_limit: true,
+            sprite_0_hit_delay: SPRITE_0_HIT_DELAY,
+            warm_up: PPU_WARM_UP,
+            warming_up: false,
+            vram: [0; 0x1000],
             oam_data: [0; 0x0100],
             
             mirroring,
@@ -105,7 +322,6 @@ impl PPU {
             */
             stat: 0,
             oam_addr: 0,
-            fetch_scroll_x: 0,
             fetch_scroll_y: 0,
             scroll_x: 0,
             scroll_y: 0,
@@ -114,100 +330,289 @@ impl PPU {
             v: 0,
             t: 0,
             x: 0,
+            scroll_name_table_x: 0,
 
             addr_latch: false,
 
             cycles: 21,
             scanlines: 0,
+            frame_is_odd: false,
+            line_registers: [LineRegisters::default(); 240],
 
             nmi_interrupt: false,
+            vblank_suppressed: false,
+
+        };
+        ppu.power_on();
+        ppu
+    }
+
+    //writable pattern tables, copied first if a clone (a rewind snapshot) still shares them
+    pub fn chr_mut(&mut self) -> &mut [u8] {
+        Arc::make_mut(&mut self.chr_rom)
+    }
+
+    //puts memory, registers, and timing into the documented power-on state
+    pub fn power_on(&mut self) {
+        if self.chr_ram {
+            self.chr_mut().fill(POWER_ON_VRAM);
+        }
+        self.palette_table = [POWER_ON_PALETTE; 0x20];
+        self.vram = [POWER_ON_VRAM; 0x1000];
+        self.oam_data = [POWER_ON_OAM; 0x0100];
+
+        self.addr_hi = 0;
+        self.addr_lo = 0;
+        self.ctrl = 0;
+        self.mask = 0;
+        self.stat = 0;
+        self.oam_addr = 0;
+        self.fetch_scroll_y = 0;
+        self.scroll_x = 0;
+        self.scroll_y = 0;
+        self.data_buf = 0;
 
+        self.v = 0;
+        self.t = 0;
+        self.x = 0;
+        self.scroll_name_table_x = 0;
+        self.addr_latch = false;
+
+        self.cycles = POWER_ON_DOT;
+        self.scanlines = POWER_ON_SCANLINE;
+        self.frame_is_odd = false;
+        self.line_registers = [LineRegisters::default(); 240];
+        self.nmi_interrupt = false;
+        self.vblank_suppressed = false;
+        self.warming_up = self.warm_up;
+    }
+
+    //takes effect from the next power-on, turning it off also ends a warm-up in progress
+    pub fn set_warm_up(&mut self, enabled: bool) {
+        self.warm_up = enabled;
+        self.warming_up &= enabled;
+    }
+
+    pub fn warm_up(&self) -> bool {
+        self.warm_up
+    }
+
+    //advances the given number of dots, returns true if a frame was completed
+    pub fn tick(&mut self, cycles: u16) -> bool {
+        let mut frame_done = false;
+        for _ in 0..cycles {
+            if self.step_dot() == Some(PpuEvent::FrameComplete) {
+                frame_done = true;
+            }
         }
+        frame_done
     }
 
-    pub fn tick(&mut self, cycles: u8) -> bool {
-        for i in 0..cycles {
-            self.cycles += 1;
-            if self.cycles == 256 {
-                if (self.v & 0x7000) != 0x7000 {
-                    self.v += 0x1000;
+    //advances exactly one dot
+    pub fn step_dot(&mut self) -> Option<PpuEvent> {
+        let mut event = None;
+        self.cycles += 1;
+        if self.cycles == 256 {
+            if (self.v & 0x7000) != 0x7000 {
+                self.v += 0x1000;
+            } else {
+                self.v &= 0x8FFF;
+                let mut y = (self.v & 0x03E0) >> 5;
+                if y == 29 {
+                    y = 0;
+                    self.v ^= 0x0800;
+                } else if y == 31 {
+                    y = 0;
                 } else {
-                    self.v &= 0x8FFF;
-                    let mut y = (self.v & 0x03E0) >> 5;
-                    if y == 29 {
-                        y = 0;
-                        self.v ^= 0x0800;
-                    } else if y == 31 {
-                        y = 0;
-                    } else {
-                        y += 1;
-                    }
-                    self.v = (self.v & 0xFC1F) | (y << 5);
+                    y += 1;
                 }
+                self.v = (self.v & 0xFC1F) | (y << 5);
             }
-            if self.cycles == 257 {
-                self.scroll_x = self.fetch_scroll_x;
-                self.v = (self.v & 0x7BE0) | (self.t & 0x041F);
+        }
+        if self.cycles == 257 {
+            //coarse X from t plus fine X, so $2006 writes move the scroll too
+            self.scroll_x = ((self.t & 0x001F) << 3) as u8 | self.x;
+            self.scroll_name_table_x = ((self.t & 0x0400) >> 10) as u8;
+            self.v = (self.v & 0x7BE0) | (self.t & 0x041F);
+            if self.scanlines < 240 && self.mask & 0x18 != 0 && self.evaluate_sprites(self.scanlines as usize, true).overflow {
+                self.stat |= 0x20;
             }
-            if self.scanlines == 261 && self.cycles >= 280 && self.cycles <= 304 {
-                self.scroll_y = self.fetch_scroll_y;
-                self.v = (self.v & 0x041F) | (self.t & 0x7BE0);
+        }
+        if self.scanlines == 261 && self.cycles == 1 {
+            self.warming_up = false;
+        }
+        if self.scanlines == 261 && self.cycles >= 280 && self.cycles <= 304 {
+            self.scroll_y = self.fetch_scroll_y;
+            self.v = (self.v & 0x041F) | (self.t & 0x7BE0);
+        }
+        if self.cycles == 328 || self.cycles == 336 || (
+            self.cycles > 0 && self.cycles <= 256 && self.cycles % 8 == 0
+        ) {
+            if (self.v & 0x001F) == 31 {
+                self.v &= 0xFFE0;
+                self.v ^= 0x0400;
+            } else {
+                self.v += 1;
             }
-            if self.cycles == 328 || self.cycles == 336 || (
-                self.cycles > 0 && self.cycles <= 256 && self.cycles % 8 == 0
-            ) {
-                if (self.v & 0x001F) == 31 {
-                    self.v &= 0xFFE0;
-                    self.v ^= 0x0400;
-                } else {
-                    self.v += 1;
+        }
+        //odd frames skip the last dot of the pre-render line while the background is on
+        if self.scanlines == 261 && self.cycles == 339 && self.frame_is_odd && self.mask & 0x08 != 0 {
+            self.cycles = 340;
+        }
+        if self.cycles >= 341 {
+
+            if self.is_sprite_0_hit(self.cycles as usize) {
+                if self.stat & 0x40 == 0 {
+                    event = Some(PpuEvent::Sprite0Hit);
                 }
+                self.stat |= 0x40;
             }
-            if self.cycles >= 341 {
 
-                if self.is_sprite_0_hit(self.cycles as usize) {
-                    self.stat |= 0x40;
-                }
+            self.cycles = 0;
+            self.scanlines += 1;
 
-                self.cycles = 0;
-                self.scanlines += 1;
+            if self.scanlines > 261 {
+                self.scanlines = 0;
+                self.frame_is_odd = !self.frame_is_odd;
+                self.nmi_interrupt = false;
+                self.stat &= 0x1F;
+                event = Some(PpuEvent::FrameComplete);
+            }
+            if self.scanlines < 240 {
+                self.line_registers[self.scanlines as usize] = self.latch_line_registers();
             }
         }
-        if self.scanlines == 241 {
-            self.stat |= 0x80;
-            self.stat &= 0xBF;
-            if self.ctrl & 0x80 != 0 {
-                self.nmi_interrupt = true;
+        //vblank starts at dot 1 of scanline 241
+        if self.scanlines == 241 && self.cycles == 1 {
+            if !self.vblank_suppressed {
+                self.stat |= 0x80;
+                self.stat &= 0xBF;
+                event = Some(PpuEvent::VBlankStart);
+                if self.ctrl & 0x80 != 0 {
+                    self.nmi_interrupt = true;
+                    event = Some(PpuEvent::Nmi);
+                }
             }
+            self.vblank_suppressed = false;
+        }
+        event
+    }
+
+    //advances to the start of the next scanline, returning everything that happened on the way
+    pub fn step_scanline(&mut self) -> Vec<PpuEvent> {
+        let scanline = self.scanlines;
+        let mut events = Vec::new();
+        while self.scanlines == scanline {
+            events.extend(self.step_dot());
+        }
+        events
+    }
+
+    //advances to the start of the next frame, returning everything that happened on the way
+    pub fn step_frame(&mut self) -> Vec<PpuEvent> {
+        let mut events = Vec::new();
+        loop {
+            let event = self.step_dot();
+            events.extend(event);
+            if event == Some(PpuEvent::FrameComplete) {
+                return events;
+            }
+        }
+    }
+
+    //8, or 16 with 8x16 sprites enabled
+    pub fn sprite_height(&self) -> usize {
+        if self.ctrl & 0x20 != 0 { 16 } else { 8 }
+    }
+
+    fn latch_line_registers(&self) -> LineRegisters {
+        LineRegisters {
+            ctrl: self.ctrl,
+            mask: self.mask,
+            scroll_x: self.scroll_x,
+            scroll_y: self.scroll_y,
+            name_table: ((self.v & 0x0800) >> 10) as u8 | self.scroll_name_table_x,
+            backdrop: self.backdrop(),
         }
+    }
+
+    /*
+    The backdrop is normally $3F00. With rendering off (forced blank)
+    and the VRAM address pointing into palette RAM, the PPU outputs
+    that palette entry instead, which is how some demos draw colour
+    bars without rendering anything.
+    */
+    pub fn backdrop(&self) -> u8 {
+        let addr = ((self.addr_hi as u16) << 8 | self.addr_lo as u16) & 0x3FFF;
+        let addr = if self.mask & 0x18 == 0 && addr >= 0x3F00 { addr } else { 0x3F00 };
+        self.palette_table[Self::palette_index(addr)] & 0x3F
+    }
+
+    //finds the sprites covering a scanline, with `limit` stopping at the ninth
+    pub fn evaluate_sprites(&self, scanline: usize, limit: bool) -> ScanlineSprites {
+        self.evaluate_sprites_sized(scanline, self.sprite_height(), limit)
+    }
 
-        if self.scanlines > 261 {
-            self.scanlines = 0;
-            self.nmi_interrupt = false;
-            self.stat &= 0x3F;
-            return true;
+    //same as evaluate_sprites but for an explicit sprite height (8 or 16)
+    pub fn evaluate_sprites_sized(&self, scanline: usize, height: usize, limit: bool) -> ScanlineSprites {
+        let mut sprites = ScanlineSprites {
+            oam: [0; 64],
+            count: 0,
+            overflow: false,
+        };
+        for i in (0..self.oam_data.len()).step_by(4) {
+            let y = self.oam_data[i] as usize;
+            if scanline < y || scanline >= y + height {
+                continue;
+            }
+            if sprites.count >= SPRITES_PER_LINE {
+                sprites.overflow = true;
+                if limit {
+                    break;
+                }
+            }
+            sprites.oam[sprites.count] = i;
+            sprites.count += 1;
         }
-        false
+        sprites
     }
 
     fn is_sprite_0_hit(&self, cycle: usize) -> bool {
-        let y = self.oam_data[0] as usize;
+        let y = self.oam_data[0] as u16;
         let x = self.oam_data[3] as usize;
-        (y == (self.scanlines - 4) as usize) && (x <= cycle) && (self.mask & 0x10 != 0)
+        //added to Y rather than taken off the scanline, which underflows on lines 0-3
+        (y.saturating_add(self.sprite_0_hit_delay) == self.scanlines) && (x <= cycle) && (self.mask & 0x10 != 0)
     }
 
+    /*
+    Loopy Registers:
+
+    v/t are 15 bits, yyy NN YYYYY XXXXX (fine Y, nametable, coarse Y,
+    coarse X), x is the 3 bit fine X scroll. The two $2005/$2006
+    writes share one toggle -
+        $2005 first     t XXXXX = val >> 3, x = val & 7
+        $2005 second    t yyy = val & 7, YYYYY = val >> 3
+        $2006 first     t bits 8-13 = val & 0x3F, bit 14 cleared
+        $2006 second    t bits 0-7 = val, then v = t
+    */
     pub fn addr_write(&mut self, val: u8) {
+        if self.warming_up {
+            return;
+        }
         if self.addr_latch {
             self.addr_lo = val;
-            self.t = (self.t & 0x00FF) | (((val as u16) & 0x003F) << 8);
+            self.t = (self.t & 0xFF00) | (val as u16);
+            self.v = self.t;
         } else {
             self.addr_hi = val;
-            self.t = (self.t & 0xFF00) | ((val as u16) & 0x00FF);
-            self.v = self.t;
+            self.t = (self.t & 0x00FF) | (((val as u16) & 0x003F) << 8);
         }
         self.addr_latch = !self.addr_latch;
     }
     pub fn ctrl_write(&mut self, val: u8) {
+        if self.warming_up {
+            return;
+        }
         let prev_nmi_stat = self.ctrl & 0x80 != 0;
         self.ctrl = val;
         if !prev_nmi_stat && self.ctrl & 0x80 != 0 && self.stat & 0x80 != 0 {
@@ -216,9 +621,27 @@ impl PPU {
         self.t = (self.t & 0xF3FF) | (((val as u16) & 0x0003) << 10);
     }
     pub fn mask_write(&mut self, val: u8) {
+        if self.warming_up {
+            return;
+        }
         self.mask = val;
     }
+    /*
+    VBlank Race:
+
+    Reading PPUSTATUS right around the dot vblank starts on
+    (scanline 241, dot 1) interferes with setting the flag -
+        dot 0       reads clear, the flag is never set and no NMI occurs
+        dot 1 or 2  reads set and clears it, but the NMI is suppressed
+    */
     pub fn stat_read(&mut self) -> u8 {
+        if self.scanlines == 241 {
+            match self.cycles {
+                0 => self.vblank_suppressed = true,
+                1 | 2 => self.nmi_interrupt = false,
+                _ => {},
+            }
+        }
         let res = self.stat;
         self.stat &= 0x7F;
         self.addr_latch = false;
@@ -244,39 +667,32 @@ impl PPU {
         self.oam_data[self.oam_addr as usize]
     }
     pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
-        for i in 0..256 {
-            self.oam_data[self.oam_addr as usize + i] = data[i];
+        //the copy starts at OAMADDR and wraps around the end of OAM
+        for (i, byte) in data.iter().enumerate() {
+            self.oam_data[self.oam_addr.wrapping_add(i as u8) as usize] = *byte;
         }
-        self.tick(255);
-        self.tick(255);
-        
-        self.tick(3 + (self.cycles % 2) as u8);
-        /*
-        
-        let (left, right) = self.oam_data.split_at_mut(self.oam_addr as usize + 1);
-        left.copy_from_slice(&data[(255-self.oam_addr) as usize..=255]);
-        right.copy_from_slice(&data[0..=(255-self.oam_addr) as usize]);
-        */
     }
     pub fn scroll_write(&mut self, val: u8) {
+        if self.warming_up {
+            return;
+        }
         if self.addr_latch {
             self.fetch_scroll_y = val;
-            self.t = (self.t & 0xFFE0) | ((val as u16) >> 3);
-            self.x = val &0x03;
+            self.t = (self.t & 0x0C1F) | (((val as u16) & 0x07) << 12) | (((val as u16) & 0xF8) << 2);
         } else {
-            self.fetch_scroll_x = val;
-            self.t = (self.t & 0x0C1F) | ((val as u16) << 12) | (((val as u16) & 0xF8) << 2);
+            self.t = (self.t & 0xFFE0) | ((val as u16) >> 3);
+            self.x = val & 0x07;
         }
         self.addr_latch = !self.addr_latch;
     }
     pub fn read(&mut self) -> u8 {
-        let addr = ((self.addr_hi as u16) << 8) | self.addr_lo as u16;
+        let addr = Self::mirror_nametable_space(((self.addr_hi as u16) << 8) | self.addr_lo as u16);
         self.inc_vram_addr();
 
         match addr {
             0x0000..=0x1FFF => {
                 let res = self.data_buf;
-                self.data_buf = self.chr_rom[addr as usize];
+                self.data_buf = self.chr_rom[self.chr_addr(addr)];
                 res
             },
             0x2000..=0x2FFF => {
@@ -284,68 +700,640 @@ impl PPU {
                 self.data_buf = self.vram[self.mirror_vram_addr(addr) as usize];
                 res
             },
-            0x3000..=0x3EFF => panic!("addr space 0x3000..0x3eff is not expected to be used, requested = {:X?} ", addr),
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror & 0x001F) as usize]
-            },
+            /*
+            Palette reads skip the read buffer and return immediately,
+            but the buffer is still refilled with the nametable byte
+            "underneath" the palette ($2F00-$2FFF).
+            */
             0x3F00..=0x3FFF => {
-                self.palette_table[(addr & 0x001F) as usize]
+                self.data_buf = self.vram[self.mirror_vram_addr(addr - 0x1000) as usize];
+                self.palette_table[Self::palette_index(addr)]
             },
             _ => panic!("unexpected access to mirrored space {}", addr),
         }
     }
     pub fn write(&mut self, data: u8) {
-        let addr = ((self.addr_hi as u16) << 8) | self.addr_lo as u16;
+        let addr = Self::mirror_nametable_space(((self.addr_hi as u16) << 8) | self.addr_lo as u16);
 
         match addr {
             0x0000..=0x1FFF => {
-                println!("attempt to write to chr rom space ${:04X?}", addr);
+                if self.chr_ram {
+                    let chr_addr = self.chr_addr(addr);
+                    self.chr_mut()[chr_addr] = data;
+                } else {
+                    warn!("attempt to write to chr rom space ${:04X?}", addr);
+                }
             },
             0x2000..=0x2FFF => {
                 self.vram[self.mirror_vram_addr(addr) as usize] = data;
             },
-            0x3000..=0x3EFF => panic!("addr space 0x3000..0x3eff is not expected to be used, requested = {} ", addr),
-            0x3f10 | 0x3f14 | 0x3f18 | 0x3f1c => {
-                let add_mirror = addr - 0x10;
-                self.palette_table[(add_mirror & 0x001F) as usize] = data;
-            },
             0x3F00..=0x3FFF => {
-                self.palette_table[(addr & 0x001F) as usize] = data;
+                self.palette_table[Self::palette_index(addr)] = data;
             },
             _ => {panic!("unexpected access to mirrored space ${:04X?}", addr)},
         }
         self.inc_vram_addr();
     }
 
+    /*
+    Tooling Access:
+
+    Reads and writes anywhere in PPU address space ($0000-$3FFF)
+    without touching the VRAM address, the read buffer or any
+    latch. Pokes reach CHR-ROM too, so tools can patch graphics.
+    */
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        let addr = Self::mirror_nametable_space(addr & 0x3FFF);
+        match addr {
+            0x0000..=0x1FFF => self.chr_rom[self.chr_addr(addr)],
+            0x2000..=0x2FFF => self.vram[self.mirror_vram_addr(addr) as usize],
+            _ => self.palette_table[Self::palette_index(addr)],
+        }
+    }
+    pub fn poke_vram(&mut self, addr: u16, data: u8) {
+        let addr = Self::mirror_nametable_space(addr & 0x3FFF);
+        match addr {
+            0x0000..=0x1FFF => {
+                let chr_addr = self.chr_addr(addr);
+                self.chr_mut()[chr_addr] = data;
+            },
+            0x2000..=0x2FFF => self.vram[self.mirror_vram_addr(addr) as usize] = data,
+            _ => self.palette_table[Self::palette_index(addr)] = data,
+        }
+    }
+    pub fn peek_oam(&self, addr: u8) -> u8 {
+        self.oam_data[addr as usize]
+    }
+    pub fn poke_oam(&mut self, addr: u8, data: u8) {
+        self.oam_data[addr as usize] = data;
+    }
+    /*
+    Debug Dumps:
+
+    Snapshots of OAM, the four logical nametables (after mirroring,
+    attribute tables included) and the palette as the CPU sees them,
+    plus a text rendering of all three for offline inspection.
+    */
+    pub fn sprites(&self) -> Vec<SpriteInfo> {
+        self.oam_data.chunks(4).map(SpriteInfo::from_oam).collect()
+    }
+    pub fn nametables(&self) -> [[u8; 0x400]; 4] {
+        let mut tables = [[0; 0x400]; 4];
+        for (i, table) in tables.iter_mut().enumerate() {
+            for (offset, byte) in table.iter_mut().enumerate() {
+                *byte = self.peek_vram(0x2000 + (i * 0x400 + offset) as u16);
+            }
+        }
+        tables
+    }
+    pub fn palette(&self) -> [u8; 0x20] {
+        let mut palette = [0; 0x20];
+        for (i, entry) in palette.iter_mut().enumerate() {
+            *entry = self.peek_vram(0x3F00 + i as u16);
+        }
+        palette
+    }
+    pub fn dump_text(&self) -> String {
+        let mut text = String::from("# NES_Emu PPU dump\n\n# OAM\n#  n   Y   X tile pal prio  flip\n");
+        for (i, sprite) in self.sprites().iter().enumerate() {
+            text.push_str(&format!(
+                "  {:02} {:3} {:3}  ${:02X}   {} {} {}{}\n",
+                i, sprite.y, sprite.x, sprite.tile, sprite.palette,
+                if sprite.behind_background { "back " } else { "front" },
+                if sprite.flip_horizontal { 'H' } else { '-' },
+                if sprite.flip_vertical { 'V' } else { '-' },
+            ));
+        }
+        text.push_str("\n# OAM bytes\n");
+        dump_hex(&mut text, 0, &self.oam_data, 16);
+        for (i, table) in self.nametables().iter().enumerate() {
+            text.push_str(&format!("\n# Nametable {} (attributes from ${:04X})\n", i, 0x23C0 + i * 0x400));
+            dump_hex(&mut text, 0x2000 + i * 0x400, table, 32);
+        }
+        text.push_str("\n# Palette\n");
+        dump_hex(&mut text, 0x3F00, &self.palette(), 16);
+        text
+    }
+
+    //what a $2007 read would return right now
+    pub fn peek_data(&self) -> u8 {
+        let addr = Self::mirror_nametable_space(((self.addr_hi as u16) << 8 | self.addr_lo as u16) & 0x3FFF);
+        if addr >= 0x3F00 { self.palette_table[Self::palette_index(addr)] } else { self.data_buf }
+    }
+
+    //$3000-$3EFF is a mirror of the nametables at $2000-$2EFF
+    fn mirror_nametable_space(addr: u16) -> u16 {
+        match addr {
+            0x3000..=0x3EFF => addr - 0x1000,
+            _ => addr,
+        }
+    }
+
+    /*
+    Palette RAM Layout -
+        $3F00           universal backdrop colour
+        $3F01-$3F0F     background palettes 0-3 (entries 1-3 each)
+        $3F11-$3F1F     sprite palettes 0-3 (entries 1-3 each)
+    The whole 32 bytes repeat up to $3FFF. $3F10/$14/$18/$1C are
+    mirrors of $3F00/$04/$08/$0C. $3F04/$08/$0C are real storage but
+    are never drawn, colour 0 of every palette shows the backdrop.
+    */
+    fn palette_index(addr: u16) -> usize {
+        let idx = (addr & 0x001F) as usize;
+        if idx & 0x13 == 0x10 { idx - 0x10 } else { idx }
+    }
+
+    pub fn set_chr_banks(&mut self, banks: [usize; 8]) {
+        self.chr_banks = banks;
+    }
+
+    //translates a pattern table address through the mapper's CHR banks
+    fn chr_addr(&self, addr: u16) -> usize {
+        self.chr_banks[(addr as usize >> 10) & 0x07] + (addr as usize & 0x03FF)
+    }
+
+    //the 16 bytes of a tile in pattern table `bank` (0 = $0000, 1 = $1000)
+    pub fn chr_tile(&self, bank: usize, tile_n: usize) -> &[u8] {
+        let start = self.chr_addr((bank * 0x1000 + tile_n * 16) as u16);
+        match self.chr_rom.get(start..start + 16) {
+            Some(tile) => tile,
+            None => {
+                if !CHR_OVERRUN_REPORTED.swap(true, Ordering::Relaxed) {
+                    warn!("tile {} of pattern table {} lies past the end of CHR (${:X} > ${:X}), drawing it blank",
+                        tile_n, bank, start + 16, self.chr_rom.len());
+                }
+                &BLANK_TILE
+            },
+        }
+    }
+
+    //mappers such as MMC1 switch the nametable layout at runtime
+    pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+        self.mirroring = mirroring;
+    }
+
     pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
         let mirrored_vram = addr & 0x2FFF;
         let vram_idx = mirrored_vram - 0x2000;
         let name_table = vram_idx / 0x400;
 
+        /*
+        Nametable Layout -
+            VERTICAL          HORIZONTAL        ONE_SCREEN_LOWER/UPPER
+            [ A ] [ B ]       [ A ] [ A ]       [ A ] [ A ]   or   [ B ] [ B ]
+            [ A ] [ B ]       [ B ] [ B ]       [ A ] [ A ]        [ B ] [ B ]
+        FOUR_SCREEN uses the cartridge RAM so all four are distinct.
+        */
         match(&self.mirroring, name_table) {
-            (Mirroring::VERTICAL, 2) | (Mirroring::VERTICAL, 3) |
+            (Mirroring::FOUR_SCREEN, _) => vram_idx,
+            (Mirroring::ONE_SCREEN_LOWER, _) => vram_idx & 0x03FF,
+            (Mirroring::ONE_SCREEN_UPPER, _) => 0x0400 | (vram_idx & 0x03FF),
+            (Mirroring::VERTICAL, 0) | (Mirroring::VERTICAL, 1) => vram_idx,
+            (Mirroring::VERTICAL, _) => vram_idx - 0x800,
+            (Mirroring::HORIZONTAL, 0) => vram_idx,
             (Mirroring::HORIZONTAL, 3) => vram_idx - 0x800,
-            (Mirroring::HORIZONTAL, 2) | (Mirroring::HORIZONTAL, 1) => vram_idx - 0x400,
-            _ => vram_idx,
+            (Mirroring::HORIZONTAL, _) => vram_idx - 0x400,
+        }
+    }
+}
+
+//rows of `width` hex bytes, each prefixed with the address of its first byte
+fn dump_hex(text: &mut String, base: usize, data: &[u8], width: usize) {
+    for (row, bytes) in data.chunks(width).enumerate() {
+        text.push_str(&format!("{:04X}:", base + row * width));
+        for byte in bytes {
+            text.push_str(&format!(" {:02X}", byte));
         }
+        text.push('\n');
     }
 }
 
-#[rustfmt::skip]
-
-pub static SYSTEM_PALLETE: [(u8,u8,u8); 64] = [
-   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
-   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
-   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
-   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
-   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
-   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
-   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
-   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
-   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
-   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
-   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
-   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
-   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
-];
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display;
+    use crate::mapper::tests::ines;
+    use crate::rom::Rom;
+
+    fn ppu(mirroring: Mirroring) -> PPU {
+        let mut ppu = PPU::new(vec![0; 0x2000], false, mirroring);
+        ppu.set_warm_up(false);
+        ppu
+    }
+
+    fn set_addr(ppu: &mut PPU, addr: u16) {
+        ppu.addr_write((addr >> 8) as u8);
+        ppu.addr_write(addr as u8);
+    }
+
+    //a buffered $2007 read, the first read only fills the buffer
+    fn read_data(ppu: &mut PPU, addr: u16) -> u8 {
+        set_addr(ppu, addr);
+        ppu.read();
+        ppu.read()
+    }
+
+    #[test]
+    fn nametable_mirror_writes_are_readable_below() {
+        let mut ppu = ppu(Mirroring::HORIZONTAL);
+        set_addr(&mut ppu, 0x3000);
+        ppu.write(0x42);
+        set_addr(&mut ppu, 0x3EFF);
+        ppu.write(0x24);
+        assert_eq!(read_data(&mut ppu, 0x2000), 0x42);
+        assert_eq!(read_data(&mut ppu, 0x2EFF), 0x24);
+        assert_eq!(read_data(&mut ppu, 0x3000), 0x42);
+    }
+    #[test]
+    fn palette_reads_are_immediate_and_refill_the_buffer() {
+        let mut ppu = ppu(Mirroring::HORIZONTAL);
+        set_addr(&mut ppu, 0x2F05);
+        ppu.write(0x99);
+        set_addr(&mut ppu, 0x3F05);
+        ppu.write(0x17);
+        set_addr(&mut ppu, 0x3F05);
+        assert_eq!(ppu.read(), 0x17);
+        //the buffer now holds the nametable byte under the palette
+        set_addr(&mut ppu, 0x2000);
+        assert_eq!(ppu.read(), 0x99);
+    }
+
+    #[test]
+    fn chr_ram_is_writable_and_rendered() {
+        let rom = Rom::new(&ines(0, 1, 0)).unwrap();
+        assert!(rom.chr_ram);
+        let mut ppu = PPU::new(rom.chr_rom, rom.chr_ram, rom.screen_mirroring);
+        ppu.set_warm_up(false);
+        //top row of tile 1: leftmost pixel colour 1
+        set_addr(&mut ppu, 0x0010);
+        ppu.write(0x80);
+        set_addr(&mut ppu, 0x3F01);
+        ppu.write(0x16);
+        assert_eq!(read_data(&mut ppu, 0x0010), 0x80);
+
+        let frame = display::render_pattern_table(&ppu, 0);
+        let pixel = |x: usize, y: usize| {
+            let base = (y * 256 + x) * 4;
+            (frame.data[base], frame.data[base + 1], frame.data[base + 2])
+        };
+        assert_eq!(pixel(8, 0), SYSTEM_PALLETE[0x16]);
+        assert_eq!(pixel(9, 0), SYSTEM_PALLETE[0x00]);
+    }
+
+    #[test]
+    fn four_screen_nametables_do_not_alias() {
+        let mut raw = ines(0, 1, 1);
+        raw[6] |= 0x08;
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.screen_mirroring, Mirroring::FOUR_SCREEN);
+        let mut ppu = PPU::new(rom.chr_rom, rom.chr_ram, rom.screen_mirroring);
+        ppu.set_warm_up(false);
+        for (i, base) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+            set_addr(&mut ppu, base + 0x123);
+            ppu.write(0xA0 + i as u8);
+        }
+        for (i, base) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+            assert_eq!(read_data(&mut ppu, base + 0x123), 0xA0 + i as u8);
+        }
+    }
+
+    #[test]
+    fn single_screen_modes_alias_all_four_nametables() {
+        for (mirroring, page) in [(Mirroring::ONE_SCREEN_LOWER, 0x000), (Mirroring::ONE_SCREEN_UPPER, 0x400)] {
+            let mut ppu = ppu(mirroring);
+            for (i, base) in [0x2000, 0x2400, 0x2800, 0x2C00].into_iter().enumerate() {
+                assert_eq!(ppu.mirror_vram_addr(base + 0x3FF), page + 0x3FF);
+                set_addr(&mut ppu, base + i as u16);
+                ppu.write(0x10 + i as u8);
+            }
+            for base in [0x2000, 0x2400, 0x2800, 0x2C00] {
+                for i in 0..4 {
+                    assert_eq!(read_data(&mut ppu, base + i), 0x10 + i as u8);
+                }
+            }
+        }
+    }
+
+    //NMI enabled, stepped to `dot` of scanline 241 (vblank is set on dot 1)
+    fn at_vblank_dot(dot: u16) -> PPU {
+        let mut ppu = ppu(Mirroring::HORIZONTAL);
+        ppu.ctrl_write(0x80);
+        ppu.scanlines = 241;
+        ppu.cycles = 0;
+        for _ in 0..dot {
+            ppu.step_dot();
+        }
+        ppu
+    }
+
+    #[test]
+    fn status_read_just_before_vblank_suppresses_it() {
+        let mut ppu = at_vblank_dot(0);
+        assert_eq!(ppu.stat_read() & 0x80, 0);
+        assert_eq!(ppu.step_dot(), None);
+        assert_eq!(ppu.stat & 0x80, 0);
+        assert!(!ppu.nmi_interrupt);
+    }
+
+    #[test]
+    fn status_read_on_or_after_the_vblank_dot_suppresses_the_nmi() {
+        for dot in [1, 2] {
+            let mut ppu = at_vblank_dot(dot);
+            assert!(ppu.nmi_interrupt);
+            assert_eq!(ppu.stat_read() & 0x80, 0x80);
+            assert!(!ppu.nmi_interrupt, "dot {}", dot);
+            assert_eq!(ppu.stat & 0x80, 0);
+        }
+    }
+
+    #[test]
+    fn status_read_later_in_vblank_keeps_the_nmi() {
+        let mut ppu = at_vblank_dot(3);
+        assert_eq!(ppu.stat_read() & 0x80, 0x80);
+        assert!(ppu.nmi_interrupt);
+    }
+
+    //dots in the next two frames, starting from a frame boundary
+    fn two_frame_dots(mask: u8) -> u32 {
+        let mut ppu = ppu(Mirroring::HORIZONTAL);
+        ppu.mask_write(mask);
+        ppu.step_frame();
+        let mut dots = 0;
+        for _ in 0..2 {
+            loop {
+                dots += 1;
+                if ppu.step_dot() == Some(PpuEvent::FrameComplete) {
+                    break;
+                }
+            }
+        }
+        dots
+    }
+
+    #[test]
+    fn odd_frames_skip_a_dot_while_rendering() {
+        assert_eq!(two_frame_dots(0x00), 2 * 262 * 341);
+        assert_eq!(two_frame_dots(0x08), 2 * 262 * 341 - 1);
+        //sprites alone don't skip it
+        assert_eq!(two_frame_dots(0x10), 2 * 262 * 341);
+    }
+
+    #[test]
+    fn vblank_starts_on_dot_1_of_scanline_241() {
+        let mut quiet = ppu(Mirroring::HORIZONTAL);
+        while (quiet.scanlines, quiet.cycles) != (241, 0) {
+            assert_eq!(quiet.step_dot(), None);
+        }
+        assert_eq!(quiet.stat & 0x80, 0);
+        assert_eq!(quiet.step_dot(), Some(PpuEvent::VBlankStart));
+        assert_eq!(quiet.stat & 0x80, 0x80);
+
+        let mut nmi = ppu(Mirroring::HORIZONTAL);
+        nmi.ctrl_write(0x80);
+        assert_eq!(nmi.step_frame(), [PpuEvent::Nmi, PpuEvent::FrameComplete]);
+        assert_eq!(nmi.step_scanline(), []);
+        assert_eq!(nmi.scanlines, 1);
+    }
+
+    #[cfg(feature = "serde")]
+    //a picture that changes with every pixel of scroll: distinct tiles and colours everywhere
+    fn busy_ppu() -> PPU {
+        let chr = (0..0x2000).map(|i| (i * 7 / 3) as u8).collect();
+        let mut ppu = PPU::new(chr, false, Mirroring::VERTICAL);
+        ppu.set_warm_up(false);
+        for (i, byte) in ppu.vram.iter_mut().enumerate() {
+            *byte = (i * 13) as u8;
+        }
+        for (i, entry) in ppu.palette_table.iter_mut().enumerate() {
+            *entry = i as u8 + 0x01;
+        }
+        ppu
+    }
+
+    fn step_to_scanline(ppu: &mut PPU, scanline: u16) {
+        while ppu.scanlines != scanline {
+            ppu.step_dot();
+        }
+    }
+
+    fn render_line(ppu: &PPU, line: usize) -> Vec<u8> {
+        let mut frame = display::Frame::new((0, 0, 0));
+        display::render(ppu, &mut display::Layers::new(), &mut frame, line, line + 1);
+        frame.data[line * 256 * 4..(line + 1) * 256 * 4].to_vec()
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn save_states_keep_the_scroll_latches() {
+        let mut ppu = busy_ppu();
+        ppu.mask_write(0x0A);
+        ppu.scroll_write(13);
+        ppu.scroll_write(7);
+        step_to_scanline(&mut ppu, 100);
+        //half of a new scroll pair, the write toggle is now set
+        ppu.scroll_write(0x2B);
+        let state = bincode::serialize(&ppu).unwrap();
+        let mut restored: PPU = bincode::deserialize(&state).unwrap();
+
+        for ppu in [&mut ppu, &mut restored] {
+            ppu.scroll_write(0x11);
+            step_to_scanline(ppu, 121);
+        }
+        assert_eq!(restored.x, 3);
+        assert_eq!(render_line(&restored, 120), render_line(&ppu, 120));
+        assert_ne!(render_line(&restored, 120), render_line(&restored, 99));
+    }
+
+    #[test]
+    fn palette_mirrors_share_storage() {
+        let mut ppu = ppu(Mirroring::HORIZONTAL);
+        set_addr(&mut ppu, 0x3F00);
+        for i in 0..0x20 {
+            ppu.write(0x20 + i);
+        }
+        //$3F10/$14/$18/$1C were written last, over $3F00/$04/$08/$0C
+        for i in 0..0x20 {
+            let expected = if i % 4 == 0 { 0x30 + (i & 0x0F) } else { 0x20 + i };
+            set_addr(&mut ppu, 0x3F00 + i as u16);
+            assert_eq!(ppu.read(), expected, "$3F{:02X}", i);
+        }
+        //the same holds for every 32 byte copy
+        set_addr(&mut ppu, 0x3F30);
+        ppu.write(0x01);
+        set_addr(&mut ppu, 0x3FF4);
+        ppu.write(0x02);
+        assert_eq!(ppu.palette_table[0x00], 0x01);
+        assert_eq!(ppu.palette_table[0x04], 0x02);
+    }
+
+    #[test]
+    fn a_pattern_table_switch_splits_at_the_next_line() {
+        //tile 1 is solid colour 1 in the left pattern table and solid colour 2 in the right one
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        chr[0x1018..0x1020].fill(0xFF);
+        chr[0x30] = 0xFF;
+        let mut ppu = PPU::new(chr, false, Mirroring::VERTICAL);
+        ppu.set_warm_up(false);
+        for table in [0x000, 0x400] {
+            ppu.vram[table..table + 0x3C0].fill(1);
+        }
+        ppu.palette_table[1] = 0x16;
+        ppu.palette_table[2] = 0x2A;
+        //tile 3 is only a top row, drawn as is at X 200 and flipped vertically at X 208
+        ppu.palette_table[0x11] = 0x30;
+        ppu.oam_data.fill(0xFF);
+        ppu.oam_data[..8].copy_from_slice(&[50, 3, 0x00, 200, 50, 3, 0x80, 208]);
+        ppu.mask_write(0x1E);
+        step_to_scanline(&mut ppu, 100);
+        while ppu.cycles < 128 {
+            ppu.step_dot();
+        }
+        //the write lands mid-line, line 100 keeps the table it started with
+        ppu.ctrl_write(0x10);
+        step_to_scanline(&mut ppu, 240);
+
+        let colour = |value: u8| {
+            let (r, g, b) = ppu.system_palette[value as usize];
+            [r, g, b]
+        };
+        for (line, expected) in [(1, 0x16), (100, 0x16), (101, 0x2A), (239, 0x2A)] {
+            let pixels = render_line(&ppu, line);
+            assert_eq!(pixels[..3], colour(expected), "line {}", line);
+            assert_eq!(pixels[255 * 4..255 * 4 + 3], colour(expected), "line {}", line);
+        }
+        //drawn a line at a time, the flipped sprite's top row still lands on its bottom line
+        let sprite_rows = |x: usize| (0..240).filter(|&line| render_line(&ppu, line)[x * 4..x * 4 + 3] == colour(0x30)).collect::<Vec<_>>();
+        assert_eq!(sprite_rows(200), vec![50]);
+        assert_eq!(sprite_rows(208), vec![57]);
+    }
+
+    #[test]
+    fn forced_blank_shows_the_palette_entry_v_points_at() {
+        let mut ppu = ppu(Mirroring::HORIZONTAL);
+        ppu.palette_table[0] = 0x0F;
+        ppu.palette_table[2] = 0x27;
+        set_addr(&mut ppu, 0x3F02);
+        assert_eq!(ppu.backdrop(), 0x27);
+        //mirrors of palette RAM count too
+        set_addr(&mut ppu, 0x3FE2);
+        assert_eq!(ppu.backdrop(), 0x27);
+        set_addr(&mut ppu, 0x2002);
+        assert_eq!(ppu.backdrop(), 0x0F);
+
+        //greyscale keeps the brightness bits, red emphasis dims green and blue
+        set_addr(&mut ppu, 0x3F02);
+        ppu.mask_write(0x01);
+        step_to_scanline(&mut ppu, 10);
+        ppu.mask_write(0x20);
+        step_to_scanline(&mut ppu, 20);
+        let (r, g, b) = ppu.system_palette[0x27];
+        let grey = ppu.system_palette[0x20];
+        assert_eq!(render_line(&ppu, 5)[..3], [grey.0, grey.1, grey.2]);
+        assert_eq!(render_line(&ppu, 15)[..3], [r, (g as u16 * 3 / 4) as u8, (b as u16 * 3 / 4) as u8]);
+
+        //with rendering on the backdrop is $3F00 again
+        ppu.mask_write(0x08);
+        assert_eq!(ppu.backdrop(), 0x0F);
+    }
+
+    #[test]
+    fn tiles_past_the_end_of_chr_draw_blank() {
+        //6KB of CHR, pattern table 1 ends halfway at tile $80
+        let mut ppu = PPU::new(vec![0xFF; 0x1800], false, Mirroring::HORIZONTAL);
+        ppu.poke_vram(0x3F00, 0x0F);
+        ppu.poke_vram(0x3F03, 0x16);
+        assert_eq!(ppu.chr_tile(1, 0x7F), [0xFF; 16]);
+        assert_eq!(ppu.chr_tile(1, 0x80), [0; 16]);
+        assert_eq!(ppu.chr_tile(1, 0xFF), [0; 16]);
+        let frame = display::render_pattern_table(&ppu, 1);
+        let pixel = |x: usize, y: usize| frame.data[(y * 256 + x) * 4..][..3].to_vec();
+        //tile $7F ends row 7, tile $80 starts row 8 in backdrop colour
+        assert_ne!(pixel(127, 63), pixel(0, 64));
+        assert_eq!(pixel(0, 64), pixel(127, 127));
+    }
+
+    #[test]
+    fn sprite_0_at_the_top_does_not_hit_on_the_first_lines() {
+        let mut ppu = ppu(Mirroring::HORIZONTAL);
+        ppu.poke_oam(0, 0);
+        ppu.poke_oam(3, 0);
+        ppu.mask_write(0x18);
+        step_to_scanline(&mut ppu, 0);
+        for line in 1..=4 {
+            step_to_scanline(&mut ppu, line);
+            assert_eq!(ppu.stat & 0x40, 0, "hit reported by line {}", line - 1);
+        }
+        //Y plus the delay
+        step_to_scanline(&mut ppu, 5);
+        assert_eq!(ppu.stat & 0x40, 0x40);
+    }
+
+    #[test]
+    fn dump_decodes_a_crafted_oam() {
+        let mut ppu = ppu(Mirroring::HORIZONTAL);
+        ppu.write_oam_dma(&[0; 256]);
+        for (addr, byte) in [0x20, 0x41, 0xE2, 0x30, 0x0A, 0x05, 0x01, 0xC8].into_iter().enumerate() {
+            ppu.poke_oam(addr as u8, byte);
+        }
+        ppu.poke_vram(0x2400, 0x77);
+        ppu.poke_vram(0x3F10, 0x2A);
+        let text = ppu.dump_text();
+        let lines: Vec<&str> = text.lines().collect();
+        //palette 2, behind the background, flipped both ways
+        assert!(lines.contains(&"  00  32  48  $41   2 back  HV"));
+        assert!(lines.contains(&"  01  10 200  $05   1 front --"));
+        assert!(lines.contains(&"  63   0   0  $00   0 front --"));
+        assert!(lines.contains(&"0000: 20 41 E2 30 0A 05 01 C8 00 00 00 00 00 00 00 00"));
+        //horizontal mirroring shows the byte in the first two nametables
+        assert!(lines.iter().any(|line| line.starts_with("2000: 77 00")));
+        assert!(lines.iter().any(|line| line.starts_with("2400: 77 00")));
+        assert!(lines.iter().any(|line| line.starts_with("2800: 00 00")));
+        assert!(lines.iter().any(|line| line.starts_with("3F00: 2A ")));
+    }
+
+    #[test]
+    fn fine_x_scroll_starts_inside_the_first_tile() {
+        //tile 1 has only its 4th pixel (bit 4) set on every row
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0x10);
+        let mut ppu = PPU::new(chr, false, Mirroring::HORIZONTAL);
+        ppu.set_warm_up(false);
+        ppu.vram[..0x3C0].fill(1);
+        ppu.palette_table[0x00] = 0x0F;
+        ppu.palette_table[0x01] = 0x16;
+        ppu.scroll_write(3);
+        ppu.scroll_write(0);
+        ppu.mask_write(0x0A);
+        step_to_scanline(&mut ppu, 11);
+        assert_eq!(ppu.line_registers[10].scroll_x, 3);
+        let line = render_line(&ppu, 10);
+        let (r, g, b) = ppu.system_palette[0x16];
+        let lit: Vec<usize> = (0..256).filter(|x| line[x * 4..x * 4 + 3] == [r, g, b]).collect();
+        //pixel 3 of each tile, shifted three to the left
+        assert_eq!(lit, (0..256).step_by(8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ppuctrl_writes_during_the_warm_up_are_ignored() {
+        let mut ppu = PPU::new(vec![0; 0x2000], false, Mirroring::HORIZONTAL);
+        ppu.power_on();
+        ppu.ctrl_write(0x80);
+        assert_eq!(ppu.ctrl, 0x00);
+        //the warm-up ends at the pre-render line of the first frame
+        step_to_scanline(&mut ppu, 261);
+        ppu.ctrl_write(0x80);
+        assert_eq!(ppu.ctrl, 0x00);
+        ppu.step_dot();
+        ppu.step_dot();
+        ppu.ctrl_write(0x80);
+        assert_eq!(ppu.ctrl, 0x80);
+
+        //switched off, writes land straight after power-on
+        let mut ppu = PPU::new(vec![0; 0x2000], false, Mirroring::HORIZONTAL);
+        ppu.set_warm_up(false);
+        ppu.power_on();
+        ppu.ctrl_write(0x80);
+        assert_eq!(ppu.ctrl, 0x80);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::config::parse_mirroring;
+use crate::input::{Button, KeyMap};
+use crate::rom::{Mirroring, Region};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/*
+Per-Game Profiles:
+
+Settings for a single game, kept in a TOML file named after the
+ROM's CRC32 (see Rom::hash) in the profile directory, for example
+profiles/3fe272fb.toml. Anything a profile leaves out falls back to
+the global (command line) settings.
+
+    # Super Mario Bros.
+    region = "ntsc"             # ntsc, pal, multi or dendy
+    mirroring = "vertical"      # vertical, horizontal or four, as --mirroring
+    palette = "smb.pal"         # relative to the profile directory
+    cheats = ["075A:09"]        # added to the --cheat codes
+
+    [keys]                      # scancodes, unlisted buttons keep the default
+    a = 37
+    b = 36
+*/
+
+#[derive(Debug, Error)]
+pub enum ProfileError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    #[serde(deserialize_with = "region")]
+    pub region: Option<Region>,
+    //the mirroring is fixed when the console is built, so it is applied at boot rather than by apply_profile
+    #[serde(deserialize_with = "mirroring")]
+    pub mirroring: Option<Mirroring>,
+    pub palette: Option<PathBuf>,
+    #[serde(rename = "keys", deserialize_with = "key_map")]
+    pub key_map: Option<KeyMap>,
+    pub cheats: Vec<String>,
+}
+
+impl Profile {
+    pub fn parse(text: &str) -> Result<Self, ProfileError> {
+        Ok(toml::from_str(text)?)
+    }
+
+    //relative palette paths are resolved against the profile's directory
+    pub fn load(path: &Path) -> Result<Self, ProfileError> {
+        let mut profile = Self::parse(&fs::read_to_string(path)?)?;
+        if let (Some(palette), Some(dir)) = (&profile.palette, path.parent()) {
+            profile.palette = Some(dir.join(palette));
+        }
+        Ok(profile)
+    }
+
+    //where the profile for a ROM with this CRC32 lives
+    pub fn path(dir: &Path, rom_hash: u32) -> PathBuf {
+        dir.join(format!("{:08x}.toml", rom_hash))
+    }
+}
+
+fn region<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Region>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Ok(Some(match name.to_ascii_lowercase().as_str() {
+        "ntsc" => Region::Ntsc,
+        "pal" => Region::Pal,
+        "multi" => Region::Multi,
+        "dendy" => Region::Dendy,
+        _ => return Err(D::Error::custom(format!("unknown region '{}'", name))),
+    }))
+}
+
+fn mirroring<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Mirroring>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    parse_mirroring(&name)
+        .map(Some)
+        .ok_or_else(|| D::Error::custom(format!("unknown mirroring '{}'", name)))
+}
+
+//the [keys] table, one scancode per button
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Keys {
+    a: Option<u32>,
+    b: Option<u32>,
+    select: Option<u32>,
+    start: Option<u32>,
+    up: Option<u32>,
+    down: Option<u32>,
+    left: Option<u32>,
+    right: Option<u32>,
+}
+
+fn key_map<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<KeyMap>, D::Error> {
+    let keys = Keys::deserialize(deserializer)?;
+    let mut key_map = KeyMap::default();
+    let buttons = [
+        (Button::A, keys.a), (Button::B, keys.b), (Button::Select, keys.select), (Button::Start, keys.start),
+        (Button::Up, keys.up), (Button::Down, keys.down), (Button::Left, keys.left), (Button::Right, keys.right),
+    ];
+    for (button, scancode) in buttons {
+        if let Some(scancode) = scancode {
+            key_map.set(button, scancode);
+        }
+    }
+    Ok(Some(key_map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::Emulator;
+    use crate::mapper::tests::ines;
+
+    #[test]
+    fn every_setting_parses() {
+        let profile = Profile::parse(
+            "# Super Mario Bros.\n\
+            region = \"PAL\"\n\
+            mirroring = \"vertical\"\n\
+            palette = \"smb.pal\"\n\
+            cheats = [\"075A:09\", \"SXIOPO\"]\n\
+            \n\
+            [keys]\n\
+            a = 37\n\
+            start = 28\n",
+        ).unwrap();
+        let mut key_map = KeyMap::default();
+        key_map.set(Button::A, 37);
+        key_map.set(Button::Start, 28);
+        assert_eq!(profile, Profile {
+            region: Some(Region::Pal),
+            mirroring: Some(Mirroring::VERTICAL),
+            palette: Some(PathBuf::from("smb.pal")),
+            key_map: Some(key_map),
+            cheats: vec!["075A:09".to_string(), "SXIOPO".to_string()],
+        });
+        assert_eq!(Profile::parse("").unwrap(), Profile::default());
+        for bad in ["region = \"secam\"", "mirroring = \"diagonal\"", "speed = 2", "[keys]\nturbo = 1", "cheats = \"075A:09\""] {
+            assert!(matches!(Profile::parse(bad), Err(ProfileError::Parse(_))), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn a_profile_keyed_to_the_rom_overrides_the_region() {
+        let dir = std::env::temp_dir().join(format!("nes_emu_profiles_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut emulator = Emulator::new(&ines(0, 1, 1)).unwrap();
+        assert_eq!(emulator.region(), None);
+        //a profile for some other ROM is never looked at
+        fs::write(Profile::path(&dir, emulator.rom_hash() ^ 1), "region = \"dendy\"").unwrap();
+        fs::write(Profile::path(&dir, emulator.rom_hash()), "region = \"pal\"").unwrap();
+        let profile = Profile::load(&Profile::path(&dir, emulator.rom_hash()));
+        fs::remove_dir_all(&dir).unwrap();
+        emulator.apply_profile(&profile.unwrap());
+        assert_eq!(emulator.region(), Some(Region::Pal));
+        assert!(Profile::path(&dir, 0x0123abcd).ends_with("0123abcd.toml"));
+    }
+}
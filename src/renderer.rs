@@ -104,9 +104,10 @@ impl Renderer {
         }
     }
 
+    //data is 256 pixels wide RGBA, as many rows as it holds (240, or 224 with overscan cropped)
     pub fn draw(&self, data: &[u8]) {
         unsafe {
-            self.texture.load(data);
+            self.texture.load(data, (data.len() / (256 * 4)) as i32);
             gl::ClearColor(0.3, 0.3, 0.3, 1.0);
             gl::Clear(gl::COLOR_BUFFER_BIT);
             self.texture.bind();
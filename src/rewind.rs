@@ -0,0 +1,130 @@
+use crate::cpu::CPU;
+use std::collections::VecDeque;
+
+/*
+Rewind:
+
+A ring buffer of whole-machine snapshots taken every few frames.
+Snapshots are plain clones of the CPU (and through the bus, every
+other component). The cartridge's PRG and CHR data is shared with
+the running console rather than copied (CHR-RAM is copied when it
+is next written), so a snapshot only holds the console's own state,
+RAM, nametables and the like, about 16KB whatever the cartridge
+size. The defaults keep the last 10 seconds at one snapshot every
+5 frames.
+*/
+
+pub const REWIND_INTERVAL: u32 = 5;
+pub const REWIND_SNAPSHOTS: usize = 10 * 60 / REWIND_INTERVAL as usize;
+
+#[derive(Clone)]
+pub struct Rewind {
+    snapshots: VecDeque<CPU>,
+    //frames between snapshots
+    interval: u32,
+    capacity: usize,
+    //frames left until the next snapshot
+    countdown: u32,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(REWIND_INTERVAL, REWIND_SNAPSHOTS)
+    }
+}
+
+impl Rewind {
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Rewind {
+            snapshots: VecDeque::with_capacity(capacity),
+            interval: interval.max(1),
+            capacity,
+            countdown: 0,
+        }
+    }
+
+    //called once per emulated frame, keeps a snapshot every `interval` frames
+    pub fn record(&mut self, cpu: &CPU) {
+        if self.countdown == 0 {
+            if self.snapshots.len() == self.capacity {
+                self.snapshots.pop_front();
+            }
+            if self.capacity > 0 {
+                self.snapshots.push_back(cpu.clone());
+            }
+            self.countdown = self.interval;
+        }
+        self.countdown -= 1;
+    }
+
+    //the most recent snapshot, removed from the buffer
+    pub fn pop(&mut self) -> Option<CPU> {
+        self.countdown = self.interval - 1;
+        self.snapshots.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.countdown = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::tests::{cpu, machine_state};
+    use crate::mapper::tests::ines;
+    use crate::rom::Rom;
+
+    #[test]
+    fn rewinding_restores_the_earlier_snapshots() {
+        //INC $10, JMP $8000 standing in for a game
+        let mut cpu = cpu(&[0xE6, 0x10, 0x4C, 0x00, 0x80]);
+        let mut rewind = Rewind::new(5, 4);
+        let mut states = vec![machine_state(&cpu)];
+        for _ in 0..30 {
+            cpu.run_for(1000);
+            rewind.record(&cpu);
+            states.push(machine_state(&cpu));
+        }
+        //snapshots follow frames 1, 6, 11 and so on, only the last 4 are kept
+        assert_eq!(rewind.len(), 4);
+        for frame in [26, 21, 16, 11] {
+            assert_eq!(machine_state(&rewind.pop().unwrap()), states[frame]);
+        }
+        assert!(rewind.pop().is_none());
+    }
+
+    #[test]
+    fn snapshots_leave_the_cartridge_shared() {
+        //512KB of PRG and 256KB of CHR, 768KB a snapshot if it were copied
+        let mut raw = ines(4, 32, 32);
+        //INC $10, JMP $E000 in the fixed last bank
+        let last_bank = 16 + 32 * 0x4000 - 0x2000;
+        raw[last_bank..last_bank + 5].copy_from_slice(&[0xE6, 0x10, 0x4C, 0x00, 0xE0]);
+        raw[last_bank + 0x1FFC..last_bank + 0x1FFE].copy_from_slice(&[0x00, 0xE0]);
+        let mut cpu = CPU::new(Bus::new(Rom::new(&raw).unwrap(), None).unwrap());
+        cpu.power_on();
+        let mut rewind = Rewind::default();
+        for _ in 0..REWIND_SNAPSHOTS * REWIND_INTERVAL as usize {
+            cpu.run_for(1000);
+            rewind.record(&cpu);
+        }
+        assert_eq!(rewind.len(), REWIND_SNAPSHOTS);
+        //what is left per snapshot is the console's own state
+        assert!(std::mem::size_of::<CPU>() <= 20 * 1024);
+        while let Some(snapshot) = rewind.pop() {
+            assert!(snapshot.mem_bus.shares_rom_with(&cpu.mem_bus));
+        }
+    }
+}
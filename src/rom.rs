@@ -1,63 +1,361 @@
+use log::info;
+use std::fmt;
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RomError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    #[error("file is not in iNES format")]
+    NotINes,
+    #[error("{0} headers are not supported")]
+    UnsupportedFormat(HeaderFormat),
+    #[error("mapper {0} is not supported")]
+    UnsupportedMapper(u8),
+    //the header promises more PRG/CHR data than the file holds
+    #[error("ROM file is truncated, header needs {expected} bytes but the file has {actual}")]
+    Truncated { expected: usize, actual: usize },
+    //there is nothing for the CPU to run
+    #[error("ROM has no PRG-ROM")]
+    NoPrgRom,
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[allow(non_camel_case_types)]
 pub enum Mirroring {
     VERTICAL,
     HORIZONTAL,
     FOUR_SCREEN,
+    //all four nametables show the first/second 1KB page (mapper controlled)
+    ONE_SCREEN_LOWER,
+    ONE_SCREEN_UPPER,
 }
 
 const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 0x0010;
+const TRAINER_SIZE: usize = 0x0200;
 const PRG_ROM_PAGE_SIZE: usize = 0x4000;
 const CHR_ROM_PAGE_SIZE: usize = 0x2000;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderFormat {
+    INes,
+    Nes2,
+}
+
+impl fmt::Display for HeaderFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderFormat::INes => write!(f, "iNES"),
+            HeaderFormat::Nes2 => write!(f, "NES 2.0"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    //runs on both
+    Multi,
+    Dendy,
+}
+
+/*
+iNES Header:
+
+The first 16 bytes of a .nes file -
+    0-3     "NES" followed by $1A
+    4       PRG-ROM size in 16KB units
+    5       CHR-ROM size in 8KB units (0 means the board has CHR-RAM)
+    6       NNNN FTBM   mapper low nibble, four-screen, trainer,
+                        battery backed PRG-RAM, mirroring (1: vertical)
+    7       NNNN 10xx   mapper high nibble, bits 2-3 are 10 for NES 2.0
+    8-15    iNES: mostly unused (bit 0 of 9 is PAL)
+            NES 2.0: 8 submapper/mapper bits 8-11, 9 PRG/CHR size
+            high nibbles, 12 timing (region)
+
+NES 2.0 size nibbles of $F switch that size to exponent form,
+2^E * (2M + 1) bytes with E/M packed as EEEEEEMM in byte 4/5.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: Option<u8>,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    //only known when the header records it
+    pub region: Option<Region>,
+}
+
+impl Header {
+    pub fn parse(raw: &[u8]) -> Result<Self, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::Truncated { expected: HEADER_SIZE, actual: raw.len() });
+        }
+        if raw[0..4] != NES_TAG {
+            return Err(RomError::NotINes);
+        }
+        let format = if raw[7] & 0x0C == 0x08 { HeaderFormat::Nes2 } else { HeaderFormat::INes };
+
+        let mirroring = if raw[6] & 0x08 != 0 {
+            Mirroring::FOUR_SCREEN
+        } else if raw[6] & 0x01 != 0 {
+            Mirroring::VERTICAL
+        } else {
+            Mirroring::HORIZONTAL
+        };
+        let mapper = ((raw[7] & 0xF0) | (raw[6] >> 4)) as u16;
+
+        let header = Header {
+            format,
+            mapper,
+            submapper: None,
+            prg_rom_size: raw[4] as usize * PRG_ROM_PAGE_SIZE,
+            chr_rom_size: raw[5] as usize * CHR_ROM_PAGE_SIZE,
+            mirroring,
+            battery: raw[6] & 0x02 != 0,
+            trainer: raw[6] & 0x04 != 0,
+            region: if raw[9] & 0x01 != 0 { Some(Region::Pal) } else { None },
+        };
+        if format == HeaderFormat::INes {
+            return Ok(header);
+        }
+        Ok(Header {
+            mapper: mapper | ((raw[8] & 0x0F) as u16) << 8,
+            submapper: Some(raw[8] >> 4),
+            prg_rom_size: Self::nes2_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE),
+            chr_rom_size: Self::nes2_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE),
+            region: Some(match raw[12] & 0x03 {
+                0 => Region::Ntsc,
+                1 => Region::Pal,
+                2 => Region::Multi,
+                _ => Region::Dendy,
+            }),
+            ..header
+        })
+    }
+
+    fn nes2_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+        if msb == 0x0F {
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = (lsb & 0x03) as usize * 2 + 1;
+            2usize.saturating_pow(exponent).saturating_mul(multiplier)
+        } else {
+            ((msb as usize) << 8 | lsb as usize) * page_size
+        }
+    }
+
+    //offset of PRG-ROM in the file, past the header and the optional trainer
+    pub fn prg_rom_start(&self) -> usize {
+        HEADER_SIZE + if self.trainer { TRAINER_SIZE } else { 0 }
+    }
+}
+
 pub struct Rom {
     pub prg_rom: Vec<u8>,
     pub chr_rom: Vec<u8>,
+    //cartridge has no CHR-ROM, chr_rom holds 8KB of writable CHR-RAM instead
+    pub chr_ram: bool,
     pub mapper: u8,
     pub screen_mirroring: Mirroring,
+    //CRC32 of the PRG and CHR data (header excluded), see hash
+    pub crc32: u32,
 }
 
 impl Rom {
     //convert raw bytecode to formatted ROM
-    pub fn new(raw: &Vec<u8>) -> Self {
-        // check header format
-        if &raw[0..4] != NES_TAG {
-            panic!("File is not in iNES file format");
+    pub fn new(raw: &[u8]) -> Result<Self, RomError> {
+        let header = Header::parse(raw)?;
+        if header.format == HeaderFormat::Nes2 {
+            return Err(RomError::UnsupportedFormat(header.format));
+        }
+        let mapper = header.mapper as u8;
+        let screen_mirroring = header.mirroring.clone();
+        match screen_mirroring {
+            Mirroring::FOUR_SCREEN => info!("Mirror Type: Four Screen"),
+            Mirroring::VERTICAL => info!("Mirror Type: Vertical"),
+            _ => info!("Mirror Type: Horizontal"),
         }
-        
-        let mapper = (raw[7] & 0xF0) | (raw[6] >> 4);
 
-        let ines_ver = (raw[7] >> 2) & 0x03;
-        if ines_ver != 0 {
-            panic!("NES2.0 format not supported");
+        let prg_rom_size = header.prg_rom_size;
+        let chr_rom_size = header.chr_rom_size;
+
+        info!("Program Rom Size: {} bytes", prg_rom_size);
+        info!("Character Rom Size: {} bytes", chr_rom_size);
+        if prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
         }
-        let screen_mirroring: Mirroring;
-        if raw[6] & 0x08 != 0 {
-            println!("Mirror Type: Four Screen");
-            screen_mirroring = Mirroring::FOUR_SCREEN;
+
+        let prg_rom_start: usize = header.prg_rom_start();
+        let chr_rom_start: usize = prg_rom_start + prg_rom_size;
+
+        //bad dumps sometimes claim more banks than they contain, refuse them rather than slice past the end
+        let rom_end = chr_rom_start + chr_rom_size;
+        if rom_end > raw.len() {
+            return Err(RomError::Truncated { expected: rom_end, actual: raw.len() });
         }
-        else if raw[6] &0x01 != 0 {
-            println!("Mirror Type: Vertical");
-            screen_mirroring = Mirroring::VERTICAL;
+
+        let chr_ram = chr_rom_size == 0;
+        let chr_rom = if chr_ram {
+            info!("Character Ram Size: {} bytes", CHR_ROM_PAGE_SIZE);
+            vec![0; CHR_ROM_PAGE_SIZE]
+        } else {
+            raw[chr_rom_start..chr_rom_start + chr_rom_size].to_vec()
+        };
+
+        let prg_rom = raw[prg_rom_start..prg_rom_start + prg_rom_size].to_vec();
+        let mut crc32 = crc32fast::Hasher::new();
+        crc32.update(&prg_rom);
+        if !chr_ram {
+            crc32.update(&chr_rom);
         }
-        else {
-            println!("Mirror Type: Horizontal");
-            screen_mirroring = Mirroring::HORIZONTAL;
+
+        Ok(Rom {
+            prg_rom,
+            chr_rom,
+            chr_ram,
+            mapper,
+            screen_mirroring,
+            crc32: crc32.finalize(),
+        })
+    }
+
+    /*
+    Stable identifier of the game, the CRC32 of the PRG and CHR data
+    as ROM databases (No-Intro, NesCartDB) list it. Unlike a hash of
+    the whole file it doesn't change when only the header is fixed.
+    */
+    pub fn hash(&self) -> u32 {
+        self.crc32
+    }
+
+    //MD5 of the PRG and CHR data (header excluded), as FCEUX identifies ROMs
+    pub fn md5(&self) -> [u8; 16] {
+        let mut context = md5::Context::new();
+        context.consume(&self.prg_rom);
+        if !self.chr_ram {
+            context.consume(&self.chr_rom);
         }
+        context.compute().0
+    }
+}
 
-        let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
-        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::mapper::tests::ines;
+    use log::{Level, Log, Metadata, Record};
+    use std::cell::RefCell;
+    use std::sync::Once;
 
-        println!("Program Rom Size: {} bytes", prg_rom_size);
-        println!("Character Rom Size: {} bytes", chr_rom_size);
+    thread_local! {
+        static RECORDS: RefCell<Vec<(Level, String)>> = const { RefCell::new(Vec::new()) };
+    }
 
-        let prg_rom_start: usize = 0x0010 + 0x0080 * (raw[6] & 0x04) as usize;
-        let chr_rom_start: usize = prg_rom_start + prg_rom_size;
+    //keeps each thread's records apart so tests running side by side don't see each other's
+    struct TestLogger;
 
-        Rom {
-            prg_rom: raw[prg_rom_start as usize..(prg_rom_start + prg_rom_size) as usize].to_vec(),
-            chr_rom: raw[chr_rom_start as usize..(chr_rom_start + chr_rom_size) as usize].to_vec(),
-            mapper,
-            screen_mirroring,
+    impl Log for TestLogger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &Record) {
+            RECORDS.with(|records| records.borrow_mut().push((record.level(), record.args().to_string())));
         }
+        fn flush(&self) {}
+    }
+
+    //the log records `run` emits on this thread
+    pub(crate) fn capture_logs(run: impl FnOnce()) -> Vec<(Level, String)> {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            log::set_logger(&TestLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        RECORDS.with(|records| records.borrow_mut().clear());
+        run();
+        RECORDS.with(|records| records.take())
+    }
+
+    #[test]
+    fn loading_logs_the_rom_layout() {
+        let mut raw = ines(0, 2, 0);
+        raw[6] |= 0x01;
+        let records = capture_logs(|| {
+            Rom::new(&raw).unwrap();
+        });
+        let info = |text: &str| (Level::Info, text.to_string());
+        assert_eq!(records, [
+            info("Mirror Type: Vertical"),
+            info("Program Rom Size: 32768 bytes"),
+            info("Character Rom Size: 0 bytes"),
+            info("Character Ram Size: 8192 bytes"),
+        ]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn headers_claiming_more_data_than_the_file_are_refused() {
+        //claims 2 PRG and 1 CHR bank, holds 2 PRG and half the CHR
+        let mut raw = ines(0, 2, 1);
+        raw.truncate(16 + 0x8000 + 0x1000);
+        assert!(matches!(Rom::new(&raw), Err(RomError::Truncated { expected: 0xA010, actual: 0x9010 })));
+        //PRG cut short as well
+        raw.truncate(16 + 0x4000);
+        assert!(matches!(Rom::new(&raw), Err(RomError::Truncated { expected: 0xA010, actual: 0x4010 })));
+        //a trainer counts towards the expected size
+        let mut raw = ines(0, 1, 1);
+        raw[6] |= 0x04;
+        assert!(matches!(Rom::new(&raw), Err(RomError::Truncated { expected: 0x6210, actual: 0x6010 })));
+        assert!(matches!(Rom::new(&raw[..10]), Err(RomError::Truncated { expected: 16, actual: 10 })));
+        //extra bytes past the CHR are ignored
+        let mut raw = ines(0, 1, 1);
+        raw.extend([0; 100]);
+        assert!(Rom::new(&raw).is_ok());
+    }
+
+    #[test]
+    fn malformed_page_counts_load_cleanly() {
+        //no PRG to run, with or without CHR
+        assert!(matches!(Rom::new(&ines(0, 0, 1)), Err(RomError::NoPrgRom)));
+        assert!(matches!(Rom::new(&ines(0, 0, 0)), Err(RomError::NoPrgRom)));
+        assert!(matches!(Rom::new(&[0; 16]), Err(RomError::NotINes)));
+        //no CHR pages means the board carries 8KB of CHR-RAM
+        let rom = Rom::new(&ines(0, 1, 0)).unwrap();
+        assert!(rom.chr_ram);
+        assert_eq!(rom.chr_rom, vec![0; 0x2000]);
+        //and the PPU can draw from it straight away
+        let mut bus = crate::bus::Bus::new(rom, None).unwrap();
+        bus.ppu.set_warm_up(false);
+        bus.ppu.addr_write(0x00);
+        bus.ppu.addr_write(0x10);
+        bus.ppu.write(0xAB);
+        assert_eq!(bus.ppu.peek_vram(0x0010), 0xAB);
+    }
+
+    #[test]
+    fn the_hash_of_a_known_rom_is_fixed() {
+        //16KB of PRG and 8KB of CHR holding their bank numbers, see ines
+        let raw = ines(0, 1, 1);
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.hash(), 0x9c46a715);
+        assert_eq!(rom.md5().map(|byte| format!("{:02x}", byte)).concat(), "60f8f888c9900066fc5771d383867c34");
+        //the header and a trainer are left out, so fixing either keeps the id
+        let mut fixed = raw.clone();
+        fixed[6] |= 0x01;
+        assert_eq!(Rom::new(&fixed).unwrap().hash(), 0x9c46a715);
+        let mut trainer = raw[..16].to_vec();
+        trainer[6] |= 0x04;
+        trainer.extend([0xAA; 512]);
+        trainer.extend(&raw[16..]);
+        assert_eq!(Rom::new(&trainer).unwrap().hash(), 0x9c46a715);
+        //CHR-RAM is not part of the game
+        assert_eq!(Rom::new(&ines(0, 1, 0)).unwrap().hash(), 0x3f11caec);
+    }
+}
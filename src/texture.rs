@@ -21,7 +21,7 @@ impl Texture {
     }
 
     #[inline(always)]
-    pub unsafe fn load(&self, img: &[u8]) {
+    pub unsafe fn load(&self, img: &[u8], height: i32) {
         self.bind();
 
         gl::TexImage2D(
@@ -29,7 +29,7 @@ impl Texture {
             0,
             gl::RGBA as i32,
             256,
-            240,
+            height,
             0,
             gl::RGBA,
             gl::UNSIGNED_BYTE,
@@ -0,0 +1,59 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/*
+A test ROM following the blargg protocol (see src/bin/headless.rs):
+it marks the result as running, writes the signature and message,
+then reports `result` in $6000 and spins.
+*/
+fn test_rom(result: u8, message: &str) -> Vec<u8> {
+    let mut program = Vec::new();
+    let mut store = |addr: u16, value: u8| program.extend([0xA9, value, 0x8D, addr as u8, (addr >> 8) as u8]);
+    store(0x6000, 0x80);
+    for (i, byte) in [0xDE, 0xB0, 0x61].into_iter().enumerate() {
+        store(0x6001 + i as u16, byte);
+    }
+    for (i, byte) in message.bytes().chain([0]).enumerate() {
+        store(0x6004 + i as u16, byte);
+    }
+    store(0x6000, result);
+    let spin = 0x8000 + program.len() as u16;
+    program.extend([0x4C, spin as u8, (spin >> 8) as u8]);
+
+    let mut prg = vec![0xEA; 0x4000];
+    prg[..program.len()].copy_from_slice(&program);
+    //reset vector, the 16KB bank is mirrored at $8000 and $C000
+    prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+    let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    raw.extend(prg);
+    raw.extend([0; 0x2000]);
+    raw
+}
+
+//runs the headless binary on a ROM, returns its exit code and output
+fn run_test_rom(name: &str, raw: &[u8]) -> (Option<i32>, String) {
+    let path: PathBuf = std::env::temp_dir().join(format!("nes_emu_{}_{}.nes", name, std::process::id()));
+    fs::write(&path, raw).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_headless"))
+        .arg(&path)
+        .arg("--test-rom")
+        .output()
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    (output.status.code(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn a_passing_test_rom_exits_cleanly() {
+    let (code, stdout) = run_test_rom("pass", &test_rom(0x00, "Passed"));
+    assert_eq!(code, Some(0), "{}", stdout);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), ["Passed", "result: passed"]);
+}
+
+#[test]
+fn a_failing_test_rom_exits_with_its_result_code() {
+    let (code, stdout) = run_test_rom("fail", &test_rom(0x03, "Failed #3"));
+    assert_eq!(code, Some(3), "{}", stdout);
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), ["Failed #3", "result: failed ($03)"]);
+}